@@ -0,0 +1,64 @@
+//! Compares rendering a batch of PNG exports one after another with the parallel
+//! pool `batch_export` uses. Run with `cargo bench --features bench`.
+
+use std::time::{Duration, Instant};
+
+use jamal_app_lib::bench::{export_parallel, export_sequential};
+use jamal_app_lib::ExportJob;
+
+/// Exports per batch
+const JOBS: usize = 16;
+
+/// Timed runs of each variant; the median is reported
+const RUNS: usize = 5;
+
+/// A drawing-sized SVG with enough shapes to make rendering the dominant cost
+fn sample_svg(seed: usize) -> String {
+    let mut shapes = String::new();
+    for i in 0..400 {
+        let x = (i * 37 + seed * 11) % 1600;
+        let y = (i * 53 + seed * 7) % 1200;
+        shapes.push_str(&format!(
+            "<circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"#{:06x}\" fill-opacity=\"0.6\" stroke=\"#222\" stroke-width=\"2\"/>",
+            x,
+            y,
+            20 + i % 60,
+            (i as u32).wrapping_mul(2654435761) & 0xffffff
+        ));
+    }
+    format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"1600\" height=\"1200\">{}</svg>", shapes)
+}
+
+fn median(run: impl Fn() -> Result<(), String>) -> Duration {
+    let mut times: Vec<Duration> = (0..RUNS)
+        .map(|_| {
+            let started = Instant::now();
+            run().expect("export failed");
+            started.elapsed()
+        })
+        .collect();
+    times.sort();
+    times[RUNS / 2]
+}
+
+fn main() {
+    let dir = tempfile::tempdir().expect("failed to create output directory");
+    let jobs: Vec<ExportJob> = (0..JOBS)
+        .map(|i| ExportJob {
+            svg_data: sample_svg(i),
+            width: 1600,
+            height: 1200,
+            output_path: dir.path().join(format!("{}.png", i)).to_string_lossy().to_string(),
+        })
+        .collect();
+    
+    // Warm up the allocator and page cache before timing
+    export_parallel(&jobs).expect("export failed");
+    
+    let sequential = median(|| export_sequential(&jobs));
+    let parallel = median(|| export_parallel(&jobs));
+    println!("batch export of {} PNGs, median of {} runs", JOBS, RUNS);
+    println!("  sequential: {:>8.1} ms", sequential.as_secs_f64() * 1000.0);
+    println!("  parallel:   {:>8.1} ms", parallel.as_secs_f64() * 1000.0);
+    println!("  speedup:    {:>8.2}x", sequential.as_secs_f64() / parallel.as_secs_f64());
+}
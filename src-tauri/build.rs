@@ -1,3 +1,60 @@
 fn main() {
-    tauri_build::build()
+    // Commands of the in-app `jamal-files` plugin, see `jamal_files_plugin`
+    tauri_build::try_build(tauri_build::Attributes::new().plugin(
+        "jamal-files",
+        tauri_build::InlinedPlugin::new()
+            .commands(&[
+                "save_file",
+                "read_file",
+                "file_exists",
+                "canonicalize_path",
+                "validate_json",
+                "update_drawing_name",
+                "flush_pending_writes",
+                "get_recent_files",
+                "get_recent_files_page",
+                "add_recent_file",
+                "touch_recent_file",
+                "set_recent_file_pinned",
+                "remove_recent_file",
+                "remove_recent_files",
+                "remove_recent_files_matching",
+                "clear_recent_files",
+                "read_drawing_auto",
+                "get_recent_files_grouped",
+                "get_recent_files_by_directory",
+                "get_recent_files_by_tag",
+                "relativize_recent_files",
+                "resolve_recent_files",
+                "set_file_label",
+                "set_tags",
+                "get_tags",
+                "add_tag",
+                "remove_tag",
+                "list_all_tags",
+                "find_by_tag",
+                "reconcile_tags",
+                "add_favorite",
+                "remove_favorite",
+                "get_favorites",
+                "reorder_favorites",
+                "list_backups",
+                "read_backup",
+                "get_backup_status",
+                "set_backup_directory",
+                "migrate_backups",
+                "create_snapshot",
+                "list_snapshots",
+                "restore_snapshot",
+                "delete_snapshot",
+                "save_as_template",
+                "list_templates",
+                "delete_template",
+                "new_from_template",
+                "rename_file",
+                "list_drawings",
+            ])
+            .default_permission(tauri_build::DefaultPermissionRule::AllowAllCommands),
+    ))
+    .expect("failed to run tauri-build");
 }
@@ -0,0 +1,122 @@
+//! The `jamal-files` plugin: the file and recent-files commands, the state they share
+//! and `JamalFilesExt` for Rust code holding an app handle
+
+use std::sync::Mutex;
+
+use tauri::{Manager, Runtime};
+
+use crate::{
+    check_path_scope, get_path_scope_path, grant_path, load_recent_files, read_drawing, read_json_or_default,
+    save_recent_files, write_drawing, DrawingEvent, DrawingEventBus, DrawingFile, PathScope, RecentFile, ScopeGrant,
+    WriteQueue,
+};
+
+/// Drawing file and recent-files operations for Rust code holding an app handle,
+/// available once `jamal_files_plugin` is registered
+pub trait JamalFilesExt {
+    /// The stored recent files list, unsorted
+    fn recent_files(&self) -> Result<Vec<RecentFile>, String>;
+    /// Replace the recent files list, notifying every window
+    fn set_recent_files(&self, files: &[RecentFile]) -> Result<(), String>;
+    /// Read and parse a drawing the frontend is allowed to access
+    fn read_drawing(&self, path: &str) -> Result<DrawingFile, String>;
+    /// Write a drawing the frontend is allowed to access, stamping its checksum
+    fn write_drawing(&self, path: &str, drawing: &mut DrawingFile) -> Result<(), String>;
+    /// Allow access to a path, as a dialog pick or drag and drop would
+    fn grant_path(&self, path: &str, directory: bool) -> Result<(), String>;
+    /// Receive every `DrawingEvent` published from now on
+    fn subscribe_drawing_events(&self) -> tokio::sync::broadcast::Receiver<DrawingEvent>;
+}
+
+impl<R: Runtime> JamalFilesExt for tauri::AppHandle<R> {
+    fn recent_files(&self) -> Result<Vec<RecentFile>, String> {
+        load_recent_files(self)
+    }
+    
+    fn set_recent_files(&self, files: &[RecentFile]) -> Result<(), String> {
+        save_recent_files(self, files)
+    }
+    
+    fn read_drawing(&self, path: &str) -> Result<DrawingFile, String> {
+        read_drawing(&check_path_scope(self, path)?)
+    }
+    
+    fn write_drawing(&self, path: &str, drawing: &mut DrawingFile) -> Result<(), String> {
+        write_drawing(&check_path_scope(self, path)?, drawing)
+    }
+    
+    fn grant_path(&self, path: &str, directory: bool) -> Result<(), String> {
+        grant_path(self, path, directory)
+    }
+    
+    fn subscribe_drawing_events(&self) -> tokio::sync::broadcast::Receiver<DrawingEvent> {
+        self.state::<DrawingEventBus>().0.subscribe()
+    }
+}
+
+/// The file layer as a reusable plugin: saving and reading drawings, the recent
+/// files list with its tags and favorites, backups, snapshots, templates and the
+/// path scope, with the state they share. Its commands are invoked as
+/// `plugin:jamal-files|<command>`; the list must match `build.rs`.
+pub fn jamal_files_plugin<R: Runtime>() -> tauri::plugin::TauriPlugin<R> {
+    tauri::plugin::Builder::new("jamal-files")
+        .setup(|app, _api| {
+            app.manage(WriteQueue::default());
+            app.manage(DrawingEventBus::default());
+            let grants: Vec<ScopeGrant> = read_json_or_default(&get_path_scope_path(app)?);
+            app.manage(PathScope(Mutex::new(grants)));
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            crate::save_file,
+            crate::read_file,
+            crate::file_exists,
+            crate::canonicalize_path,
+            crate::validate_json,
+            crate::update_drawing_name,
+            crate::flush_pending_writes,
+            crate::get_recent_files,
+            crate::get_recent_files_page,
+            crate::add_recent_file,
+            crate::touch_recent_file,
+            crate::set_recent_file_pinned,
+            crate::remove_recent_file,
+            crate::remove_recent_files,
+            crate::remove_recent_files_matching,
+            crate::clear_recent_files,
+            crate::read_drawing_auto,
+            crate::get_recent_files_grouped,
+            crate::get_recent_files_by_directory,
+            crate::get_recent_files_by_tag,
+            crate::relativize_recent_files,
+            crate::resolve_recent_files,
+            crate::set_file_label,
+            crate::set_tags,
+            crate::get_tags,
+            crate::add_tag,
+            crate::remove_tag,
+            crate::list_all_tags,
+            crate::find_by_tag,
+            crate::reconcile_tags,
+            crate::add_favorite,
+            crate::remove_favorite,
+            crate::get_favorites,
+            crate::reorder_favorites,
+            crate::list_backups,
+            crate::read_backup,
+            crate::get_backup_status,
+            crate::set_backup_directory,
+            crate::migrate_backups,
+            crate::create_snapshot,
+            crate::list_snapshots,
+            crate::restore_snapshot,
+            crate::delete_snapshot,
+            crate::save_as_template,
+            crate::list_templates,
+            crate::delete_template,
+            crate::new_from_template,
+            crate::rename_file,
+            crate::list_drawings
+        ])
+        .build()
+}
@@ -1,192 +1,9387 @@
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
-use tauri::Manager;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tauri::{Emitter, Manager, Runtime};
+
+mod jamal_files;
+
+pub use jamal_files::{jamal_files_plugin, JamalFilesExt};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RecentFile {
     pub path: String,
     pub name: String,
     pub last_opened: i64,
+    /// Filled from the tags store when listing, never persisted here. Tags are
+    /// stored trimmed and lowercased, see `store_tags`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Color label, one of `LABEL_COLORS`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Pinned entries stay at the top of the list and aren't dropped by the length limit
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    /// How many times the file was opened through `add_recent_file`
+    #[serde(default)]
+    pub open_count: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RecentSortKey {
+    LastOpened,
+    Name,
+    OpenCount,
+    FileSize,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct RecentFilesSort {
+    pub key: RecentSortKey,
+    /// Defaults to A–Z for names and to largest first otherwise
+    pub ascending: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecentFilesPage {
+    pub files: Vec<RecentFile>,
+    pub total: u32,
+    pub page: u32,
+    pub total_pages: u32,
+}
+
+/// Recent files by when they were last opened, each group newest first
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct GroupedRecentFiles {
+    pub today: Vec<RecentFile>,
+    /// Earlier in the current Monday-to-Sunday week
+    pub this_week: Vec<RecentFile>,
+    /// Earlier in the current calendar month
+    pub this_month: Vec<RecentFile>,
+    pub older: Vec<RecentFile>,
+}
+
+/// Recent files sharing a parent directory
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecentFileGroup {
+    /// Canonical directory path
+    pub directory: String,
+    /// The directory with the home directory shortened to `~`
+    pub display_name: String,
+    /// Newest `last_opened` in the group
+    pub last_opened: i64,
+    /// Newest first
+    pub files: Vec<RecentFile>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Named colors accepted as file labels
+const LABEL_COLORS: [&str; 7] = ["red", "orange", "yellow", "green", "blue", "purple", "gray"];
+
+/// A `.jamal` drawing as written by the frontend (camelCase keys, millisecond timestamps)
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct DrawingFile {
     pub version: u32,
     pub name: String,
     pub store: serde_json::Value,
+    #[serde(alias = "created_at")]
     pub created_at: i64,
+    #[serde(alias = "updated_at")]
     pub updated_at: i64,
+    /// Total editing time recorded through `record_session`
+    #[serde(default, alias = "session_seconds")]
+    pub session_seconds: u64,
+    /// Undo history: a JSON array of store patches, deflated and base64 encoded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<String>,
+    /// SHA-256 of the canonical serialization of `store`, see `store_checksum`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Embedded binary assets (images, fonts) by id, base64 encoded
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub assets: BTreeMap<String, String>,
+    /// Dominant colors as `#rrggbb`, most common first, see `extract_palette`. Filled in
+    /// by `save_file` from the drawing's `previewSvg`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub palette: Option<Vec<String>>,
+    /// Fields owned by the frontend (e.g. `cloudId`), kept untouched on rewrite
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
 }
 
-/// Get the app data directory for storing recent files list
-fn get_app_data_dir(app: &tauri::AppHandle) -> Result<PathBuf, String> {
-    app.path()
-        .app_data_dir()
-        .map_err(|e| format!("Failed to get app data directory: {}", e))
+/// A stored checksum that doesn't match the store: the file may be corrupted,
+/// or it may just have been edited by hand
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChecksumMismatch {
+    pub expected: String,
+    pub actual: String,
+    pub message: String,
+}
+
+/// A drawing together with its decoded undo history
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DrawingWithHistory {
+    pub drawing: DrawingFile,
+    pub history: Vec<serde_json::Value>,
+    pub checksum_warning: Option<ChecksumMismatch>,
+}
+
+/// An SVG file read for import, with the drawing fields recovered from it
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SvgImport {
+    pub svg: String,
+    pub drawing: DrawingFile,
+    /// Whether the fields came from metadata written by `save_svg`
+    pub has_metadata: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FilenameSuggestion {
+    /// File name without extension
+    pub suggestion: String,
+    /// Runner-up names, best first
+    pub alternatives: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SanitizedFilename {
+    pub name: String,
+    /// Human-readable reasons the name differs from the input
+    pub changes: Vec<String>,
+}
+
+/// A drawing salvaged from a damaged file
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RecoveredDrawing {
+    pub drawing: DrawingFile,
+    /// Bytes at the end of the file that had to be discarded
+    pub bytes_dropped: u64,
+    pub warning: Option<String>,
+}
+
+/// Everything a new window needs to open a drawing, gathered in one round trip
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OpenPayload {
+    pub content: String,
+    pub version: u32,
+    pub name: String,
+    /// SHA-256 of `content`
+    pub hash: String,
+    /// Whether saving back to the same path will work
+    pub writable: bool,
+    pub read_only_cause: Option<ReadOnlyCause>,
+    /// Set when the file is close to the size limit
+    pub size_warning: Option<String>,
+    /// Invalid UTF-8 bytes replaced by a lossy read; non-zero means the file may be damaged
+    pub replaced_bytes: u64,
+}
+
+/// Payload of the `file-damaged-warning` event
+#[derive(Debug, Serialize, Clone)]
+pub struct DamagedFileWarning {
+    pub path: String,
+    pub replaced_bytes: u64,
+}
+
+/// Significant events published on the `DrawingEventBus`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", content = "payload", rename_all = "camelCase")]
+pub enum DrawingEvent {
+    FileSaved(String),
+    FileRenamed { from: String, to: String },
+    RecentFileAdded(String),
+    RecentFileRemoved(String),
+    /// Several entries removed in one operation
+    RecentFilesRemoved(Vec<String>),
+    RecentFilesCleared,
+    SnapshotCreated(String),
+    SnapshotRestored(String),
+    PreferencesChanged,
+}
+
+/// In-process broadcast of `DrawingEvent`s, for plugins and frontend subscribers
+pub struct DrawingEventBus(pub tokio::sync::broadcast::Sender<DrawingEvent>);
+
+impl Default for DrawingEventBus {
+    fn default() -> Self {
+        Self(tokio::sync::broadcast::channel(64).0)
+    }
+}
+
+/// Publish an event; having no subscribers is not an error
+fn publish_event<R: Runtime>(app: &tauri::AppHandle<R>, event: DrawingEvent) {
+    let _ = app.state::<DrawingEventBus>().0.send(event);
+}
+
+/// A file or directory the user allowed the app to read and write
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ScopeGrant {
+    /// Normalized with `normalize_scope_path`
+    pub path: String,
+    /// Whether everything below `path` is allowed too
+    pub directory: bool,
+}
+
+/// Paths the user granted through a dialog, drag and drop or an approval prompt
+#[derive(Default)]
+pub struct PathScope(pub Mutex<Vec<ScopeGrant>>);
+
+/// Why a file can't be written
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReadOnlyCause {
+    /// The file itself is marked read-only
+    Attribute,
+    /// The containing directory doesn't allow writes
+    Directory,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileInfo {
+    pub path: String,
+    pub size: u64,
+    pub modified: u64,
+    pub writable: bool,
+    pub read_only_cause: Option<ReadOnlyCause>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DrawingValidation {
+    pub valid: bool,
+    pub errors: Vec<String>,
+    pub checksum_warning: Option<ChecksumMismatch>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DrawingInfo {
+    pub name: String,
+    pub version: u32,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub session_seconds: u64,
+    pub size: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FontInfo {
+    pub family: String,
+    pub style: String,
+    pub weight: u32,
+    pub path: String,
+}
+
+/// Text measurements in pixels at the requested font size
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TextMetrics {
+    /// Advance width of the widest line
+    pub width: f32,
+    pub ascent: f32,
+    /// Distance below the baseline, positive
+    pub descent: f32,
+    pub line_height: f32,
+    pub line_count: u32,
+    /// Family the text was measured with
+    pub resolved_family: String,
+    /// Set when `font_family` wasn't available and a fallback was used
+    pub fallback: bool,
+}
+
+/// User preferences, the single source of truth for settings read by other commands.
+/// Missing fields fall back to their defaults so older files keep loading.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Preferences {
+    /// "light", "dark" or "system"
+    pub theme: String,
+    /// "png" or "svg"
+    pub default_export_format: String,
+    pub grid_enabled: bool,
+    pub grid_size: u32,
+    pub autosave_interval_secs: u32,
+    /// Command run after every successful `save_file`. Only changed through
+    /// `set_post_save_hook`, which asks the user first
+    pub post_save_hook: Option<PostSaveHook>,
+    /// Backups kept per file; older ones are pruned
+    pub backup_retention: u32,
+    /// Total size of all backups before the oldest are pruned across files
+    pub backup_storage_cap_mb: u64,
+    /// User-chosen backup root; the app data dir is used when unset or unavailable.
+    /// Only changed through `set_backup_directory` and `migrate_backups`
+    pub backup_directory: Option<String>,
+    /// Length of the recent files list
+    pub max_recent_files: u32,
+    /// Store undo history inside drawing files; when off it's stripped on save
+    pub persist_history: bool,
+    pub history_max_entries: u32,
+    /// Cap on the compressed history size
+    pub history_max_bytes: u64,
+    /// Default for explicit saves: fsync the file and its directory before reporting
+    /// success. That's one or two extra device flushes per save, unnoticeable on an SSD
+    /// but up to hundreds of milliseconds on slow USB sticks. Autosaves never sync.
+    pub durable_saves: bool,
+    /// Backups of drawings that no longer exist are removed by `cleanup_app_data`
+    /// once their newest backup is this old
+    pub orphaned_backup_max_age_days: u32,
+    /// Files larger than this, on disk or decompressed, are only opened on request
+    pub max_file_size_mb: u64,
+    /// File operations still running after this long fail with `TIMED_OUT`
+    pub io_timeout_secs: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PostSaveHook {
+    pub command: String,
+    /// Arguments passed before the saved file's path
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub working_dir: Option<String>,
+}
+
+/// Payload of the `post-save-hook-output` event
+#[derive(Debug, Serialize, Clone)]
+pub struct PostSaveHookOutput {
+    pub path: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub error: Option<String>,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Self {
+            theme: "system".to_string(),
+            default_export_format: "png".to_string(),
+            grid_enabled: false,
+            grid_size: 20,
+            autosave_interval_secs: 30,
+            post_save_hook: None,
+            backup_retention: 10,
+            backup_storage_cap_mb: 500,
+            backup_directory: None,
+            max_recent_files: 20,
+            persist_history: true,
+            history_max_entries: 100,
+            history_max_bytes: 5 * 1024 * 1024,
+            durable_saves: false,
+            orphaned_backup_max_age_days: 30,
+            max_file_size_mb: 300,
+            io_timeout_secs: 120,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ViewState {
+    pub x: f64,
+    pub y: f64,
+    pub zoom: f64,
+    pub selected_page: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct ViewStateEntry {
+    #[serde(flatten)]
+    state: ViewState,
+    updated_at: i64,
+}
+
+/// Number of files whose view state is remembered
+const MAX_VIEW_STATES: usize = 300;
+
+/// Manifest entry of a user template
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct TemplateManifestEntry {
+    name: String,
+    created_at: i64,
+    has_thumbnail: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TemplateInfo {
+    pub name: String,
+    pub created_at: i64,
+    /// PNG thumbnail as a data URL
+    pub thumbnail: Option<String>,
+    /// Built-in templates ship with the app and can't be deleted
+    pub built_in: bool,
+}
+
+/// Templates embedded in the binary, as (name, drawing JSON)
+const BUILT_IN_TEMPLATES: [(&str, &str); 3] = [
+    ("Blank", include_str!("../templates/blank.jamal")),
+    ("Retrospective", include_str!("../templates/retrospective.jamal")),
+    ("Kanban", include_str!("../templates/kanban.jamal")),
+];
+
+/// Record id prefixes that get fresh ids when a template is instantiated
+const REGENERATED_ID_PREFIXES: [&str; 3] = ["shape:", "binding:", "asset:"];
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Favorite {
+    pub path: String,
+    pub name: String,
+    pub added_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+
+/// A favorite with live information about the file on disk
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FavoriteFile {
+    #[serde(flatten)]
+    pub favorite: Favorite,
+    pub exists: bool,
+    pub size: Option<u64>,
+    pub modified_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackupInfo {
+    /// Milliseconds since epoch, also the backup's id
+    pub timestamp: i64,
+    pub size: u64,
+}
+
+/// Record of the last time the storage cap forced backups to be pruned
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct BackupPruneReport {
+    pub pruned_at: i64,
+    pub backups_removed: u32,
+    pub bytes_freed: u64,
+}
+
+/// What `cleanup_app_data` removed
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct CleanupReport {
+    /// Template thumbnails whose template is gone
+    pub thumbnails_removed: u32,
+    /// Backups of deleted drawings past the age limit, and leftover temp files
+    pub recovery_files_removed: u32,
+    /// Backups beyond the per-file retention count
+    pub backups_removed: u32,
+    /// Snapshot blobs no snapshot refers to
+    pub snapshot_blobs_removed: u32,
+    /// Least recently used drawing previews beyond the cache size limit
+    pub previews_removed: u32,
+    pub bytes_freed: u64,
+}
+
+/// Bytes used under the app data directory, by category
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct DataUsage {
+    pub recent_files: u64,
+    /// Preferences, tags, favorites, view states and path scope
+    pub settings: u64,
+    pub backups: u64,
+    pub snapshots: u64,
+    pub templates: u64,
+    /// Template thumbnails
+    pub thumbnails: u64,
+    /// Cached drawing previews, see `get_or_request_preview`
+    pub previews: u64,
+    pub logs: u64,
+    /// Anything else, such as temp files left by interrupted writes
+    pub other: u64,
+    pub total: u64,
+    /// Directories that couldn't be read and are missing from the totals
+    pub warnings: Vec<String>,
+}
+
+/// Whether a drawing's preview is cached or has to be rendered by the frontend
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PreviewStatus {
+    Cached,
+    NeedsRender,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Preview {
+    pub status: PreviewStatus,
+    /// PNG as a data URL, when cached
+    pub thumbnail: Option<String>,
+}
+
+/// A drawing in a folder listed by `list_gallery`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct GalleryEntry {
+    pub path: String,
+    pub name: String,
+    pub size: u64,
+    pub modified_at: Option<i64>,
+    pub preview: PreviewStatus,
+}
+
+/// A drawing in a folder listed by `list_drawings`, with its tags
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DrawingListEntry {
+    pub path: String,
+    pub name: String,
+    pub size: u64,
+    pub modified_at: Option<i64>,
+    pub tags: Vec<String>,
+}
+
+/// Payload of the `backup-migration-progress` event
+#[derive(Debug, Serialize, Clone)]
+pub struct BackupMigrationProgress {
+    pub moved: u32,
+    pub total: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BackupStatus {
+    pub total_bytes: u64,
+    pub cap_bytes: u64,
+    pub last_prune: Option<BackupPruneReport>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OsInfo {
+    pub os: String,
+    pub arch: String,
+    pub version: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SnapshotInfo {
+    pub id: String,
+    pub label: String,
+    pub created_at: i64,
+    /// Uncompressed size in bytes
+    pub size: u64,
+    /// SHA-256 of the content, naming the compressed blob shared by identical snapshots
+    pub content_hash: String,
+}
+
+/// One file of an all-or-nothing `save_file_set`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileWrite {
+    pub path: String,
+    pub content: Vec<u8>,
+}
+
+/// Contents of a `<drawing>.lock` sidecar
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LockInfo {
+    pub hostname: String,
+    pub user: String,
+    pub pid: u32,
+    pub acquired_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LockStatus {
+    pub acquired: bool,
+    /// The current holder when the lock couldn't be acquired
+    pub holder: Option<LockInfo>,
+    /// Whether the holder's lock looks abandoned and can be broken
+    pub stale: bool,
+}
+
+/// Lock files created by this process, released on exit
+#[derive(Default)]
+struct HeldLocks(Mutex<std::collections::HashSet<PathBuf>>);
+
+/// Saves waiting on or running for one path
+struct PathWrites {
+    /// Number of the newest submitted `save_file`
+    latest: u64,
+    /// Writes submitted and not yet finished
+    pending: u32,
+    /// Fair, so writes run in submission order
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+/// A save's place in its path's queue
+struct QueuedWrite {
+    key: String,
+    number: u64,
+    lock: Arc<tokio::sync::Mutex<()>>,
+}
+
+/// Per-path queue serializing writes. Writes to one path run one at a time in
+/// submission order, and a queued `save_file` is skipped when a newer `save_file`
+/// for the same path is already waiting. Other writes (file sets, edits of a
+/// drawing's current content) take their turn without superseding anything, since
+/// they may still fail or depend on what was saved before them. Different paths
+/// don't wait for each other.
+#[derive(Default)]
+struct WriteQueue(Mutex<std::collections::HashMap<String, PathWrites>>);
+
+/// Turns taken with `WriteQueue::wait_turns`, given back when dropped
+struct QueueTurns<'a> {
+    queue: &'a WriteQueue,
+    writes: Vec<QueuedWrite>,
+    turns: Vec<tokio::sync::OwnedMutexGuard<()>>,
+}
+
+impl Drop for QueueTurns<'_> {
+    fn drop(&mut self) {
+        self.turns.clear();
+        for write in &self.writes {
+            self.queue.finish(write);
+        }
+    }
+}
+
+impl WriteQueue {
+    fn writes(&self) -> Result<std::sync::MutexGuard<'_, std::collections::HashMap<String, PathWrites>>, String> {
+        self.0.lock().map_err(|_| "Write queue is poisoned".to_string())
+    }
+    
+    /// Queue a `save_file`, superseding the saves of the path queued before it
+    fn enqueue(&self, path: &str) -> Result<QueuedWrite, String> {
+        self.enqueue_write(path, true)
+    }
+    
+    fn enqueue_write(&self, path: &str, supersedes: bool) -> Result<QueuedWrite, String> {
+        let key = canonical_key(path);
+        let mut writes = self.writes()?;
+        let entry = writes.entry(key.clone()).or_insert_with(|| PathWrites {
+            latest: 0,
+            pending: 0,
+            lock: Arc::new(tokio::sync::Mutex::new(())),
+        });
+        if supersedes {
+            entry.latest += 1;
+        }
+        entry.pending += 1;
+        Ok(QueuedWrite {
+            key,
+            number: entry.latest,
+            lock: entry.lock.clone(),
+        })
+    }
+    
+    /// Wait for the turn of every path in `paths` (duplicates count once), for a
+    /// write that must neither be skipped nor make queued saves skip
+    async fn wait_turns(&self, paths: &[&str]) -> Result<QueueTurns<'_>, String> {
+        let mut turns = QueueTurns { queue: self, writes: Vec::new(), turns: Vec::new() };
+        for path in paths {
+            let key = canonical_key(path);
+            if !turns.writes.iter().any(|write| write.key == key) {
+                turns.writes.push(self.enqueue_write(path, false)?);
+            }
+        }
+        // Take the turns in key order so two overlapping sets can't wait on each other
+        turns.writes.sort_by(|a, b| a.key.cmp(&b.key));
+        for i in 0..turns.writes.len() {
+            let lock = turns.writes[i].lock.clone();
+            turns.turns.push(lock.lock_owned().await);
+        }
+        Ok(turns)
+    }
+    
+    /// Whether newer content for the same path was submitted after this write
+    fn is_superseded(&self, write: &QueuedWrite) -> bool {
+        self.writes()
+            .map(|writes| writes.get(&write.key).is_some_and(|entry| entry.latest > write.number))
+            .unwrap_or(false)
+    }
+    
+    fn finish(&self, write: &QueuedWrite) {
+        if let Ok(mut writes) = self.writes() {
+            if let Some(entry) = writes.get_mut(&write.key) {
+                entry.pending -= 1;
+                if entry.pending == 0 {
+                    writes.remove(&write.key);
+                }
+            }
+        }
+    }
+}
+
+/// Social media image presets
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum SocialPreset {
+    InstagramSquare,
+    InstagramPortrait,
+    InstagramStory,
+    Twitter,
+    Facebook,
+    Linkedin,
+    Pinterest,
+    YoutubeThumbnail,
+}
+
+impl SocialPreset {
+    /// Recommended image size in pixels
+    fn dimensions(self) -> (u32, u32) {
+        match self {
+            SocialPreset::InstagramSquare => (1080, 1080),
+            SocialPreset::InstagramPortrait => (1080, 1350),
+            SocialPreset::InstagramStory => (1080, 1920),
+            SocialPreset::Twitter => (1200, 675),
+            SocialPreset::Facebook => (1200, 630),
+            SocialPreset::Linkedin => (1200, 627),
+            SocialPreset::Pinterest => (1000, 1500),
+            SocialPreset::YoutubeThumbnail => (1280, 720),
+        }
+    }
+}
+
+/// Standard paper sizes for print exports
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PaperSize {
+    A5,
+    A4,
+    A3,
+    Letter,
+    Legal,
+}
+
+impl PaperSize {
+    /// Portrait width and height in millimetres
+    fn dimensions_mm(self) -> (f32, f32) {
+        match self {
+            PaperSize::A5 => (148.0, 210.0),
+            PaperSize::A4 => (210.0, 297.0),
+            PaperSize::A3 => (297.0, 420.0),
+            PaperSize::Letter => (215.9, 279.4),
+            PaperSize::Legal => (215.9, 355.6),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+/// Print finishing for `export_to_png_for_print`
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+pub struct ExportPrintSettings {
+    /// Extra artwork kept past the trim edge on each side, in millimetres
+    pub bleed_mm: f32,
+    pub crop_marks: bool,
+    pub registration_marks: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SocialExport {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SvgOptimizationResult {
+    pub svg: String,
+    pub original_size: u64,
+    pub optimized_size: u64,
+    pub duplicates_removed: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PaletteRemapResult {
+    pub svg: String,
+    pub colors_changed: u32,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoundtripReport {
+    /// Mean per-channel difference between the two renders, from 0.0 to 1.0
+    pub difference: f64,
+    pub differing_pixels: u64,
+    pub total_pixels: u64,
+    pub dropped_node_types: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SvgValidationResult {
+    pub is_valid: bool,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    pub width: Option<f32>,
+    pub height: Option<f32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OutlinedSvg {
+    pub svg: String,
+    /// Text that couldn't be turned into outlines, e.g. for lack of a font
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CompatWarning {
+    /// "transparency", "gradients", "patterns", "filters", "masks", "clip_paths", "images" or "text"
+    pub feature: String,
+    pub message: String,
+    /// Ids of the affected elements that have one
+    pub element_ids: Vec<String>,
+    pub element_count: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CompatReport {
+    pub format: String,
+    pub warnings: Vec<CompatWarning>,
+}
+
+/// Payload of the `read-progress` event
+#[derive(Debug, Serialize, Clone)]
+pub struct ReadProgress {
+    pub request_id: String,
+    pub bytes_read: u64,
+    pub total: u64,
+}
+
+/// Payload of the `download-progress` event
+#[derive(Debug, Serialize, Clone)]
+pub struct DownloadProgress {
+    pub url: String,
+    pub bytes_received: u64,
+    pub content_length: Option<u64>,
+}
+
+/// Payload of the `video-export-progress` event
+#[derive(Debug, Serialize, Clone)]
+pub struct VideoExportProgress {
+    pub frame: u32,
+    pub total: u32,
+}
+
+/// One PNG to render in `batch_export`
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExportJob {
+    pub svg_data: String,
+    pub width: u32,
+    pub height: u32,
+    pub output_path: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ExportJobResult {
+    pub output_path: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct BatchResult {
+    pub results: Vec<ExportJobResult>,
+    pub succeeded: u32,
+    pub failed: u32,
+}
+
+/// Payload of the `batch-export-progress` event; `index` is the job's position in the input
+#[derive(Debug, Serialize, Clone)]
+pub struct BatchExportProgress {
+    pub index: u32,
+    pub completed: u32,
+    pub total: u32,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RenameResult {
+    pub old_path: String,
+    pub new_path: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DroppedFileStatus {
+    Openable,
+    Invalid,
+    NotADrawing,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DroppedFileResult {
+    pub path: String,
+    pub status: DroppedFileStatus,
+    pub name: Option<String>,
+    pub error: Option<String>,
+}
+
+/// A font file registered at runtime through `register_font`
+struct CustomFont {
+    family: String,
+    path: String,
+    ids: Vec<usvg::fontdb::ID>,
+}
+
+#[derive(Default)]
+struct FontState {
+    db: Option<Arc<usvg::fontdb::Database>>,
+    custom: Vec<CustomFont>,
+    /// Cached font listing, cleared whenever the database changes
+    listing: Option<Vec<FontInfo>>,
+}
+
+impl FontState {
+    /// The font database used for SVG rendering, loading system fonts on first use
+    fn database(&mut self) -> &mut Arc<usvg::fontdb::Database> {
+        self.db.get_or_insert_with(|| {
+            let mut db = usvg::fontdb::Database::new();
+            db.load_system_fonts();
+            Arc::new(db)
+        })
+    }
+}
+
+/// Fonts shared by every SVG render and the font listing commands
+#[derive(Default)]
+struct FontStore(Mutex<FontState>);
+
+impl FontStore {
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, FontState>, String> {
+        self.0.lock().map_err(|_| "Font store is poisoned".to_string())
+    }
+
+    /// Build usvg options resolving text against the shared font database
+    fn svg_options(&self) -> Result<usvg::Options<'static>, String> {
+        Ok(usvg::Options {
+            fontdb: self.lock()?.database().clone(),
+            ..Default::default()
+        })
+    }
+}
+
+/// Parsed SVG trees kept per cache
+const TREE_CACHE_CAPACITY: usize = 4;
+
+/// Recently exported SVGs, parsed, so re-rendering the same SVG with different
+/// raster options (as the export dialog preview does) skips parsing. Keyed by the
+/// SVG's hash, least recently used first. Font changes must call `clear`.
+#[derive(Default)]
+struct TreeCache(Mutex<Vec<(String, Arc<usvg::Tree>)>>);
+
+impl TreeCache {
+    /// The parsed tree of an SVG, from the cache or freshly parsed. The lock is only
+    /// held for lookups, so parses and renders of different SVGs run concurrently.
+    fn get_or_parse(&self, svg: &str, opt: &usvg::Options) -> Result<Arc<usvg::Tree>, String> {
+        let key = sha256_hex(svg.as_bytes());
+        {
+            let mut entries = self.0.lock().map_err(|_| "Tree cache is poisoned".to_string())?;
+            if let Some(index) = entries.iter().position(|(cached, _)| *cached == key) {
+                let entry = entries.remove(index);
+                let tree = entry.1.clone();
+                entries.push(entry);
+                return Ok(tree);
+            }
+        }
+        
+        let tree = Arc::new(usvg::Tree::from_str(svg, opt).map_err(|e| format!("Failed to parse SVG: {}", e))?);
+        let mut entries = self.0.lock().map_err(|_| "Tree cache is poisoned".to_string())?;
+        if !entries.iter().any(|(cached, _)| *cached == key) {
+            if entries.len() >= TREE_CACHE_CAPACITY {
+                entries.remove(0);
+            }
+            entries.push((key, tree.clone()));
+        }
+        Ok(tree)
+    }
+    
+    fn clear(&self) {
+        if let Ok(mut entries) = self.0.lock() {
+            entries.clear();
+        }
+    }
+}
+
+/// Replaces the platform app data directory when managed, e.g. for a portable install
+pub struct AppDataDir(pub PathBuf);
+
+/// Get the app data directory for storing recent files list
+fn get_app_data_dir<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    if let Some(dir) = app.try_state::<AppDataDir>() {
+        return Ok(dir.0.clone());
+    }
+    app.path()
+        .app_data_dir()
+        .map_err(|e| format!("Failed to get app data directory: {}", e))
+}
+
+/// Get the path of a file in the app data directory
+fn get_app_data_file<R: Runtime>(app: &tauri::AppHandle<R>, file_name: &str) -> Result<PathBuf, String> {
+    let mut path = get_app_data_dir(app)?;
+    // Ensure directory exists
+    fs::create_dir_all(&path).map_err(|e| format!("Failed to create app data directory: {}", e))?;
+    path.push(file_name);
+    Ok(path)
+}
+
+/// Get a subdirectory of the app data directory, creating it if needed
+fn get_app_data_subdir<R: Runtime>(app: &tauri::AppHandle<R>, dir_name: &str) -> Result<PathBuf, String> {
+    let path = get_app_data_dir(app)?.join(dir_name);
+    fs::create_dir_all(&path).map_err(|e| format!("Failed to create {} directory: {}", dir_name, e))?;
+    Ok(path)
+}
+
+/// Get the recent files JSON path
+fn get_recent_files_path<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    get_app_data_file(app, "recent_files.json")
+}
+
+/// Load the recent files list, treating an unreadable file as empty
+fn load_recent_files<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<Vec<RecentFile>, String> {
+    Ok(read_json_or_default(&get_recent_files_path(app)?))
+}
+
+/// Persist the recent files list and send it to every window
+fn save_recent_files<R: Runtime>(app: &tauri::AppHandle<R>, files: &[RecentFile]) -> Result<(), String> {
+    write_json(&get_recent_files_path(app)?, &files)?;
+    notify_recent_files_changed(app, files);
+    Ok(())
+}
+
+/// Emit `recent-files-changed` with the stored list (unsorted, without tags) to all
+/// windows. Only sent once the list is on disk, so re-querying sees the same data
+fn notify_recent_files_changed<R: Runtime>(app: &tauri::AppHandle<R>, files: &[RecentFile]) {
+    let _ = app.emit("recent-files-changed", files);
+}
+
+/// Get the tags JSON path
+fn get_tags_path<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    get_app_data_file(app, "tags.json")
+}
+
+/// Get the favorites JSON path
+fn get_favorites_path<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    get_app_data_file(app, "favorites.json")
+}
+
+/// Get the view states JSON path
+fn get_view_states_path<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    get_app_data_file(app, "view_states.json")
+}
+
+/// Get the preferences JSON path
+fn get_preferences_path<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    get_app_data_file(app, "preferences.json")
+}
+
+/// Load preferences, using defaults when the file is absent or unreadable
+fn load_preferences<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<Preferences, String> {
+    Ok(read_json_or_default(&get_preferences_path(app)?))
+}
+
+/// Check preference values before they're persisted
+fn validate_preferences(prefs: &Preferences) -> Result<(), String> {
+    if !["light", "dark", "system"].contains(&prefs.theme.as_str()) {
+        return Err(format!("Invalid theme: {}", prefs.theme));
+    }
+    if !["png", "svg"].contains(&prefs.default_export_format.as_str()) {
+        return Err(format!("Invalid export format: {}", prefs.default_export_format));
+    }
+    if !(1..=500).contains(&prefs.grid_size) {
+        return Err(format!("Grid size must be between 1 and 500, got {}", prefs.grid_size));
+    }
+    if prefs.autosave_interval_secs < 5 {
+        return Err(format!(
+            "Autosave interval must be at least 5 seconds, got {}",
+            prefs.autosave_interval_secs
+        ));
+    }
+    if !(1..=1000).contains(&prefs.max_recent_files) {
+        return Err(format!(
+            "Recent files limit must be between 1 and 1000, got {}",
+            prefs.max_recent_files
+        ));
+    }
+    if prefs.backup_retention == 0 {
+        return Err("Backup retention must keep at least one backup".to_string());
+    }
+    if prefs.backup_storage_cap_mb == 0 {
+        return Err("Backup storage cap must be at least 1 MB".to_string());
+    }
+    if prefs.max_file_size_mb == 0 {
+        return Err("File size limit must be at least 1 MB".to_string());
+    }
+    if prefs.io_timeout_secs == 0 {
+        return Err("File operation timeout must be at least 1 second".to_string());
+    }
+    if let Some(hook) = &prefs.post_save_hook {
+        if hook.command.trim().is_empty() {
+            return Err("Post-save hook command must not be empty".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Read a JSON store, falling back to the default when missing or unreadable
+fn read_json_or_default<T: DeserializeOwned + Default>(path: &PathBuf) -> T {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+/// Write a JSON store atomically
+fn write_json<T: Serialize>(path: &PathBuf, value: &T) -> Result<(), String> {
+    let content = serde_json::to_string_pretty(value)
+        .map_err(|e| format!("Failed to serialize {}: {}", path.display(), e))?;
+    write_atomic(path, content.as_bytes(), false).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Held across a read-modify-write of the JSON stores in the app data directory
+/// (recent files, tags, favorites, view states, template manifest, snapshot indexes)
+/// so commands running side by side on the blocking pool don't lose each other's updates
+static JSON_STORES: Mutex<()> = Mutex::new(());
+
+fn lock_json_stores() -> std::sync::MutexGuard<'static, ()> {
+    // The data lives on disk, so a panic mid-update leaves nothing in memory to poison
+    JSON_STORES.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Error prefix for a file operation that didn't finish within `io_timeout_secs`
+const ERR_TIMED_OUT: &str = "TIMED_OUT";
+
+/// Run blocking file I/O on the blocking thread pool so a slow disk or a hung network
+/// mount can't stall unrelated commands. Gives up after the `io_timeout_secs`
+/// preference; the operation itself can't be cancelled and finishes in the background.
+async fn run_blocking<R, T, F>(app: tauri::AppHandle<R>, task: F) -> Result<T, String>
+where
+    R: Runtime,
+    T: Send + 'static,
+    F: FnOnce(tauri::AppHandle<R>) -> Result<T, String> + Send + 'static,
+{
+    let timeout_secs = load_preferences(&app)?.io_timeout_secs;
+    let handle = tauri::async_runtime::spawn_blocking(move || task(app));
+    match tokio::time::timeout(std::time::Duration::from_secs(timeout_secs), handle).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(format!("File operation failed: {}", e)),
+        Err(_) => Err(format!(
+            "{}: the file operation did not finish within {} seconds",
+            ERR_TIMED_OUT, timeout_secs
+        )),
+    }
+}
+
+/// Distinguishes the temp files of concurrent `write_atomic` calls
+static TEMP_FILE_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Write a file through a temp file in the same directory and rename it into place,
+/// so readers never observe a partially written file. With `durable` set, the temp
+/// file is fsynced before the rename and the parent directory after it.
+fn write_atomic(path: &Path, bytes: &[u8], durable: bool) -> Result<(), String> {
+    write_atomic_parts(path, &[bytes], durable)
+}
+
+/// `write_atomic` for content made of several slices, written one after another
+fn write_atomic_parts(path: &Path, parts: &[&[u8]], durable: bool) -> Result<(), String> {
+    use std::io::Write;
+    
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| format!("Invalid file path: {}", path.display()))?;
+    // Unique per call, so concurrent writes to one path never share a temp file
+    let tmp_path = path.with_file_name(format!(
+        ".{}.{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    ));
+    
+    let write_tmp = || -> std::io::Result<()> {
+        let mut file = fs::File::create(&tmp_path)?;
+        for part in parts {
+            file.write_all(part)?;
+        }
+        if durable {
+            file.sync_all()?;
+        }
+        Ok(())
+    };
+    if let Err(e) = write_tmp() {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(format!("Failed to write file: {}", e));
+    }
+    
+    fs::rename(&tmp_path, path).map_err(|e| {
+        let _ = fs::remove_file(&tmp_path);
+        format!("Failed to replace file: {}", e)
+    })?;
+    
+    if durable {
+        sync_parent_dir(path)?;
+    }
+    Ok(())
+}
+
+/// Flush a directory entry change (create/rename) to disk
+#[cfg(unix)]
+fn sync_parent_dir(path: &Path) -> Result<(), String> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::File::open(parent)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| format!("Failed to sync directory: {}", e))
+}
+
+/// Windows commits the rename with the file's metadata; there's no directory handle to sync
+#[cfg(not(unix))]
+fn sync_parent_dir(_path: &Path) -> Result<(), String> {
+    Ok(())
+}
+
+/// Read and parse a drawing file
+fn read_drawing(path: &Path) -> Result<DrawingFile, String> {
+    let content = read_text_content(path)?;
+    serde_json::from_str(&content).map_err(|e| format!("Failed to parse drawing: {}", e))
+}
+
+/// Serialize a drawing the way the frontend does, stamp its checksum and write it atomically
+fn write_drawing(path: &Path, drawing: &mut DrawingFile) -> Result<(), String> {
+    drawing.checksum = Some(store_checksum(&drawing.store));
+    let content = serde_json::to_string_pretty(drawing)
+        .map_err(|e| format!("Failed to serialize drawing: {}", e))?;
+    write_atomic(path, content.as_bytes(), false)
+}
+
+/// Container a drawing file is stored in
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DrawingContainer {
+    Json,
+    Gzip,
+    Cbor,
+}
+
+/// Tell a drawing file's container from its first bytes
+fn drawing_container(bytes: &[u8]) -> DrawingContainer {
+    if bytes.starts_with(&GZIP_MAGIC) {
+        DrawingContainer::Gzip
+    } else if matches!(bytes.first(), Some(0xa0..=0xbb | 0xbf)) {
+        // A CBOR map; these bytes can't start UTF-8 text
+        DrawingContainer::Cbor
+    } else {
+        DrawingContainer::Json
+    }
+}
+
+/// Read and parse a drawing along with its container, so an edit can be written
+/// back the way it was stored. Encrypted files fail with a `PASSWORD_REQUIRED` error.
+fn read_drawing_in(path: &Path) -> Result<(DrawingFile, DrawingContainer), String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let container = drawing_container(&bytes);
+    let (text, _) = decode_utf8(decode_drawing_bytes(path, bytes, None)?, false)?;
+    let drawing = serde_json::from_str(&normalize_text(text))
+        .map_err(|e| format!("Failed to parse drawing: {}", e))?;
+    Ok((drawing, container))
+}
+
+/// Encode a drawing for `container`: pretty JSON the way the frontend writes it,
+/// that JSON gzipped, or CBOR
+fn encode_drawing(drawing: &DrawingFile, container: DrawingContainer) -> Result<Vec<u8>, String> {
+    let json = || serde_json::to_vec_pretty(drawing).map_err(|e| format!("Failed to serialize drawing: {}", e));
+    match container {
+        DrawingContainer::Json => json(),
+        DrawingContainer::Gzip => gzip(&json()?),
+        DrawingContainer::Cbor => {
+            let mut bytes = Vec::new();
+            ciborium::ser::into_writer(drawing, &mut bytes).map_err(|e| format!("Failed to encode CBOR: {}", e))?;
+            Ok(bytes)
+        }
+    }
+}
+
+/// Change a drawing file in place, keeping its container. A checksum the file
+/// already has is kept in step with the store; none is added.
+fn edit_drawing_file<T>(path: &Path, edit: impl FnOnce(&mut DrawingFile) -> Result<T, String>) -> Result<T, String> {
+    let (mut drawing, container) = read_drawing_in(path)?;
+    let result = edit(&mut drawing)?;
+    if drawing.checksum.is_some() {
+        drawing.checksum = Some(store_checksum(&drawing.store));
+    }
+    write_atomic(path, &encode_drawing(&drawing, container)?, false)?;
+    Ok(result)
+}
+
+/// `edit_drawing_file` on a path from the frontend, in the path's turn of the write
+/// queue so it neither races a `save_file` nor makes a queued one skip
+async fn edit_drawing<R, T, F>(app: tauri::AppHandle<R>, queue: &WriteQueue, path: String, edit: F) -> Result<T, String>
+where
+    R: Runtime,
+    T: Send + 'static,
+    F: FnOnce(&tauri::AppHandle<R>, &mut DrawingFile) -> Result<T, String> + Send + 'static,
+{
+    let _turn = queue.wait_turns(&[&path]).await?;
+    run_blocking(app, move |app| {
+        let path = check_path_scope(&app, &path)?;
+        edit_drawing_file(&path, |drawing| edit(&app, drawing))
+    })
+    .await
+}
+
+/// Write a JSON value in canonical form: object keys sorted by code point, no
+/// whitespace, integral numbers without a fraction and other numbers in shortest
+/// round-trip form, strings escaped the way serde_json does
+fn write_canonical_json(value: &serde_json::Value, out: &mut String) {
+    match value {
+        serde_json::Value::Null => out.push_str("null"),
+        serde_json::Value::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+        serde_json::Value::Number(n) => match n.as_f64() {
+            Some(f) if n.is_f64() && f.fract() == 0.0 && f.abs() < 9.0e15 => {
+                out.push_str(&(f as i64).to_string())
+            }
+            _ => out.push_str(&n.to_string()),
+        },
+        serde_json::Value::String(s) => {
+            out.push_str(&serde_json::to_string(s).unwrap_or_default())
+        }
+        serde_json::Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical_json(item, out);
+            }
+            out.push(']');
+        }
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<(&String, &serde_json::Value)> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            out.push('{');
+            for (i, (key, item)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&serde_json::to_string(key).unwrap_or_default());
+                out.push(':');
+                write_canonical_json(item, out);
+            }
+            out.push('}');
+        }
+    }
+}
+
+/// Checksum of a drawing store, independent of key order and number formatting
+fn store_checksum(store: &serde_json::Value) -> String {
+    let mut canonical = String::new();
+    write_canonical_json(store, &mut canonical);
+    sha256_hex(canonical.as_bytes())
+}
+
+/// Compare a drawing's stored checksum against its store; drawings without one pass
+fn verify_checksum(drawing: &DrawingFile) -> Option<ChecksumMismatch> {
+    let expected = drawing.checksum.as_ref()?;
+    let actual = store_checksum(&drawing.store);
+    if *expected == actual {
+        return None;
+    }
+    Some(ChecksumMismatch {
+        expected: expected.clone(),
+        actual,
+        message: "File may be corrupted: its content doesn't match the saved checksum".to_string(),
+    })
+}
+
+/// Canonical form of a path, used as the key of the sidecar stores.
+/// Falls back to the parent directory for files that don't exist yet.
+fn canonical_key(path: &str) -> String {
+    canonical_path(path)
+        .map(|canonical| canonical.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Absolute path with symlinks resolved, or for a file that doesn't exist yet its
+/// resolved parent joined with the file name. Relative paths are taken from the
+/// working directory.
+fn canonical_path(path: &str) -> Option<PathBuf> {
+    let path_buf = PathBuf::from(path);
+    if let Ok(canonical) = fs::canonicalize(&path_buf) {
+        return Some(canonical);
+    }
+    let parent = match path_buf.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let name = path_buf.file_name()?;
+    fs::canonicalize(parent).ok().map(|canonical| canonical.join(name))
+}
+
+/// Get the canonical absolute form of a path, so paths from the frontend and the
+/// backend compare equal. Files that don't exist yet resolve through their parent.
+#[tauri::command]
+async fn canonicalize_path(path: String) -> Result<String, String> {
+    canonical_path(&path)
+        .map(|canonical| canonical.to_string_lossy().to_string())
+        .ok_or_else(|| format!("Failed to resolve {}: neither it nor its parent directory exists", path))
+}
+
+/// Trim and lowercase tags, dropping empty ones and duplicates
+fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || normalized.contains(&tag) {
+            continue;
+        }
+        normalized.push(tag);
+    }
+    normalized
+}
+
+/// Tags match case-insensitively, which also covers stores written before tags
+/// were lowercased
+fn tag_matches(a: &str, b: &str) -> bool {
+    a.trim().to_lowercase() == b.trim().to_lowercase()
+}
+
+/// Set the tags of one file in the tags store, normalized with `normalize_tags` so
+/// "WIP" and "wip" can't end up as two variants of one tag, dropping its entry when
+/// none are left. Returns the stored tags
+fn store_tags(store: &mut BTreeMap<String, Vec<String>>, key: String, tags: Vec<String>) -> Vec<String> {
+    let tags = normalize_tags(tags);
+    store.remove(&key);
+    if !tags.is_empty() {
+        store.insert(key, tags.clone());
+    }
+    tags
+}
+
+/// Fill in the tags of recent files from the tags store
+fn attach_tags(files: &mut [RecentFile], store: &BTreeMap<String, Vec<String>>) {
+    for file in files.iter_mut() {
+        file.tags = store.get(&canonical_key(&file.path)).cloned().unwrap_or_default();
+    }
+}
+
+/// The recent files carrying a tag
+fn filter_by_tag(files: Vec<RecentFile>, tag: &str) -> Vec<RecentFile> {
+    files
+        .into_iter()
+        .filter(|f| f.tags.iter().any(|t| tag_matches(t, tag)))
+        .collect()
+}
+
+/// Run the configured post-save hook in the background and report its output as an event
+fn run_post_save_hook<R: Runtime>(app: &tauri::AppHandle<R>, path: &str) {
+    let hook = match load_preferences(app).ok().and_then(|prefs| prefs.post_save_hook) {
+        Some(hook) => hook,
+        None => return,
+    };
+    
+    let app = app.clone();
+    let path = path.to_string();
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut command = std::process::Command::new(&hook.command);
+        command.args(&hook.args).arg(&path);
+        if let Some(dir) = &hook.working_dir {
+            command.current_dir(dir);
+        }
+        
+        let output = match command.output() {
+            Ok(output) => PostSaveHookOutput {
+                path,
+                success: output.status.success(),
+                exit_code: output.status.code(),
+                stdout: String::from_utf8_lossy(&output.stdout).to_string(),
+                stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+                error: None,
+            },
+            Err(e) => PostSaveHookOutput {
+                path,
+                success: false,
+                exit_code: None,
+                stdout: String::new(),
+                stderr: String::new(),
+                error: Some(format!("Failed to run post-save hook: {}", e)),
+            },
+        };
+        let _ = app.emit("post-save-hook-output", output);
+    });
+}
+
+/// Hex encoded SHA-256 of some bytes
+fn sha256_hex(bytes: &[u8]) -> String {
+    use sha2::Digest;
+    
+    sha2::Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Folder created inside a user-chosen backup directory
+const EXTERNAL_BACKUPS_DIR: &str = "Jamal Backups";
+
+/// Root directory of the rotating backups: the user-chosen directory when it's
+/// available, otherwise the app data dir (with a warning event)
+fn get_backups_dir<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    if let Some(dir) = load_preferences(app)?.backup_directory {
+        let dir = PathBuf::from(dir);
+        if dir.is_dir() {
+            // Keep backups in their own folder so we never touch the user's other files
+            let root = dir.join(EXTERNAL_BACKUPS_DIR);
+            fs::create_dir_all(&root).map_err(|e| format!("Failed to create backup directory: {}", e))?;
+            return Ok(root);
+        }
+        tracing::warn!("Backup directory {} is unavailable, falling back to app data", dir.display());
+        let _ = app.emit("backup-directory-unavailable", dir.to_string_lossy().to_string());
+    }
+    get_app_data_subdir(app, "backups")
+}
+
+/// Check that a path is an existing directory we can write to
+fn validate_writable_dir(path: &str) -> Result<PathBuf, String> {
+    let dir = PathBuf::from(path);
+    if !dir.is_dir() {
+        return Err(format!("Not an existing directory: {}", path));
+    }
+    let probe = dir.join(format!(".jamal-write-test-{}", std::process::id()));
+    fs::write(&probe, b"").map_err(|e| format!("Directory is not writable: {}", e))?;
+    let _ = fs::remove_file(&probe);
+    Ok(dir)
+}
+
+/// Error prefix the frontend matches on when a path needs approval via `request_path_access`
+const ERR_OUT_OF_SCOPE: &str = "OUT_OF_SCOPE";
+
+/// Get the persisted path scope JSON path
+fn get_path_scope_path<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    get_app_data_file(app, "path_scope.json")
+}
+
+/// Whether the rest of a `\\?\` path names a drive or a UNC share rather than a device
+#[cfg(windows)]
+fn is_verbatim_disk_or_unc(rest: &str) -> bool {
+    let bytes = rest.as_bytes();
+    (bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':')
+        || rest.to_ascii_lowercase().starts_with("unc\\")
+}
+
+/// Turn a path into the form scope checks compare: absolute, without `.` or `..`,
+/// and with symlinks resolved as far as the path exists
+fn normalize_scope_path(path: &str) -> Result<PathBuf, String> {
+    if path.contains('\0') {
+        return Err(format!("Invalid path: {}", path));
+    }
+    
+    // Device namespace paths (\\.\PhysicalDrive0, \\?\GLOBALROOT\...) bypass normal file semantics
+    #[cfg(windows)]
+    {
+        let unified = path.replace('/', "\\");
+        let device = unified.starts_with("\\\\.\\")
+            || (unified.starts_with("\\\\?\\") && !is_verbatim_disk_or_unc(&unified[4..]));
+        if device {
+            return Err(format!("Device paths are not allowed: {}", path));
+        }
+    }
+    
+    let path = Path::new(path);
+    if !path.is_absolute() {
+        return Err(format!("Path must be absolute: {}", path.display()));
+    }
+    
+    // Resolve symlinks component by component, so a `..` after a link climbs out of
+    // the link's target the way the OS does; what doesn't exist yet can't be a link
+    let mut resolved = PathBuf::new();
+    for component in path.components() {
+        match component {
+            std::path::Component::CurDir => {}
+            std::path::Component::ParentDir => {
+                resolved.pop();
+            }
+            other => {
+                resolved.push(other);
+                if let Ok(canonical) = fs::canonicalize(&resolved) {
+                    resolved = canonical;
+                }
+            }
+        }
+    }
+    
+    Ok(resolved)
+}
+
+/// Allow access to a path and persist the grant
+fn grant_path<R: Runtime>(app: &tauri::AppHandle<R>, path: &str, directory: bool) -> Result<(), String> {
+    let grant = ScopeGrant {
+        path: normalize_scope_path(path)?.to_string_lossy().to_string(),
+        directory,
+    };
+    let scope = app.state::<PathScope>();
+    let mut grants = scope.0.lock().map_err(|_| "Path scope is poisoned".to_string())?;
+    if !grants.contains(&grant) {
+        grants.push(grant);
+        write_json(&get_path_scope_path(app)?, &*grants)?;
+    }
+    Ok(())
+}
+
+/// App data files that decide what the backend runs or may access. They're only
+/// written by their own commands, never through a path from the frontend.
+const PROTECTED_APP_DATA_FILES: [&str; 4] = ["preferences.json", "path_scope.json", "recent_files.json", "favorites.json"];
+
+/// Make sure a path is one the frontend may touch: under the app data directory
+/// (except `PROTECTED_APP_DATA_FILES`), granted by the user, or already in recents
+/// or favorites
+fn check_path_scope<R: Runtime>(app: &tauri::AppHandle<R>, path: &str) -> Result<PathBuf, String> {
+    let normalized = normalize_scope_path(path)?;
+    
+    let app_data = get_app_data_dir(app)
+        .ok()
+        .and_then(|dir| normalize_scope_path(&dir.to_string_lossy()).ok());
+    if let Some(app_data) = app_data {
+        if is_protected_app_data_file(&app_data, &normalized) {
+            return Err(format!("{}: {} is managed by Jamal", ERR_OUT_OF_SCOPE, path));
+        }
+        if normalized.starts_with(&app_data) {
+            return Ok(normalized);
+        }
+    }
+    
+    let granted = app
+        .state::<PathScope>()
+        .0
+        .lock()
+        .map(|grants| {
+            grants.iter().any(|grant| {
+                let granted = Path::new(&grant.path);
+                if grant.directory { normalized.starts_with(granted) } else { normalized == granted }
+            })
+        })
+        .unwrap_or(false);
+    if granted {
+        return Ok(normalized);
+    }
+    
+    let favorites: Vec<Favorite> = read_json_or_default(&get_favorites_path(app)?);
+    let known = load_recent_files(app)?
+        .into_iter()
+        .map(|f| f.path)
+        .chain(favorites.into_iter().map(|f| f.path))
+        .any(|known| normalize_scope_path(&known).is_ok_and(|known| known == normalized));
+    if known {
+        return Ok(normalized);
+    }
+    
+    Err(format!("{}: access to {} has not been granted", ERR_OUT_OF_SCOPE, path))
+}
+
+/// Whether `path` is one of `PROTECTED_APP_DATA_FILES` or one of their temp files,
+/// both normalized
+fn is_protected_app_data_file(app_data: &Path, path: &Path) -> bool {
+    if path.parent() != Some(app_data) {
+        return false;
+    }
+    // Case-insensitive file systems would open the same file under another casing
+    let name = path.file_name().map(|name| name.to_string_lossy().to_lowercase()).unwrap_or_default();
+    PROTECTED_APP_DATA_FILES.iter().any(|protected| {
+        name == *protected || (name.starts_with(&format!(".{}.", protected)) && name.ends_with(".tmp"))
+    })
+}
+
+/// Error prefix for a save whose missing parent directory couldn't be created
+const ERR_PARENT_NOT_CREATED: &str = "PARENT_NOT_CREATED";
+
+/// Create the missing parent directories of a file about to be written. Only
+/// paths in scope may get new directories, so a typo can't scaffold folders anywhere.
+fn ensure_parent_dir<R: Runtime>(app: &tauri::AppHandle<R>, path: &str, create_parents: bool) -> Result<(), String> {
+    let parent = match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() && !parent.exists() => parent,
+        _ => return Ok(()),
+    };
+    if !create_parents {
+        return Err(format!("Folder does not exist: {}", parent.display()));
+    }
+    check_path_scope(app, path)?;
+    fs::create_dir_all(parent)
+        .map_err(|e| format!("{}: could not create {}: {}", ERR_PARENT_NOT_CREATED, parent.display(), e))
+}
+
+/// Error prefix the frontend matches on when a save target can't be written
+const ERR_READ_ONLY: &str = "READ_ONLY";
+
+/// Find out whether `path` can be written, and if not, why. Missing files are
+/// judged by their directory.
+fn read_only_cause(path: &Path) -> Option<ReadOnlyCause> {
+    if let Ok(metadata) = fs::metadata(path) {
+        if metadata.permissions().readonly() {
+            return Some(ReadOnlyCause::Attribute);
+        }
+    }
+    
+    // Atomic saves create a sibling temp file, so the directory must be writable too
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let probe = dir.join(format!(".jamal-write-test-{}", std::process::id()));
+    match fs::write(&probe, b"") {
+        Ok(()) => {
+            let _ = fs::remove_file(&probe);
+            None
+        }
+        Err(_) => Some(ReadOnlyCause::Directory),
+    }
+}
+
+/// Error message for a save refused because of `cause`
+fn read_only_error(path: &str, cause: ReadOnlyCause) -> String {
+    match cause {
+        ReadOnlyCause::Attribute => format!("{}: file is marked read-only: {}", ERR_READ_ONLY, path),
+        ReadOnlyCause::Directory => format!("{}: directory is not writable: {}", ERR_READ_ONLY, path),
+    }
+}
+
+/// Move a directory, copying its files when a rename isn't possible (e.g. across drives)
+fn move_dir(from: &Path, to: &Path) -> Result<(), String> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::create_dir_all(to).map_err(|e| format!("Failed to create {}: {}", to.display(), e))?;
+    for entry in fs::read_dir(from).map_err(|e| format!("Failed to read {}: {}", from.display(), e))? {
+        let entry = entry.map_err(|e| format!("Failed to read {}: {}", from.display(), e))?;
+        fs::copy(entry.path(), to.join(entry.file_name()))
+            .map_err(|e| format!("Failed to copy {}: {}", entry.path().display(), e))?;
+    }
+    fs::remove_dir_all(from).map_err(|e| format!("Failed to remove {}: {}", from.display(), e))
+}
+
+/// Directory holding the backups of one file, named after its canonical path's hash
+fn get_file_backups_dir<R: Runtime>(app: &tauri::AppHandle<R>, path: &str) -> Result<PathBuf, String> {
+    let key = canonical_key(path);
+    Ok(get_backups_dir(app)?.join(&sha256_hex(key.as_bytes())[..32]))
+}
+
+/// List the backups in one file's backup directory, oldest first
+fn list_backup_files(dir: &Path) -> Vec<(PathBuf, BackupInfo)> {
+    let mut backups: Vec<(PathBuf, BackupInfo)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension().map(|ext| ext != "jamal").unwrap_or(true) {
+                return None;
+            }
+            let timestamp = path.file_stem()?.to_str()?.parse::<i64>().ok()?;
+            let size = entry.metadata().ok()?.len();
+            Some((path, BackupInfo { timestamp, size }))
+        })
+        .collect();
+    backups.sort_by_key(|(_, info)| info.timestamp);
+    backups
+}
+
+/// Write a timestamped backup of a saved file, then apply retention and the storage cap
+fn create_backup<R: Runtime>(app: &tauri::AppHandle<R>, path: &str, content: &[u8]) -> Result<(), String> {
+    let prefs = load_preferences(app)?;
+    let dir = get_file_backups_dir(app, path)?;
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create backup directory: {}", e))?;
+    
+    fs::write(dir.join("source.txt"), canonical_key(path))
+        .map_err(|e| format!("Failed to write backup: {}", e))?;
+    fs::write(dir.join(format!("{}.jamal", timestamp_millis())), content)
+        .map_err(|e| format!("Failed to write backup: {}", e))?;
+    
+    // Keep the newest N backups of this file
+    let backups = list_backup_files(&dir);
+    let excess = backups.len().saturating_sub(prefs.backup_retention as usize);
+    for (backup_path, _) in backups.into_iter().take(excess) {
+        let _ = fs::remove_file(backup_path);
+    }
+    
+    enforce_backup_cap(app, prefs.backup_storage_cap_mb * 1024 * 1024)
+}
+
+/// Prune the oldest backups across all files until the total fits under the cap
+fn enforce_backup_cap<R: Runtime>(app: &tauri::AppHandle<R>, cap_bytes: u64) -> Result<(), String> {
+    let root = get_backups_dir(app)?;
+    let mut all: Vec<(PathBuf, BackupInfo)> = fs::read_dir(&root)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .flat_map(|entry| list_backup_files(&entry.path()))
+        .collect();
+    all.sort_by_key(|(_, info)| info.timestamp);
+    
+    let mut total: u64 = all.iter().map(|(_, info)| info.size).sum();
+    let mut report = BackupPruneReport {
+        pruned_at: chrono_timestamp(),
+        ..Default::default()
+    };
+    for (backup_path, info) in all {
+        if total <= cap_bytes {
+            break;
+        }
+        if fs::remove_file(&backup_path).is_ok() {
+            total -= info.size;
+            report.backups_removed += 1;
+            report.bytes_freed += info.size;
+        }
+    }
+    
+    if report.backups_removed > 0 {
+        write_json(&root.join("last_prune.json"), &report)?;
+    }
+    Ok(())
+}
+
+/// List the backups of a file, newest first
+#[tauri::command]
+async fn list_backups<R: Runtime>(app: tauri::AppHandle<R>, path: String) -> Result<Vec<BackupInfo>, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        let dir = get_file_backups_dir(&app, &path)?;
+        Ok(list_backup_files(&dir).into_iter().rev().map(|(_, info)| info).collect())
+    })
+    .await
+}
+
+/// Read the content of one backup so it can be previewed before restoring
+#[tauri::command]
+async fn read_backup<R: Runtime>(app: tauri::AppHandle<R>, path: String, timestamp: i64) -> Result<String, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        let backup_path = get_file_backups_dir(&app, &path)?.join(format!("{}.jamal", timestamp));
+        fs::read_to_string(&backup_path).map_err(|e| format!("Failed to read backup: {}", e))
+    })
+    .await
+}
+
+/// Choose where backups are written; `None` goes back to the app data dir.
+/// Existing backups stay where they are (see `migrate_backups`).
+#[tauri::command]
+async fn set_backup_directory<R: Runtime>(app: tauri::AppHandle<R>, path: Option<String>) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        if let Some(path) = &path {
+            check_path_scope(&app, path)?;
+            validate_writable_dir(path)?;
+        }
+        
+        let mut prefs = load_preferences(&app)?;
+        prefs.backup_directory = path;
+        write_json(&get_preferences_path(&app)?, &prefs)
+    })
+    .await
+}
+
+/// Move all existing backups into a new directory and make it the backup directory
+#[tauri::command]
+async fn migrate_backups<R: Runtime>(app: tauri::AppHandle<R>, to_new_dir: String) -> Result<u32, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &to_new_dir)?;
+        let target = validate_writable_dir(&to_new_dir)?.join(EXTERNAL_BACKUPS_DIR);
+        fs::create_dir_all(&target).map_err(|e| format!("Failed to create backup directory: {}", e))?;
+        let source = get_backups_dir(&app)?;
+        if canonical_key(&source.to_string_lossy()) == canonical_key(&target.to_string_lossy()) {
+            return Ok(0);
+        }
+        
+        let entries: Vec<fs::DirEntry> = fs::read_dir(&source)
+            .map_err(|e| format!("Failed to read backups: {}", e))?
+            .flatten()
+            .filter(|entry| entry.path().is_dir() || entry.file_name() == "last_prune.json")
+            .collect();
+        
+        let total = entries.len() as u32;
+        let mut moved = 0;
+        for entry in entries {
+            let destination = target.join(entry.file_name());
+            if entry.path().is_dir() {
+                move_dir(&entry.path(), &destination)?;
+            } else {
+                fs::copy(entry.path(), &destination).map_err(|e| format!("Failed to move backup: {}", e))?;
+                let _ = fs::remove_file(entry.path());
+            }
+            moved += 1;
+            let _ = app.emit("backup-migration-progress", BackupMigrationProgress { moved, total });
+        }
+        
+        let mut prefs = load_preferences(&app)?;
+        prefs.backup_directory = Some(to_new_dir);
+        write_json(&get_preferences_path(&app)?, &prefs)?;
+        
+        Ok(moved)
+    })
+    .await
+}
+
+/// Report backup storage use and the last cap-triggered prune, for diagnostics
+#[tauri::command]
+async fn get_backup_status<R: Runtime>(app: tauri::AppHandle<R>) -> Result<BackupStatus, String> {
+    run_blocking(app, move |app| {
+        let root = get_backups_dir(&app)?;
+        let prefs = load_preferences(&app)?;
+        
+        let total_bytes = fs::read_dir(&root)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|entry| entry.path().is_dir())
+            .flat_map(|entry| list_backup_files(&entry.path()))
+            .map(|(_, info)| info.size)
+            .sum();
+        let last_prune = fs::read_to_string(root.join("last_prune.json"))
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok());
+        
+        Ok(BackupStatus {
+            total_bytes,
+            cap_bytes: prefs.backup_storage_cap_mb * 1024 * 1024,
+            last_prune,
+        })
+    })
+    .await
+}
+
+/// Parse `content` as strict JSON, pointing at the first syntax error
+fn check_json(content: &str) -> Result<(), String> {
+    serde_json::from_str::<serde_json::Value>(content)
+        .map(|_| ())
+        // serde_json's message already ends with "at line L column C"
+        .map_err(|e| format!("Invalid JSON: {}", e))
+}
+
+/// Check that `content` is valid JSON (no trailing commas, `NaN` or the like)
+/// without saving it
+#[tauri::command]
+async fn validate_json(content: String) -> Result<(), String> {
+    check_json(&content)
+}
+
+/// How `save_file` writes; every field is optional for the frontend
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SaveOptions {
+    /// Fsync the data before returning, which is slow but survives power loss.
+    /// Defaults to off for autosaves and to the `durable_saves` preference otherwise
+    pub durable: Option<bool>,
+    /// Autosaves skip the backup and don't create missing parent directories
+    pub autosave: bool,
+    /// Create missing parent directories; defaults to on for explicit saves
+    pub create_parents: Option<bool>,
+    /// Refuse content that isn't valid JSON before anything is written
+    pub validate: bool,
+}
+
+/// Save a drawing file to disk, atomically, as set out by `options`. Explicit saves
+/// (anything but `autosave`) also write a rotating backup and fill in the `palette`
+/// of drawings saved with a `previewSvg`.
+/// Saves to the same path run in submission order; a save overtaken by a newer
+/// one for the same path before it started is skipped and still succeeds.
+#[tauri::command]
+async fn save_file<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    queue: tauri::State<'_, WriteQueue>,
+    path: String,
+    content: String,
+    options: Option<SaveOptions>,
+) -> Result<(), String> {
+    let SaveOptions { durable, autosave, create_parents, validate } = options.unwrap_or_default();
+    if validate {
+        check_json(&content)?;
+    }
+    
+    let write = queue.enqueue(&path)?;
+    let _turn = write.lock.lock().await;
+    if queue.is_superseded(&write) {
+        // Newer content is queued for this path; writing this one would be wasted
+        queue.finish(&write);
+        return Ok(());
+    }
+    
+    let result = run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        ensure_parent_dir(&app, &path, create_parents.unwrap_or(!autosave))?;
+        if let Some(cause) = read_only_cause(Path::new(&path)) {
+            return Err(read_only_error(&path, cause));
+        }
+        let durable = match durable {
+            Some(durable) => durable,
+            None => !autosave && load_preferences(&app)?.durable_saves,
+        };
+        let content = if autosave {
+            content
+        } else {
+            // Keep the palette in step with the preview the drawing is saved with
+            let options = match app.try_state::<FontStore>() {
+                Some(fonts) => fonts.svg_options()?,
+                None => usvg::Options::default(),
+            };
+            with_palette(&content, &options).unwrap_or(content)
+        };
+        tracing::debug!("Saving {} ({} bytes, durable: {}, autosave: {})", path, content.len(), durable, autosave);
+        write_atomic(Path::new(&path), content.as_bytes(), durable).map_err(|e| {
+            tracing::error!("Failed to save {}: {}", path, e);
+            format!("Failed to save file: {}", e)
+        })?;
+        
+        if !autosave {
+            // The save itself succeeded; a failed backup shouldn't turn it into an error
+            if let Err(e) = create_backup(&app, &path, content.as_bytes()) {
+                tracing::error!("Failed to back up {}: {}", path, e);
+            }
+        }
+        
+        run_post_save_hook(&app, &path);
+        publish_event(&app, DrawingEvent::FileSaved(path));
+        Ok(())
+    })
+    .await;
+    queue.finish(&write);
+    result
+}
+
+/// Save a group of files so that either all of them are replaced or none are.
+/// Queued with `save_file` writes to the same paths, so neither overwrites the other
+/// midway. A set never makes a queued `save_file` skip, since the set may still fail.
+#[tauri::command]
+async fn save_file_set(
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, WriteQueue>,
+    files: Vec<FileWrite>,
+) -> Result<(), String> {
+    let paths: Vec<&str> = files.iter().map(|file| file.path.as_str()).collect();
+    let _turns = queue.wait_turns(&paths).await?;
+    run_blocking(app, move |app| {
+        for file in &files {
+            check_path_scope(&app, &file.path)?;
+        }
+        write_file_set(&files)
+    })
+    .await
+}
+
+/// Replace every file of a set or none of them: stage them all as temp files, then
+/// swap them in with the originals kept aside until the last rename succeeded
+fn write_file_set(files: &[FileWrite]) -> Result<(), String> {
+    let mut keys = std::collections::HashSet::new();
+    if let Some(duplicate) = files.iter().find(|f| !keys.insert(canonical_key(&f.path))) {
+        return Err(format!("{} appears more than once in the set", duplicate.path));
+    }
+    
+    let sibling = |path: &Path, suffix: &str| -> PathBuf {
+        let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        path.with_file_name(format!(
+            ".{}.{}.{}.{}",
+            name,
+            std::process::id(),
+            TEMP_FILE_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed),
+            suffix
+        ))
+    };
+    let targets: Vec<PathBuf> = files.iter().map(|f| PathBuf::from(&f.path)).collect();
+    let temps: Vec<PathBuf> = targets.iter().map(|t| sibling(t, "tmp")).collect();
+    let originals: Vec<PathBuf> = targets.iter().map(|t| sibling(t, "orig")).collect();
+    
+    // Stage every file first; nothing visible has changed if this fails
+    for (file, temp) in files.iter().zip(&temps) {
+        if let Err(e) = fs::write(temp, &file.content) {
+            temps.iter().for_each(|t| { let _ = fs::remove_file(t); });
+            return Err(format!("Failed to write {}: {}", file.path, e));
+        }
+    }
+    
+    // Swap them in, keeping the originals aside until every rename succeeded
+    let mut moved_aside = vec![false; targets.len()];
+    let mut failure = None;
+    for (i, target) in targets.iter().enumerate() {
+        if target.exists() {
+            if let Err(e) = fs::rename(target, &originals[i]) {
+                failure = Some((i, e));
+                break;
+            }
+            moved_aside[i] = true;
+        }
+        if let Err(e) = fs::rename(&temps[i], target) {
+            failure = Some((i, e));
+            break;
+        }
+    }
+    
+    if let Some((failed, e)) = failure {
+        for i in (0..=failed).rev() {
+            if i < failed {
+                let _ = fs::remove_file(&targets[i]);
+            }
+            if moved_aside[i] {
+                let _ = fs::rename(&originals[i], &targets[i]);
+            }
+        }
+        temps.iter().for_each(|t| { let _ = fs::remove_file(t); });
+        return Err(format!("Failed to save {}: {}", files[failed].path, e));
+    }
+    
+    originals.iter().for_each(|o| { let _ = fs::remove_file(o); });
+    Ok(())
+}
+
+/// Magic bytes at the start of a gzip stream
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Read a drawing's text, decompressing gzip by its magic bytes whatever the extension
+/// and normalizing BOM and line endings. Encrypted files fail with a `PASSWORD_REQUIRED` error.
+fn read_text_content(path: &Path) -> Result<String, String> {
+    read_text_limited(path, None, false).map(|content| content.text)
+}
+
+/// Error prefix for a file over the size limit; the frontend can retry with `allow_large`
+const ERR_FILE_TOO_LARGE: &str = "FILE_TOO_LARGE";
+
+/// Share of the size limit above which a read succeeds with a warning
+const SIZE_WARNING_RATIO: f64 = 0.8;
+
+fn file_too_large_error(path: &Path, size: u64, limit: u64) -> String {
+    format!(
+        "{}: {} is {} bytes, more than the {} byte limit",
+        ERR_FILE_TOO_LARGE,
+        path.display(),
+        size,
+        limit
+    )
+}
+
+/// Read a file's raw bytes, refusing files over `limit` bytes
+fn read_bytes_limited(path: &Path, limit: Option<u64>) -> Result<Vec<u8>, String> {
+    read_bytes_with_progress(path, limit, &mut |_, _| {})
+}
+
+/// Chunk size of reads that report progress
+const READ_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// `read_bytes_limited`, calling `progress(bytes_read, total)` after every chunk
+/// and once more at the end, when `bytes_read == total`
+fn read_bytes_with_progress(
+    path: &Path,
+    limit: Option<u64>,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+    
+    let mut file = fs::File::open(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let size = file.metadata().map_err(|e| format!("Failed to read file: {}", e))?.len();
+    if let Some(limit) = limit.filter(|&limit| size > limit) {
+        return Err(file_too_large_error(path, size, limit));
+    }
+    
+    let mut content = Vec::with_capacity(size as usize);
+    let mut buffer = vec![0u8; READ_CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buffer).map_err(|e| format!("Failed to read file: {}", e))?;
+        if read == 0 {
+            break;
+        }
+        content.extend_from_slice(&buffer[..read]);
+        // A file growing while we read it mustn't get past the limit either
+        if let Some(limit) = limit.filter(|&limit| content.len() as u64 > limit) {
+            return Err(file_too_large_error(path, content.len() as u64, limit));
+        }
+        progress(content.len() as u64, size.max(content.len() as u64));
+    }
+    
+    // The file may have changed size while reading; always finish at 100%
+    progress(content.len() as u64, content.len() as u64);
+    Ok(content)
+}
+
+/// Turn the bytes of a drawing file in any supported format into JSON bytes,
+/// sniffing the format from its content: encrypted envelopes are refused, gzip is
+/// decompressed (bounded by `limit`) and CBOR is converted
+fn decode_drawing_bytes(path: &Path, bytes: Vec<u8>, limit: Option<u64>) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+    
+    if bytes.starts_with(ENCRYPTED_MAGIC) {
+        return Err(format!("{}: {} is password protected", ERR_PASSWORD_REQUIRED, path.display()));
+    }
+    let bytes = match drawing_container(&bytes) {
+        DrawingContainer::Gzip => {
+            // Bound the decompressed stream so a small file can't expand without limit
+            let mut decoded = Vec::new();
+            flate2::read::GzDecoder::new(&bytes[..])
+                .take(limit.map_or(u64::MAX, |limit| limit + 1))
+                .read_to_end(&mut decoded)
+                .map_err(|e| format!("Failed to decompress: {}", e))?;
+            if let Some(limit) = limit.filter(|&limit| decoded.len() as u64 > limit) {
+                return Err(file_too_large_error(path, decoded.len() as u64, limit));
+            }
+            decoded
+        }
+        DrawingContainer::Cbor => {
+            let drawing: DrawingFile = ciborium::de::from_reader(&bytes[..])
+                .map_err(|e| format!("Failed to parse CBOR drawing: {}", e))?;
+            serde_json::to_vec_pretty(&drawing).map_err(|e| format!("Failed to serialize drawing: {}", e))?
+        }
+        DrawingContainer::Json => bytes,
+    };
+    Ok(bytes)
+}
+
+/// Decode UTF-8, replacing invalid sequences when `lossy` is set. Returns the text
+/// and how many bytes were replaced.
+fn decode_utf8(bytes: Vec<u8>, lossy: bool) -> Result<(String, usize), String> {
+    let bytes = match String::from_utf8(bytes) {
+        Ok(text) => return Ok((text, 0)),
+        Err(e) if lossy => e.into_bytes(),
+        Err(e) => {
+            return Err(format!(
+                "Failed to read file: invalid UTF-8 at byte {}; the file may be damaged",
+                e.utf8_error().valid_up_to()
+            ))
+        }
+    };
+    
+    let mut replaced = 0;
+    let mut rest = &bytes[..];
+    while let Err(e) = std::str::from_utf8(rest) {
+        let invalid = e.error_len().unwrap_or(rest.len() - e.valid_up_to());
+        replaced += invalid;
+        rest = &rest[e.valid_up_to() + invalid..];
+    }
+    Ok((String::from_utf8_lossy(&bytes).into_owned(), replaced))
+}
+
+/// Text of a drawing file and what had to be overlooked to get it
+struct TextContent {
+    text: String,
+    /// Set when the content is close to the size limit
+    size_warning: Option<String>,
+    /// Invalid UTF-8 bytes replaced by a lossy read
+    replaced_bytes: usize,
+}
+
+/// `read_text_content` refusing files over `limit` bytes, both on disk and after
+/// decompression, and optionally recovering from invalid UTF-8
+fn read_text_limited(path: &Path, limit: Option<u64>, lossy: bool) -> Result<TextContent, String> {
+    read_text_with_progress(path, limit, lossy, &mut |_, _| {})
+}
+
+/// `read_text_limited`, reporting progress on the raw bytes as they're read
+fn read_text_with_progress(
+    path: &Path,
+    limit: Option<u64>,
+    lossy: bool,
+    progress: &mut dyn FnMut(u64, u64),
+) -> Result<TextContent, String> {
+    let bytes = decode_drawing_bytes(path, read_bytes_with_progress(path, limit, progress)?, limit)?;
+    
+    let size = bytes.len() as u64;
+    let size_warning = limit
+        .filter(|&limit| size as f64 > limit as f64 * SIZE_WARNING_RATIO)
+        .map(|limit| format!("{} is {} bytes, close to the {} byte limit", path.display(), size, limit));
+    
+    let (text, replaced_bytes) = decode_utf8(bytes, lossy)?;
+    Ok(TextContent {
+        text: normalize_text(text),
+        size_warning,
+        replaced_bytes,
+    })
+}
+
+/// The configured read size limit in bytes, or none when the caller allows large files
+fn read_size_limit<R: Runtime>(app: &tauri::AppHandle<R>, allow_large: Option<bool>) -> Result<Option<u64>, String> {
+    if allow_large.unwrap_or(false) {
+        return Ok(None);
+    }
+    Ok(Some(load_preferences(app)?.max_file_size_mb * 1024 * 1024))
+}
+
+/// Strip a UTF-8 BOM and turn CRLF into LF. Safe for drawings: JSON strings can't
+/// contain raw line breaks, so only insignificant whitespace changes.
+fn normalize_text(text: String) -> String {
+    let text = match text.strip_prefix('\u{feff}') {
+        Some(stripped) => stripped.to_string(),
+        None => text,
+    };
+    if text.contains('\r') {
+        text.replace("\r\n", "\n")
+    } else {
+        text
+    }
+}
+
+/// Read a drawing file from disk, decompressing gzip and converting CBOR. Encrypted
+/// files fail with a `PASSWORD_REQUIRED` error so the frontend can prompt and retry
+/// with `read_file_encrypted`. Files over the `max_file_size_mb` preference fail with
+/// `FILE_TOO_LARGE` unless `allow_large` is set; files close to it emit a
+/// `file-size-warning` event. With `lossy`, invalid UTF-8 is replaced instead of
+/// failing and a `file-damaged-warning` event reports how many bytes were affected.
+#[tauri::command]
+async fn read_file<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    path: String,
+    allow_large: Option<bool>,
+    lossy: Option<bool>,
+) -> Result<String, String> {
+    run_blocking(app, move |app| {
+        tracing::debug!("Reading {}", path);
+        check_path_scope(&app, &path)?;
+        let content = read_text_limited(Path::new(&path), read_size_limit(&app, allow_large)?, lossy.unwrap_or(false))
+            .inspect_err(|e| tracing::error!("Failed to read {}: {}", path, e))?;
+        if let Some(warning) = content.size_warning {
+            let _ = app.emit("file-size-warning", warning);
+        }
+        if content.replaced_bytes > 0 {
+            let _ = app.emit("file-damaged-warning", DamagedFileWarning {
+                path: path.clone(),
+                replaced_bytes: content.replaced_bytes as u64,
+            });
+        }
+        Ok(content.text)
+    })
+    .await
+}
+
+/// Read a drawing as JSON text whether it's stored as plain `.jamal` JSON or as
+/// gzip (`.jamalz`), going by the content's magic bytes rather than the extension.
+/// Same decoding and size limit as `read_file`, without its warning events.
+#[tauri::command]
+async fn read_drawing_auto<R: Runtime>(app: tauri::AppHandle<R>, path: String) -> Result<String, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        read_text_limited(Path::new(&path), read_size_limit(&app, None)?, false).map(|content| content.text)
+    })
+    .await
+}
+
+/// Read a file's raw bytes, base64 encoded, for callers that handle the format themselves
+#[tauri::command]
+async fn read_file_bytes(app: tauri::AppHandle, path: String, allow_large: Option<bool>) -> Result<String, String> {
+    run_blocking(app, move |app| {
+        use base64::Engine;
+        
+        check_path_scope(&app, &path)?;
+        let bytes = read_bytes_limited(Path::new(&path), read_size_limit(&app, allow_large)?)?;
+        Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+    })
+    .await
+}
+
+/// Read a drawing file like `read_file`, emitting `read-progress` events for every
+/// chunk of the file read from disk
+#[tauri::command]
+async fn read_file_with_progress(
+    app: tauri::AppHandle,
+    path: String,
+    request_id: String,
+    allow_large: Option<bool>,
+) -> Result<String, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        let mut report = |bytes_read, total| {
+            let _ = app.emit("read-progress", ReadProgress {
+                request_id: request_id.clone(),
+                bytes_read,
+                total,
+            });
+        };
+        let content = read_text_with_progress(Path::new(&path), read_size_limit(&app, allow_large)?, false, &mut report)?;
+        if let Some(warning) = content.size_warning {
+            let _ = app.emit("file-size-warning", warning);
+        }
+        Ok(content.text)
+    })
+    .await
+}
+
+/// Download a drawing, save it to `save_path` and add it to recent files
+#[tauri::command]
+async fn import_from_url(app: tauri::AppHandle, url: String, save_path: String) -> Result<DrawingFile, String> {
+    check_path_scope(&app, &save_path)?;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::limited(10))
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+    let mut response = client
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?;
+    if response.status() != reqwest::StatusCode::OK {
+        return Err(format!("Failed to download {}: server returned {}", url, response.status()));
+    }
+    
+    // Same limit as opening a local file, checked as the bytes arrive since the
+    // advertised length can't be trusted
+    let limit = read_size_limit(&app, None)?;
+    let content_length = response.content_length();
+    if let (Some(length), Some(limit)) = (content_length, limit) {
+        if length > limit {
+            return Err(file_too_large_error(Path::new(&url), length, limit));
+        }
+    }
+    let mut bytes = Vec::with_capacity(content_length.unwrap_or(0) as usize);
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| format!("Failed to download {}: {}", url, e))?
+    {
+        bytes.extend_from_slice(&chunk);
+        if let Some(limit) = limit.filter(|&limit| bytes.len() as u64 > limit) {
+            return Err(file_too_large_error(Path::new(&url), bytes.len() as u64, limit));
+        }
+        let _ = app.emit("download-progress", DownloadProgress {
+            url: url.clone(),
+            bytes_received: bytes.len() as u64,
+            content_length,
+        });
+    }
+    
+    let content = decode_drawing_bytes(Path::new(&url), bytes, limit)?;
+    let drawing: DrawingFile = serde_json::from_slice(&content)
+        .map_err(|e| format!("Downloaded file is not a valid drawing: {}", e))?;
+    
+    let target = save_path.clone();
+    run_blocking(app.clone(), move |_| {
+        write_atomic(Path::new(&target), &content, false)
+            .map_err(|e| format!("Failed to save file: {}", e))
+    })
+    .await?;
+    add_recent_file(app, save_path, drawing.name.clone()).await?;
+    
+    Ok(drawing)
+}
+
+/// Sort recent files, keeping pinned entries at the top. Without a sort the stored
+/// order is kept, which is most recently opened first.
+fn sort_recent_files(files: &mut [RecentFile], sort: Option<RecentFilesSort>) {
+    if let Some(sort) = sort {
+        let ascending = sort.ascending.unwrap_or(sort.key == RecentSortKey::Name);
+        match sort.key {
+            RecentSortKey::LastOpened => files.sort_by_key(|f| f.last_opened),
+            RecentSortKey::Name => files.sort_by_cached_key(|f| f.name.to_lowercase()),
+            RecentSortKey::OpenCount => files.sort_by_key(|f| f.open_count),
+            RecentSortKey::FileSize => {
+                // Missing files count as empty
+                files.sort_by_cached_key(|f| fs::metadata(&f.path).map(|m| m.len()).unwrap_or(0))
+            }
+        }
+        if !ascending {
+            files.reverse();
+        }
+    }
+    // Stable, so the order within both groups is kept
+    files.sort_by_key(|f| !f.pinned);
+}
+
+/// Get one page of the recent files list. Sorting applies to the whole list before
+/// it's paged.
+#[tauri::command]
+async fn get_recent_files_page<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    page: u32,
+    page_size: u32,
+    sort: Option<RecentFilesSort>,
+) -> Result<RecentFilesPage, String> {
+    run_blocking(app, move |app| {
+        let path = get_recent_files_path(&app)?;
+        
+        let mut files: Vec<RecentFile> = if path.exists() {
+            let content = fs::read_to_string(&path)
+                .map_err(|e| format!("Failed to read recent files: {}", e))?;
+            serde_json::from_str(&content)
+                .map_err(|e| format!("Failed to parse recent files: {}", e))?
+        } else {
+            Vec::new()
+        };
+        sort_recent_files(&mut files, sort);
+        
+        let page_size = page_size.max(1);
+        let total = files.len() as u32;
+        let mut files: Vec<RecentFile> = files
+            .into_iter()
+            .skip(page.saturating_mul(page_size) as usize)
+            .take(page_size as usize)
+            .collect();
+        
+        attach_tags(&mut files, &read_json_or_default(&get_tags_path(&app)?));
+        
+        Ok(RecentFilesPage {
+            files,
+            total,
+            page,
+            total_pages: total.div_ceil(page_size),
+        })
+    })
+    .await
+}
+
+/// Get the list of recent files, in stored order unless `sort` is given; pinned
+/// entries always come first
+#[tauri::command]
+async fn get_recent_files<R: Runtime>(app: tauri::AppHandle<R>, sort: Option<RecentFilesSort>) -> Result<Vec<RecentFile>, String> {
+    let max = load_preferences(&app)?.max_recent_files;
+    Ok(get_recent_files_page(app, 0, max, sort).await?.files)
+}
+
+/// Get the recent files grouped into today, this week, this month and older, by UTC date
+#[tauri::command]
+async fn get_recent_files_grouped<R: Runtime>(app: tauri::AppHandle<R>) -> Result<GroupedRecentFiles, String> {
+    let mut files = get_recent_files(app, None).await?;
+    files.sort_by(|a, b| b.last_opened.cmp(&a.last_opened));
+    
+    let now = chrono_timestamp();
+    let today = now.div_euclid(86_400);
+    // 1970-01-01 was a Thursday, three days after a Monday
+    let week_start = today - (today + 3).rem_euclid(7);
+    let (year, month, _) = civil_date(now);
+    
+    let mut grouped = GroupedRecentFiles::default();
+    for file in files {
+        let day = file.last_opened.div_euclid(86_400);
+        let (file_year, file_month, _) = civil_date(file.last_opened);
+        let group = if day >= today {
+            &mut grouped.today
+        } else if day >= week_start {
+            &mut grouped.this_week
+        } else if (file_year, file_month) == (year, month) {
+            &mut grouped.this_month
+        } else {
+            &mut grouped.older
+        };
+        group.push(file);
+    }
+    Ok(grouped)
+}
+
+/// Get the recent files grouped by canonical parent directory, groups and the files
+/// in them newest first. Every file is in a group, even when it's the only one.
+#[tauri::command]
+async fn get_recent_files_by_directory<R: Runtime>(app: tauri::AppHandle<R>) -> Result<Vec<RecentFileGroup>, String> {
+    let home = app.path().home_dir().ok().and_then(|home| fs::canonicalize(home).ok());
+    let files = get_recent_files(app, None).await?;
+    
+    let mut groups: BTreeMap<String, Vec<RecentFile>> = BTreeMap::new();
+    for file in files {
+        let directory = Path::new(&canonical_key(&file.path))
+            .parent()
+            .map(|parent| parent.to_string_lossy().to_string())
+            .unwrap_or_default();
+        groups.entry(directory).or_default().push(file);
+    }
+    
+    let mut groups: Vec<RecentFileGroup> = groups
+        .into_iter()
+        .map(|(directory, mut files)| {
+            files.sort_by(|a, b| b.last_opened.cmp(&a.last_opened));
+            let display_name = home
+                .as_ref()
+                .and_then(|home| Path::new(&directory).strip_prefix(home).ok())
+                .map(|relative| Path::new("~").join(relative).to_string_lossy().to_string())
+                .unwrap_or_else(|| directory.clone());
+            RecentFileGroup {
+                last_opened: files.first().map(|f| f.last_opened).unwrap_or(0),
+                directory,
+                display_name,
+                files,
+            }
+        })
+        .collect();
+    groups.sort_by(|a, b| b.last_opened.cmp(&a.last_opened));
+    Ok(groups)
+}
+
+/// Keep only `max` recent files, dropping the oldest unpinned ones
+fn trim_recent_files(files: &mut Vec<RecentFile>, max: usize) {
+    while files.len() > max {
+        match files.iter().rposition(|f| !f.pinned) {
+            Some(oldest) => files.remove(oldest),
+            None => break,
+        };
+    }
+}
+
+/// Add a file to the recent files list
+#[tauri::command]
+async fn add_recent_file<R: Runtime>(app: tauri::AppHandle<R>, path: String, name: String) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        // Recents count as granted, so only paths already in scope may join them
+        check_path_scope(&app, &path)?;
+        // Stored canonical, so different spellings of one file share an entry
+        let path = canonical_key(&path);
+        let mut files = load_recent_files(&app)?;
+        
+        // Remove if already exists, carrying its label, pin and count over
+        let existing = files.iter().position(|f| canonical_key(&f.path) == path).map(|i| files.remove(i));
+        let (label, pinned, open_count) = existing
+            .map(|f| (f.label, f.pinned, f.open_count))
+            .unwrap_or((None, false, 0));
+        
+        // Add to front
+        files.insert(0, RecentFile {
+            path: path.clone(),
+            name,
+            last_opened: chrono_timestamp(),
+            tags: Vec::new(),
+            label,
+            pinned,
+            open_count: open_count.saturating_add(1),
+        });
+        
+        trim_recent_files(&mut files, load_preferences(&app)?.max_recent_files as usize);
+        
+        save_recent_files(&app, &files)?;
+        publish_event(&app, DrawingEvent::RecentFileAdded(path));
+        Ok(())
+    })
+    .await
+}
+
+/// Pin or unpin a recent file
+#[tauri::command]
+async fn set_recent_file_pinned<R: Runtime>(app: tauri::AppHandle<R>, path: String, pinned: bool) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let key = canonical_key(&path);
+        let mut files = load_recent_files(&app)?;
+        let file = files
+            .iter_mut()
+            .find(|f| canonical_key(&f.path) == key)
+            .ok_or_else(|| format!("Not in recent files: {}", path))?;
+        file.pinned = pinned;
+        save_recent_files(&app, &files)
+    })
+    .await
+}
+
+/// Update a recent file's `last_opened`, and its name when given, keeping its position
+/// in the list. For autosaves, which shouldn't reorder recents the way opening a file
+/// with `add_recent_file` does. Paths not in the list are left out.
+#[tauri::command]
+async fn touch_recent_file<R: Runtime>(app: tauri::AppHandle<R>, path: String, name: Option<String>) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let key = canonical_key(&path);
+        let mut files = load_recent_files(&app)?;
+        let file = match files.iter_mut().find(|f| canonical_key(&f.path) == key) {
+            Some(file) => file,
+            None => return Ok(()),
+        };
+        
+        file.last_opened = chrono_timestamp();
+        if let Some(name) = name {
+            file.name = name;
+        }
+        save_recent_files(&app, &files)
+    })
+    .await
+}
+
+/// Remove a file from recent files list
+#[tauri::command]
+async fn remove_recent_file<R: Runtime>(app: tauri::AppHandle<R>, path: String) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let recent_path = get_recent_files_path(&app)?;
+        
+        if !recent_path.exists() {
+            return Ok(());
+        }
+        
+        let mut files = load_recent_files(&app)?;
+        let key = canonical_key(&path);
+        files.retain(|f| f.path != path && canonical_key(&f.path) != key);
+        
+        save_recent_files(&app, &files)?;
+        publish_event(&app, DrawingEvent::RecentFileRemoved(path));
+        Ok(())
+    })
+    .await
+}
+
+/// Remove the recent files matching a predicate with a single write and event,
+/// returning the removed paths
+fn remove_recent_files_where<R: Runtime>(
+    app: &tauri::AppHandle<R>,
+    matches: impl Fn(&RecentFile) -> bool,
+) -> Result<Vec<String>, String> {
+    let _stores = lock_json_stores();
+    let (removed, kept): (Vec<RecentFile>, Vec<RecentFile>) = load_recent_files(app)?.into_iter().partition(matches);
+    let removed: Vec<String> = removed.into_iter().map(|f| f.path).collect();
+    if !removed.is_empty() {
+        save_recent_files(app, &kept)?;
+        publish_event(app, DrawingEvent::RecentFilesRemoved(removed.clone()));
+    }
+    Ok(removed)
+}
+
+/// Match a path against a glob with `/` separators: `*` and `?` stay within one
+/// path segment, `**` also crosses them
+fn glob_matches(pattern: &[char], path: &[char]) -> bool {
+    match pattern.first() {
+        None => path.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => (0..=path.len()).any(|i| glob_matches(&pattern[2..], &path[i..])),
+        Some('*') => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '/')
+            .any(|i| glob_matches(&pattern[1..], &path[i..])),
+        Some('?') => path.first().is_some_and(|&c| c != '/') && glob_matches(&pattern[1..], &path[1..]),
+        Some(c) => path.first() == Some(c) && glob_matches(&pattern[1..], &path[1..]),
+    }
+}
+
+/// Remove several recent files with one write, returning the stored paths that were
+/// in the list
+#[tauri::command]
+async fn remove_recent_files<R: Runtime>(app: tauri::AppHandle<R>, paths: Vec<String>) -> Result<Vec<String>, String> {
+    run_blocking(app, move |app| {
+        let keys: std::collections::HashSet<String> = paths.iter().map(|path| canonical_key(path)).collect();
+        remove_recent_files_where(&app, |f| paths.contains(&f.path) || keys.contains(&canonical_key(&f.path)))
+    })
+    .await
+}
+
+/// Remove every recent file under a folder, or matching a glob when `pattern` contains
+/// `*` or `?` (e.g. `/projects/old/**/*.jamal`), with one write. Returns the removed paths.
+#[tauri::command]
+async fn remove_recent_files_matching<R: Runtime>(app: tauri::AppHandle<R>, pattern: String) -> Result<Vec<String>, String> {
+    run_blocking(app, move |app| {
+        if pattern.contains(['*', '?']) {
+            let unify = |path: &str| -> Vec<char> { path.replace('\\', "/").chars().collect() };
+            let glob = unify(&pattern);
+            remove_recent_files_where(&app, |f| {
+                glob_matches(&glob, &unify(&f.path)) || glob_matches(&glob, &unify(&canonical_key(&f.path)))
+            })
+        } else {
+            let folder = PathBuf::from(canonical_key(&pattern));
+            remove_recent_files_where(&app, |f| {
+                Path::new(&f.path).starts_with(&pattern) || Path::new(&canonical_key(&f.path)).starts_with(&folder)
+            })
+        }
+    })
+    .await
+}
+
+/// Clear the recent files, keeping pinned entries unless `include_pinned` is set.
+/// Returns how many entries were removed
+#[tauri::command]
+async fn clear_recent_files<R: Runtime>(app: tauri::AppHandle<R>, include_pinned: Option<bool>) -> Result<u32, String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let include_pinned = include_pinned.unwrap_or(false);
+        let (removed, kept): (Vec<RecentFile>, Vec<RecentFile>) = load_recent_files(&app)?
+            .into_iter()
+            .partition(|f| include_pinned || !f.pinned);
+        
+        if !kept.is_empty() {
+            if !removed.is_empty() {
+                save_recent_files(&app, &kept)?;
+                publish_event(&app, DrawingEvent::RecentFilesRemoved(removed.iter().map(|f| f.path.clone()).collect()));
+            }
+            return Ok(removed.len() as u32);
+        }
+        
+        let recent_path = get_recent_files_path(&app)?;
+        if recent_path.exists() {
+            fs::remove_file(&recent_path)
+                .map_err(|e| format!("Failed to clear recent files: {}", e))?;
+        }
+        
+        notify_recent_files_changed(&app, &[]);
+        publish_event(&app, DrawingEvent::RecentFilesCleared);
+        Ok(removed.len() as u32)
+    })
+    .await
+}
+
+/// File in a shared base directory holding its recent files as relative paths
+const SHARED_RECENT_FILES: &str = ".jamal-recent.json";
+
+/// Copies of the recent files with paths relative to `base`, forward slashed so they
+/// work on every platform the list may travel to. Entries without a relative route,
+/// e.g. on another drive on Windows, are left out
+fn relativized_recent_files(files: &[RecentFile], base: &Path) -> Vec<RecentFile> {
+    files
+        .iter()
+        .filter(|file| Path::new(&file.path).is_absolute())
+        .filter_map(|file| {
+            let relative = pathdiff::diff_paths(&file.path, base)?;
+            Some(RecentFile {
+                path: relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/"),
+                ..file.clone()
+            })
+        })
+        .collect()
+}
+
+/// Add shared entries, resolved to absolute paths by `resolve`, after the recent files
+/// not already listed. Entries `resolve` rejects are skipped. Returns how many were added
+fn merge_shared_recent_files(
+    files: &mut Vec<RecentFile>,
+    shared: Vec<RecentFile>,
+    resolve: impl Fn(&str) -> Option<PathBuf>,
+) -> usize {
+    let mut seen: std::collections::HashSet<String> = files.iter().map(|f| canonical_key(&f.path)).collect();
+    let before = files.len();
+    for file in shared {
+        if Path::new(&file.path).is_absolute() {
+            continue;
+        }
+        if let Some(resolved) = resolve(&file.path) {
+            let path = resolved.to_string_lossy().to_string();
+            if seen.insert(canonical_key(&path)) {
+                files.push(RecentFile { path, ..file });
+            }
+        }
+    }
+    files.len() - before
+}
+
+/// Write the recent files, relative to `base_dir`, to a shared file in `base_dir` so the
+/// list can be used from a checkout at another location. The list itself keeps its
+/// absolute paths. Returns how many entries were written
+#[tauri::command]
+async fn relativize_recent_files<R: Runtime>(app: tauri::AppHandle<R>, base_dir: String) -> Result<u32, String> {
+    run_blocking(app, move |app| {
+        let base = check_path_scope(&app, &base_dir)?;
+        let files = {
+            let _stores = lock_json_stores();
+            load_recent_files(&app)?
+        };
+        
+        let shared = relativized_recent_files(&files, &base);
+        write_json(&base.join(SHARED_RECENT_FILES), &shared)?;
+        Ok(shared.len() as u32)
+    })
+    .await
+}
+
+/// Add the recent files shared in `base_dir` by `relativize_recent_files` to this
+/// machine's list, resolved under `base_dir`. Entries that would land outside the
+/// granted scope are skipped
+#[tauri::command]
+async fn resolve_recent_files<R: Runtime>(app: tauri::AppHandle<R>, base_dir: String) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let base = check_path_scope(&app, &base_dir)?;
+        let shared_path = base.join(SHARED_RECENT_FILES);
+        let shared: Vec<RecentFile> = serde_json::from_str(
+            &fs::read_to_string(&shared_path).map_err(|e| format!("Failed to read shared recent files: {}", e))?,
+        )
+        .map_err(|e| format!("Failed to parse shared recent files: {}", e))?;
+        
+        let _stores = lock_json_stores();
+        let mut files = load_recent_files(&app)?;
+        let added = merge_shared_recent_files(&mut files, shared, |relative| {
+            check_path_scope(&app, &base.join(relative).to_string_lossy()).ok()
+        });
+        if added > 0 {
+            trim_recent_files(&mut files, load_preferences(&app)?.max_recent_files as usize);
+            save_recent_files(&app, &files)?;
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Replace the tags of a drawing
+#[tauri::command]
+async fn set_tags<R: Runtime>(app: tauri::AppHandle<R>, path: String, tags: Vec<String>) -> Result<Vec<String>, String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let tags_path = get_tags_path(&app)?;
+        let mut store: BTreeMap<String, Vec<String>> = read_json_or_default(&tags_path);
+        
+        let tags = store_tags(&mut store, canonical_key(&path), tags);
+        write_json(&tags_path, &store)?;
+        Ok(tags)
+    })
+    .await
+}
+
+/// Get the tags of a drawing
+#[tauri::command]
+async fn get_tags<R: Runtime>(app: tauri::AppHandle<R>, path: String) -> Result<Vec<String>, String> {
+    run_blocking(app, move |app| {
+        let store: BTreeMap<String, Vec<String>> = read_json_or_default(&get_tags_path(&app)?);
+        Ok(store.get(&canonical_key(&path)).cloned().unwrap_or_default())
+    })
+    .await
+}
+
+/// List every tag in use, sorted
+#[tauri::command]
+async fn list_all_tags<R: Runtime>(app: tauri::AppHandle<R>) -> Result<Vec<String>, String> {
+    run_blocking(app, move |app| {
+        let store: BTreeMap<String, Vec<String>> = read_json_or_default(&get_tags_path(&app)?);
+        
+        let mut all = normalize_tags(store.into_values().flatten().collect());
+        all.sort();
+        Ok(all)
+    })
+    .await
+}
+
+/// Find the paths of all drawings carrying a tag
+#[tauri::command]
+async fn find_by_tag<R: Runtime>(app: tauri::AppHandle<R>, tag: String) -> Result<Vec<String>, String> {
+    run_blocking(app, move |app| {
+        let store: BTreeMap<String, Vec<String>> = read_json_or_default(&get_tags_path(&app)?);
+        
+        Ok(store
+            .into_iter()
+            .filter(|(_, tags)| tags.iter().any(|t| tag_matches(t, &tag)))
+            .map(|(path, _)| path)
+            .collect())
+    })
+    .await
+}
+
+/// Add one tag to a drawing
+#[tauri::command]
+async fn add_tag<R: Runtime>(app: tauri::AppHandle<R>, path: String, tag: String) -> Result<Vec<String>, String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let tags_path = get_tags_path(&app)?;
+        let mut store: BTreeMap<String, Vec<String>> = read_json_or_default(&tags_path);
+        
+        let key = canonical_key(&path);
+        let mut tags = store.get(&key).cloned().unwrap_or_default();
+        tags.push(tag);
+        let tags = store_tags(&mut store, key, tags);
+        write_json(&tags_path, &store)?;
+        Ok(tags)
+    })
+    .await
+}
+
+/// Remove one tag from a drawing; the drawing itself stays in recents
+#[tauri::command]
+async fn remove_tag<R: Runtime>(app: tauri::AppHandle<R>, path: String, tag: String) -> Result<Vec<String>, String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let tags_path = get_tags_path(&app)?;
+        let mut store: BTreeMap<String, Vec<String>> = read_json_or_default(&tags_path);
+        
+        let key = canonical_key(&path);
+        let mut tags = store.get(&key).cloned().unwrap_or_default();
+        tags.retain(|t| !tag_matches(t, &tag));
+        let tags = store_tags(&mut store, key, tags);
+        write_json(&tags_path, &store)?;
+        Ok(tags)
+    })
+    .await
+}
+
+/// Get the recent files carrying a tag
+#[tauri::command]
+async fn get_recent_files_by_tag<R: Runtime>(app: tauri::AppHandle<R>, tag: String) -> Result<Vec<RecentFile>, String> {
+    Ok(filter_by_tag(get_recent_files(app, None).await?, &tag))
+}
+
+/// Report tag entries whose files no longer exist, optionally dropping them
+#[tauri::command]
+async fn reconcile_tags<R: Runtime>(app: tauri::AppHandle<R>, prune: Option<bool>) -> Result<Vec<String>, String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let tags_path = get_tags_path(&app)?;
+        let mut store: BTreeMap<String, Vec<String>> = read_json_or_default(&tags_path);
+        
+        let missing: Vec<String> = store
+            .keys()
+            .filter(|path| !PathBuf::from(path).exists())
+            .cloned()
+            .collect();
+        
+        if prune.unwrap_or(false) && !missing.is_empty() {
+            store.retain(|path, _| !missing.contains(path));
+            write_json(&tags_path, &store)?;
+        }
+        
+        Ok(missing)
+    })
+    .await
+}
+
+/// Classify a dropped path, returning the drawing name when it can be opened.
+/// Compressed and CBOR drawings are read like `read_file` would, within `limit`
+fn classify_dropped_file(path: &str, limit: Option<u64>) -> (DroppedFileStatus, Option<String>, Option<String>) {
+    let path_buf = PathBuf::from(path);
+    if !path_buf.is_file() {
+        return (DroppedFileStatus::Invalid, None, Some("File does not exist".to_string()));
+    }
+    
+    let is_jamal = path_buf
+        .extension()
+        .map(|ext| ext.eq_ignore_ascii_case("jamal") || ext.eq_ignore_ascii_case("jamalz"))
+        .unwrap_or(false);
+    let file_stem = || path_buf.file_stem().map(|stem| stem.to_string_lossy().to_string());
+    
+    let bytes = match read_bytes_limited(&path_buf, limit).and_then(|bytes| decode_drawing_bytes(&path_buf, bytes, limit)) {
+        Ok(bytes) => bytes,
+        // The password is asked for when it's opened
+        Err(e) if e.starts_with(ERR_PASSWORD_REQUIRED) => return (DroppedFileStatus::Openable, file_stem(), None),
+        Err(e) => return (DroppedFileStatus::Invalid, None, Some(e)),
+    };
+    
+    let value = match serde_json::from_slice::<serde_json::Value>(&bytes) {
+        Ok(value) => value,
+        Err(e) if is_jamal => {
+            return (DroppedFileStatus::Invalid, None, Some(format!("Failed to parse drawing: {}", e)))
+        }
+        Err(_) => return (DroppedFileStatus::NotADrawing, None, None),
+    };
+    
+    let is_drawing = value.get("version").map(|v| v.is_u64()).unwrap_or(false)
+        && value.get("store").is_some();
+    if !is_drawing {
+        return if is_jamal {
+            (DroppedFileStatus::Invalid, None, Some("Missing version or store field".to_string()))
+        } else {
+            (DroppedFileStatus::NotADrawing, None, None)
+        };
+    }
+    
+    let name = value
+        .get("name")
+        .and_then(|n| n.as_str())
+        .map(|n| n.to_string())
+        .or_else(file_stem);
+    
+    (DroppedFileStatus::Openable, name, None)
+}
+
+/// Validate files dropped onto the window and register the openable ones as recent
+#[tauri::command]
+async fn handle_dropped_files(app: tauri::AppHandle, paths: Vec<String>) -> Vec<DroppedFileResult> {
+    let mut results = Vec::with_capacity(paths.len());
+    let limit = read_size_limit(&app, None);
+    
+    for path in paths {
+        let (status, name, mut error) = match check_path_scope(&app, &path).and(limit.clone()) {
+            Ok(limit) => classify_dropped_file(&path, limit),
+            Err(e) => (DroppedFileStatus::Invalid, None, Some(e)),
+        };
+        
+        if status == DroppedFileStatus::Openable {
+            let recent_name = name.clone().unwrap_or_default();
+            if let Err(e) = add_recent_file(app.clone(), path.clone(), recent_name).await {
+                error = Some(e);
+            }
+        }
+        
+        results.push(DroppedFileResult { path, status, name, error });
+    }
+    
+    results
+}
+
+/// Add a drawing to favorites, or update its name if it's already there
+#[tauri::command]
+async fn add_favorite<R: Runtime>(app: tauri::AppHandle<R>, path: String, name: String) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        check_path_scope(&app, &path)?;
+        let favorites_path = get_favorites_path(&app)?;
+        let mut favorites: Vec<Favorite> = read_json_or_default(&favorites_path);
+        
+        let key = canonical_key(&path);
+        match favorites.iter_mut().find(|f| canonical_key(&f.path) == key) {
+            Some(existing) => existing.name = name,
+            None => favorites.push(Favorite {
+                path,
+                name,
+                added_at: chrono_timestamp(),
+                label: None,
+            }),
+        }
+        
+        write_json(&favorites_path, &favorites)
+    })
+    .await
+}
+
+/// Remove a drawing from favorites
+#[tauri::command]
+async fn remove_favorite<R: Runtime>(app: tauri::AppHandle<R>, path: String) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let favorites_path = get_favorites_path(&app)?;
+        let mut favorites: Vec<Favorite> = read_json_or_default(&favorites_path);
+        
+        let key = canonical_key(&path);
+        favorites.retain(|f| canonical_key(&f.path) != key);
+        
+        write_json(&favorites_path, &favorites)
+    })
+    .await
+}
+
+/// Get the favorites in their user-defined order, with live file info
+#[tauri::command]
+async fn get_favorites<R: Runtime>(app: tauri::AppHandle<R>) -> Result<Vec<FavoriteFile>, String> {
+    run_blocking(app, move |app| {
+        let favorites: Vec<Favorite> = read_json_or_default(&get_favorites_path(&app)?);
+        
+        Ok(favorites
+            .into_iter()
+            .map(|favorite| {
+                let metadata = fs::metadata(&favorite.path).ok();
+                FavoriteFile {
+                    exists: metadata.is_some(),
+                    size: metadata.as_ref().map(|m| m.len()),
+                    modified_at: metadata
+                        .and_then(|m| m.modified().ok())
+                        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs() as i64),
+                    favorite,
+                }
+            })
+            .collect())
+    })
+    .await
+}
+
+/// Reorder favorites; favorites missing from `paths` keep their relative order at the end
+#[tauri::command]
+async fn reorder_favorites<R: Runtime>(app: tauri::AppHandle<R>, paths: Vec<String>) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let favorites_path = get_favorites_path(&app)?;
+        let mut remaining: Vec<Favorite> = read_json_or_default(&favorites_path);
+        
+        let mut ordered = Vec::with_capacity(remaining.len());
+        for path in paths {
+            let key = canonical_key(&path);
+            if let Some(index) = remaining.iter().position(|f| canonical_key(&f.path) == key) {
+                ordered.push(remaining.remove(index));
+            }
+        }
+        ordered.append(&mut remaining);
+        
+        write_json(&favorites_path, &ordered)
+    })
+    .await
+}
+
+/// Get the user preferences
+#[tauri::command]
+async fn get_preferences(app: tauri::AppHandle) -> Result<Preferences, String> {
+    run_blocking(app, move |app| {
+        load_preferences(&app)
+    })
+    .await
+}
+
+/// Validate and persist the user preferences. The post-save hook and the backup
+/// directory are kept as stored; they have their own commands.
+#[tauri::command]
+async fn set_preferences(app: tauri::AppHandle, mut preferences: Preferences) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let stored = load_preferences(&app)?;
+        preferences.post_save_hook = stored.post_save_hook;
+        preferences.backup_directory = stored.backup_directory;
+        validate_preferences(&preferences)?;
+        write_json(&get_preferences_path(&app)?, &preferences)?;
+        publish_event(&app, DrawingEvent::PreferencesChanged);
+        Ok(())
+    })
+    .await
+}
+
+/// Set or clear the post-save hook. A new hook runs arbitrary commands, so it's only
+/// stored once the user allows it in a native prompt the webview can't answer
+/// itself. Returns whether the change was made.
+#[tauri::command]
+async fn set_post_save_hook(app: tauri::AppHandle, hook: Option<PostSaveHook>) -> Result<bool, String> {
+    use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+    
+    if let Some(hook) = &hook {
+        if hook.command.trim().is_empty() {
+            return Err("Post-save hook command must not be empty".to_string());
+        }
+        
+        let mut command_line = vec![hook.command.clone()];
+        command_line.extend(hook.args.iter().cloned());
+        command_line.push("<saved file>".to_string());
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        app.dialog()
+            .message(format!(
+                "Allow Jamal to run this command after every save?\n\n{}{}",
+                command_line.join(" "),
+                hook.working_dir
+                    .as_ref()
+                    .map(|dir| format!("\n\nin {}", dir))
+                    .unwrap_or_default()
+            ))
+            .title("Post-save hook")
+            .kind(MessageDialogKind::Warning)
+            .buttons(MessageDialogButtons::OkCancelCustom("Allow".to_string(), "Deny".to_string()))
+            .show(move |allowed| {
+                let _ = tx.send(allowed);
+            });
+        if !rx.await.unwrap_or(false) {
+            return Ok(false);
+        }
+    }
+    
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let mut prefs = load_preferences(&app)?;
+        prefs.post_save_hook = hook;
+        write_json(&get_preferences_path(&app)?, &prefs)?;
+        publish_event(&app, DrawingEvent::PreferencesChanged);
+        Ok(true)
+    })
+    .await
+}
+
+/// Remember where the user left a document
+#[tauri::command]
+async fn save_view_state(app: tauri::AppHandle, path: String, view_state: ViewState) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let view_states_path = get_view_states_path(&app)?;
+        let mut view_states: BTreeMap<String, ViewStateEntry> = read_json_or_default(&view_states_path);
+        
+        view_states.insert(canonical_key(&path), ViewStateEntry {
+            state: view_state,
+            updated_at: chrono_timestamp(),
+        });
+        
+        // Drop the least recently updated entries beyond the cap
+        if view_states.len() > MAX_VIEW_STATES {
+            let mut by_age: Vec<(String, i64)> = view_states
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.updated_at))
+                .collect();
+            by_age.sort_by_key(|(_, updated_at)| *updated_at);
+            for (key, _) in by_age.into_iter().take(view_states.len() - MAX_VIEW_STATES) {
+                view_states.remove(&key);
+            }
+        }
+        
+        write_json(&view_states_path, &view_states)
+    })
+    .await
+}
+
+/// Get the saved view state of a document, if any
+#[tauri::command]
+async fn get_view_state(app: tauri::AppHandle, path: String) -> Result<Option<ViewState>, String> {
+    run_blocking(app, move |app| {
+        let view_states: BTreeMap<String, ViewStateEntry> = read_json_or_default(&get_view_states_path(&app)?);
+        Ok(view_states.get(&canonical_key(&path)).map(|entry| entry.state.clone()))
+    })
+    .await
+}
+
+/// Point the per-file entries (tags, view state, snapshots, favorites, recents) at a
+/// renamed drawing and move its change logs along
+fn relocate_file_entries<R: Runtime>(app: &tauri::AppHandle<R>, old_path: &str, new_path: &str, new_name: &str) -> Result<(), String> {
+    let _stores = lock_json_stores();
+    let old_key = canonical_key(old_path);
+    let new_key = canonical_key(new_path);
+    
+    let tags_path = get_tags_path(app)?;
+    let mut tags: BTreeMap<String, Vec<String>> = read_json_or_default(&tags_path);
+    if move_store_entry(&mut tags, &old_key, &new_key) {
+        write_json(&tags_path, &tags)?;
+    }
+    
+    let view_states_path = get_view_states_path(app)?;
+    let mut view_states: BTreeMap<String, ViewStateEntry> = read_json_or_default(&view_states_path);
+    if move_store_entry(&mut view_states, &old_key, &new_key) {
+        write_json(&view_states_path, &view_states)?;
+    }
+    
+    let snapshots_path = get_snapshots_index_path(app)?;
+    let mut snapshots: BTreeMap<String, Vec<SnapshotInfo>> = read_json_or_default(&snapshots_path);
+    if move_store_entry(&mut snapshots, &old_key, &new_key) {
+        write_json(&snapshots_path, &snapshots)?;
+    }
+    
+    let favorites_path = get_favorites_path(app)?;
+    let mut favorites: Vec<Favorite> = read_json_or_default(&favorites_path);
+    let mut favorites_changed = false;
+    for favorite in favorites.iter_mut().filter(|f| canonical_key(&f.path) == old_key) {
+        favorite.path = new_path.to_string();
+        favorite.name = new_name.to_string();
+        favorites_changed = true;
+    }
+    if favorites_changed {
+        write_json(&favorites_path, &favorites)?;
+    }
+    
+    let mut recent = load_recent_files(app)?;
+    if relocate_recent_entries(&mut recent, &old_key, new_path, new_name) {
+        save_recent_files(app, &recent)?;
+    }
+    
+    relocate_change_logs(old_path, new_path)
+}
+
+/// Move the entry of a store keyed by canonical path to a new key. Returns whether
+/// there was one
+fn move_store_entry<T>(store: &mut BTreeMap<String, T>, old_key: &str, new_key: &str) -> bool {
+    match store.remove(old_key) {
+        Some(entry) => {
+            store.insert(new_key.to_string(), entry);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Point the recent files at `old_key` to their new path and name. Entries are
+/// compared by canonical key, since the list may spell the path differently.
+/// Returns whether any changed
+fn relocate_recent_entries(recent: &mut [RecentFile], old_key: &str, new_path: &str, new_name: &str) -> bool {
+    let mut changed = false;
+    for file in recent.iter_mut().filter(|f| canonical_key(&f.path) == old_key) {
+        file.path = new_path.to_string();
+        file.name = new_name.to_string();
+        changed = true;
+    }
+    changed
+}
+
+/// Longest file name most file systems accept, in bytes
+const MAX_FILENAME_BYTES: usize = 255;
+
+/// Device names Windows reserves regardless of extension
+const RESERVED_FILENAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "CONIN$", "CONOUT$",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Characters no file name may contain on at least one supported platform
+fn is_illegal_filename_char(c: char) -> bool {
+    matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*') || c.is_control()
+}
+
+/// Make `name` a valid file name on Windows, macOS and Linux, recording each change
+fn sanitize_file_name(name: &str, replacement: &str) -> SanitizedFilename {
+    let mut changes = Vec::new();
+    // A replacement that is itself illegal would defeat the purpose
+    let replacement = if replacement.chars().any(is_illegal_filename_char) { "" } else { replacement };
+    
+    let mut illegal: Vec<char> = name.chars().filter(|&c| is_illegal_filename_char(c)).collect();
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| if is_illegal_filename_char(c) { replacement.to_string() } else { c.to_string() })
+        .collect();
+    if !illegal.is_empty() {
+        illegal.sort_unstable();
+        illegal.dedup();
+        let shown: Vec<String> = illegal.iter().map(|c| format!("{:?}", c)).collect();
+        changes.push(format!("Replaced characters not allowed in file names: {}", shown.join(" ")));
+    }
+    
+    let trim = |s: &str| s.trim_start().trim_end_matches(['.', ' ']).to_string();
+    if trim(&sanitized) != sanitized {
+        sanitized = trim(&sanitized);
+        changes.push("Removed leading spaces and trailing dots or spaces".to_string());
+    }
+    
+    if sanitized.len() > MAX_FILENAME_BYTES {
+        // Keep a short extension and shorten the part before it
+        let (stem, extension) = match sanitized.rfind('.') {
+            Some(dot) if dot > 0 && sanitized.len() - dot <= 16 => sanitized.split_at(dot),
+            _ => (sanitized.as_str(), ""),
+        };
+        let mut end = MAX_FILENAME_BYTES - extension.len();
+        while !stem.is_char_boundary(end) {
+            end -= 1;
+        }
+        sanitized = format!("{}{}", trim(&stem[..end]), extension);
+        changes.push(format!("Shortened to at most {} bytes", MAX_FILENAME_BYTES));
+    }
+    
+    let stem = sanitized.split('.').next().unwrap_or("").to_string();
+    if RESERVED_FILENAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem.trim_end())) {
+        sanitized.insert(stem.len(), '_');
+        changes.push(format!("\"{}\" is a reserved name on Windows", stem));
+    }
+    
+    if sanitized.is_empty() {
+        sanitized = "Untitled".to_string();
+        changes.push("Name was empty after cleaning up".to_string());
+    }
+    
+    SanitizedFilename { name: sanitized, changes }
+}
+
+/// Turn a document name into a file name that's valid everywhere, explaining any changes
+#[tauri::command]
+async fn sanitize_filename(name: String, replacement: Option<String>) -> Result<SanitizedFilename, String> {
+    Ok(sanitize_file_name(&name, replacement.as_deref().unwrap_or("_")))
+}
+
+/// Split "Name 2" or "Name (2)" into the base name and its number
+fn split_numbered_name(stem: &str) -> (&str, Option<u32>) {
+    let parse = |digits: &str| digits.parse::<u32>().ok().filter(|&n| n >= 2 && !digits.starts_with('0'));
+    if let Some(inner) = stem.strip_suffix(')') {
+        if let Some((base, digits)) = inner.rsplit_once(" (") {
+            if let Some(n) = parse(digits) {
+                return (base, Some(n));
+            }
+        }
+    }
+    if let Some((base, digits)) = stem.rsplit_once(' ') {
+        if let Some(n) = parse(digits) {
+            return (base, Some(n));
+        }
+    }
+    (stem, None)
+}
+
+/// `base` with the number suffix the platform's file manager would use
+fn numbered_name(base: &str, n: u32) -> String {
+    match n {
+        1 => base.to_string(),
+        _ if cfg!(target_os = "macos") => format!("{} {}", base, n),
+        _ => format!("{} ({})", base, n),
+    }
+}
+
+/// The first of `base`, `base 2`, `base 3`, … (starting at number `first`) not taken
+/// in `dir`, counting both "Name 2" and "Name (2)" styles as taken. Nothing is
+/// reserved, so writers still need no-clobber semantics.
+fn next_free_path(dir: &Path, base: &str, extension: &str, first: u32) -> Result<PathBuf, String> {
+    let extension = extension.trim_start_matches('.');
+    let mut taken = std::collections::HashSet::new();
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    for entry in entries.flatten() {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let path = Path::new(&file_name);
+        let matches_extension = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().eq_ignore_ascii_case(extension))
+            .unwrap_or(extension.is_empty());
+        if !matches_extension {
+            continue;
+        }
+        let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        match split_numbered_name(&stem) {
+            (name, Some(n)) if name == base => {
+                taken.insert(n);
+            }
+            _ if stem == base => {
+                taken.insert(1);
+            }
+            _ => {}
+        }
+    }
+    
+    let n = (first.max(1)..).find(|n| !taken.contains(n)).unwrap_or(first);
+    let mut file_name = numbered_name(base, n);
+    if !extension.is_empty() {
+        file_name = format!("{}.{}", file_name, extension);
+    }
+    Ok(dir.join(file_name))
+}
+
+/// Full path of the first unused `base_name`, `base_name 2`, … in `dir`
+#[tauri::command]
+async fn next_available_name(app: tauri::AppHandle, dir: String, base_name: String, extension: String) -> Result<String, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &dir)?;
+        Ok(next_free_path(Path::new(&dir), &base_name, &extension, 1)?
+            .to_string_lossy()
+            .to_string())
+    })
+    .await
+}
+
+/// Path for a duplicate or "save a copy" of `path`, next to it: a copy of
+/// "Plan 2.jamal" becomes the first free of "Plan 3.jamal", "Plan 4.jamal", …
+#[tauri::command]
+async fn next_available_copy_path(app: tauri::AppHandle, path: String) -> Result<String, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        Ok(copy_path(Path::new(&path))?.to_string_lossy().to_string())
+    })
+    .await
+}
+
+/// The path behind `next_available_copy_path`. A trailing "(n)" is always a copy
+/// number, while a bare " n" only is when the unnumbered file is next to it, so a
+/// copy of "Report 2024.jamal" doesn't become "Report 2.jamal"
+fn copy_path(path: &Path) -> Result<PathBuf, String> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let extension = path.extension().map(|e| e.to_string_lossy().to_string()).unwrap_or_default();
+    let base = match split_numbered_name(&stem) {
+        (base, Some(_)) if stem.ends_with(')') => base,
+        (base, Some(_)) => {
+            let unnumbered = if extension.is_empty() { base.to_string() } else { format!("{}.{}", base, extension) };
+            if dir.join(unnumbered).exists() { base } else { &stem }
+        }
+        (base, None) => base,
+    };
+    next_free_path(dir, base, &extension, 2)
+}
+
+/// Rename a single drawing: rewrite its name field, in the container the file is in,
+/// and move it to a matching filename without replacing another file
+fn rename_drawing(path: &str, pattern: &regex::Regex, replacement: &str) -> Result<Option<(String, String)>, String> {
+    let old_path = PathBuf::from(path);
+    let (mut drawing, container) = read_drawing_in(&old_path)?;
+    if !pattern.is_match(&drawing.name) {
+        return Ok(None);
+    }
+    
+    let new_name = pattern.replace_all(&drawing.name, replacement).trim().to_string();
+    if new_name.is_empty() {
+        return Err(format!("Invalid drawing name: {:?}", new_name));
+    }
+    
+    // Appended rather than set with `set_extension`, which would cut a dotted name
+    // like "Report v1.2" at its last dot
+    let extension = old_path
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_string())
+        .unwrap_or_else(|| "jamal".to_string());
+    let new_path = old_path.with_file_name(format!("{}.{}", sanitize_file_name(&new_name, "_").name, extension));
+    drawing.name = new_name.clone();
+    let content = encode_drawing(&drawing, container)?;
+    
+    if new_path == old_path {
+        write_atomic(&old_path, &content, false)?;
+    } else {
+        // A name differing only in spelling (e.g. case on a case-insensitive file
+        // system) is the same file and may be renamed onto
+        let same_file = canonical_key(path) == canonical_key(&new_path.to_string_lossy());
+        let move_file = |from: &Path, to: &Path| {
+            if same_file {
+                fs::rename(from, to).map_err(|e| format!("Failed to rename file: {}", e))
+            } else {
+                rename_no_clobber(from, to)
+            }
+        };
+        move_file(&old_path, &new_path)?;
+        if let Err(e) = write_atomic(&new_path, &content, false) {
+            // Put the untouched file back
+            let _ = move_file(&new_path, &old_path);
+            return Err(e);
+        }
+    }
+    
+    Ok(Some((new_path.to_string_lossy().to_string(), new_name)))
+}
+
+/// Move a file without ever replacing one at `to`. Hard-linking fails when `to`
+/// exists, so the check can't race; file systems without hard links (FAT, some
+/// network shares) get an exclusively created placeholder at `to` instead, which
+/// the rename then replaces.
+fn rename_no_clobber(from: &Path, to: &Path) -> Result<(), String> {
+    use std::io::ErrorKind;
+    
+    let exists = || format!("A file already exists at {}", to.display());
+    let undo = |e: std::io::Error| {
+        let _ = fs::remove_file(to);
+        format!("Failed to rename file: {}", e)
+    };
+    match fs::hard_link(from, to) {
+        Ok(()) => fs::remove_file(from).map_err(undo),
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(exists()),
+        Err(_) => {
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(to)
+                .map_err(|e| match e.kind() {
+                    ErrorKind::AlreadyExists => exists(),
+                    _ => format!("Failed to rename file: {}", e),
+                })?;
+            fs::rename(from, to).map_err(undo)
+        }
+    }
+}
+
+/// Move a file to a new path, taking its recent entry, tags, view state, snapshots
+/// and favorite along. Refuses to replace an existing file
+#[tauri::command]
+async fn rename_file<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    queue: tauri::State<'_, WriteQueue>,
+    path: String,
+    new_path: String,
+) -> Result<(), String> {
+    let _turns = queue.wait_turns(&[&path, &new_path]).await?;
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        check_path_scope(&app, &new_path)?;
+        if canonical_key(&path) == canonical_key(&new_path) {
+            // Only the spelling changes, e.g. the case on a case-insensitive file system
+            fs::rename(&path, &new_path).map_err(|e| format!("Failed to rename file: {}", e))?;
+        } else {
+            rename_no_clobber(Path::new(&path), Path::new(&new_path))?;
+        }
+        
+        let new_name = Path::new(&new_path)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        relocate_file_entries(&app, &path, &new_path, &new_name)?;
+        publish_event(&app, DrawingEvent::FileRenamed { from: path, to: new_path });
+        Ok(())
+    })
+    .await
+}
+
+/// Rename every drawing whose name matches `pattern` (a regex, or a literal if it isn't one)
+#[tauri::command]
+async fn batch_rename(
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, WriteQueue>,
+    paths: Vec<String>,
+    pattern: String,
+    replacement: String,
+) -> Result<Vec<RenameResult>, String> {
+    let regex = regex::Regex::new(&pattern)
+        .or_else(|_| regex::Regex::new(&regex::escape(&pattern)))
+        .map_err(|e| format!("Invalid pattern: {}", e))?;
+    
+    let queued: Vec<&str> = paths.iter().map(|path| path.as_str()).collect();
+    let _turns = queue.wait_turns(&queued).await?;
+    run_blocking(app, move |app| {
+        let mut results = Vec::with_capacity(paths.len());
+        for old_path in paths {
+            let renamed = check_path_scope(&app, &old_path).and_then(|_| rename_drawing(&old_path, &regex, &replacement));
+            let result = match renamed {
+                Ok(Some((new_path, new_name))) => {
+                    let error = relocate_file_entries(&app, &old_path, &new_path, &new_name).err();
+                    publish_event(&app, DrawingEvent::FileRenamed { from: old_path.clone(), to: new_path.clone() });
+                    RenameResult { old_path, new_path: Some(new_path), error }
+                }
+                Ok(None) => RenameResult { old_path, new_path: None, error: None },
+                Err(e) => RenameResult { old_path, new_path: None, error: Some(e) },
+            };
+            results.push(result);
+        }
+        Ok(results)
+    })
+    .await
+}
+
+/// Set or clear the color label of a file in recents and favorites
+#[tauri::command]
+async fn set_file_label<R: Runtime>(app: tauri::AppHandle<R>, path: String, label: Option<String>) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let label = label.map(|l| l.trim().to_lowercase()).filter(|l| !l.is_empty());
+        if let Some(label) = &label {
+            if !LABEL_COLORS.contains(&label.as_str()) {
+                return Err(format!("Unknown label color: {}", label));
+            }
+        }
+        
+        let key = canonical_key(&path);
+        
+        let mut recent = load_recent_files(&app)?;
+        let mut recent_changed = false;
+        for file in recent.iter_mut().filter(|f| canonical_key(&f.path) == key) {
+            file.label = label.clone();
+            recent_changed = true;
+        }
+        if recent_changed {
+            save_recent_files(&app, &recent)?;
+        }
+        
+        let favorites_path = get_favorites_path(&app)?;
+        let mut favorites: Vec<Favorite> = read_json_or_default(&favorites_path);
+        let mut favorites_changed = false;
+        for favorite in favorites.iter_mut().filter(|f| canonical_key(&f.path) == key) {
+            favorite.label = label.clone();
+            favorites_changed = true;
+        }
+        if favorites_changed {
+            write_json(&favorites_path, &favorites)?;
+        }
+        
+        Ok(())
+    })
+    .await
+}
+
+/// Get the templates directory
+fn get_templates_dir<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    get_app_data_subdir(app, "templates")
+}
+
+/// Template names become file names, so keep them to a single path component
+fn validate_template_name(name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', ':']) {
+        return Err(format!("Invalid template name: {:?}", name));
+    }
+    Ok(name.to_string())
+}
+
+/// Save a drawing (given by path or as JSON content) as a reusable template
+#[tauri::command]
+async fn save_as_template<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    fonts: tauri::State<'_, FontStore>,
+    source_path_or_content: String,
+    template_name: String,
+    thumbnail_svg: Option<String>,
+    overwrite: Option<bool>,
+) -> Result<TemplateInfo, String> {
+    let options = fonts.svg_options()?;
+    run_blocking(app, move |app| {
+        use base64::Engine;
+        
+        let name = validate_template_name(&template_name)?;
+        if BUILT_IN_TEMPLATES.iter().any(|(built_in, _)| *built_in == name) {
+            return Err(format!("\"{}\" is a built-in template name", name));
+        }
+        
+        // Render before taking the store lock so other stores aren't held up
+        let png = match thumbnail_svg {
+            Some(svg) => {
+                let tree = usvg::Tree::from_str(&svg, &options)
+                    .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+                let png = render_tree_fit(&tree, 256, 256, None)?
+                    .encode_png()
+                    .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+                Some(png)
+            }
+            None => None,
+        };
+        
+        let _stores = lock_json_stores();
+        let templates_dir = get_templates_dir(&app)?;
+        let manifest_path = templates_dir.join("manifest.json");
+        let mut manifest: Vec<TemplateManifestEntry> = read_json_or_default(&manifest_path);
+        
+        if manifest.iter().any(|t| t.name == name) && !overwrite.unwrap_or(false) {
+            return Err(format!("A template named \"{}\" already exists", name));
+        }
+        
+        let mut drawing: DrawingFile = match serde_json::from_str(&source_path_or_content) {
+            Ok(drawing) => drawing,
+            Err(_) => read_drawing(&check_path_scope(&app, &source_path_or_content)?)?,
+        };
+        
+        // Strip everything tied to the source document
+        drawing.name = name.clone();
+        drawing.created_at = 0;
+        drawing.updated_at = 0;
+        drawing.session_seconds = 0;
+        drawing.history = None;
+        drawing.extra.clear();
+        write_drawing(&templates_dir.join(format!("{}.jamal", name)), &mut drawing)?;
+        
+        let thumbnail_path = templates_dir.join(format!("{}.png", name));
+        let thumbnail = match png {
+            Some(png) => {
+                fs::write(&thumbnail_path, &png).map_err(|e| format!("Failed to save thumbnail: {}", e))?;
+                Some(format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(&png)))
+            }
+            None => {
+                let _ = fs::remove_file(&thumbnail_path);
+                None
+            }
+        };
+        
+        let entry = TemplateManifestEntry {
+            name: name.clone(),
+            created_at: chrono_timestamp(),
+            has_thumbnail: thumbnail.is_some(),
+        };
+        manifest.retain(|t| t.name != name);
+        manifest.push(entry.clone());
+        write_json(&manifest_path, &manifest)?;
+        
+        Ok(TemplateInfo {
+            name,
+            created_at: entry.created_at,
+            thumbnail,
+            built_in: false,
+        })
+    })
+    .await
+}
+
+/// List saved templates with their thumbnails
+#[tauri::command]
+async fn list_templates<R: Runtime>(app: tauri::AppHandle<R>) -> Result<Vec<TemplateInfo>, String> {
+    run_blocking(app, move |app| {
+        use base64::Engine;
+        
+        let templates_dir = get_templates_dir(&app)?;
+        let manifest: Vec<TemplateManifestEntry> = read_json_or_default(&templates_dir.join("manifest.json"));
+        
+        let built_ins = BUILT_IN_TEMPLATES.iter().map(|(name, _)| TemplateInfo {
+            name: name.to_string(),
+            created_at: 0,
+            thumbnail: None,
+            built_in: true,
+        });
+        
+        Ok(built_ins
+            .chain(manifest.into_iter().map(|entry| {
+                let thumbnail = if entry.has_thumbnail {
+                    fs::read(templates_dir.join(format!("{}.png", entry.name)))
+                        .ok()
+                        .map(|png| format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(png)))
+                } else {
+                    None
+                };
+                TemplateInfo {
+                    name: entry.name,
+                    created_at: entry.created_at,
+                    thumbnail,
+                    built_in: false,
+                }
+            }))
+            .collect())
+    })
+    .await
+}
+
+/// Delete a saved template
+#[tauri::command]
+async fn delete_template<R: Runtime>(app: tauri::AppHandle<R>, name: String) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        if BUILT_IN_TEMPLATES.iter().any(|(built_in, _)| *built_in == name) {
+            return Err(format!("Built-in template \"{}\" can't be deleted", name));
+        }
+        
+        let templates_dir = get_templates_dir(&app)?;
+        let manifest_path = templates_dir.join("manifest.json");
+        let mut manifest: Vec<TemplateManifestEntry> = read_json_or_default(&manifest_path);
+        
+        let before = manifest.len();
+        manifest.retain(|t| t.name != name);
+        if manifest.len() == before {
+            return Err(format!("Template not found: {}", name));
+        }
+        
+        let _ = fs::remove_file(templates_dir.join(format!("{}.jamal", name)));
+        let _ = fs::remove_file(templates_dir.join(format!("{}.png", name)));
+        write_json(&manifest_path, &manifest)
+    })
+    .await
+}
+
+/// Generate a random record id suffix
+fn random_id() -> String {
+    use std::hash::{BuildHasher, Hasher};
+    
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u128(
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0),
+    );
+    format!("{:016x}", hasher.finish())
+}
+
+/// Give every shape, binding and asset record in a store a fresh id,
+/// rewriting all references (keys and string values) to match
+fn regenerate_record_ids(store: &mut serde_json::Value) {
+    fn consider(s: &str, ids: &mut std::collections::HashMap<String, String>) {
+        if let Some(prefix) = REGENERATED_ID_PREFIXES.iter().find(|p| s.starts_with(**p)) {
+            ids.entry(s.to_string())
+                .or_insert_with(|| format!("{}{}", prefix, random_id()));
+        }
+    }
+    
+    fn collect(value: &serde_json::Value, ids: &mut std::collections::HashMap<String, String>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, child) in map {
+                    consider(key, ids);
+                    collect(child, ids);
+                }
+            }
+            serde_json::Value::Array(items) => items.iter().for_each(|item| collect(item, ids)),
+            serde_json::Value::String(s) => consider(s, ids),
+            _ => {}
+        }
+    }
+    
+    fn replace(value: &mut serde_json::Value, ids: &std::collections::HashMap<String, String>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                for (key, mut child) in std::mem::take(map) {
+                    replace(&mut child, ids);
+                    map.insert(ids.get(&key).cloned().unwrap_or(key), child);
+                }
+            }
+            serde_json::Value::Array(items) => items.iter_mut().for_each(|item| replace(item, ids)),
+            serde_json::Value::String(s) => {
+                if let Some(new_id) = ids.get(s.as_str()) {
+                    *s = new_id.clone();
+                }
+            }
+            _ => {}
+        }
+    }
+    
+    let mut ids = std::collections::HashMap::new();
+    collect(store, &mut ids);
+    replace(store, &ids);
+}
+
+/// Create a new document from a built-in or user template. The drawing is written to
+/// `target_path` when given, and returned either way; the template itself is never modified.
+#[tauri::command]
+async fn new_from_template<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    template_name: String,
+    target_path: Option<String>,
+) -> Result<DrawingFile, String> {
+    run_blocking(app, move |app| {
+        let mut drawing: DrawingFile = match BUILT_IN_TEMPLATES.iter().find(|(name, _)| *name == template_name) {
+            Some((_, content)) => serde_json::from_str(content)
+                .map_err(|e| format!("Failed to parse built-in template: {}", e))?,
+            None => {
+                let name = validate_template_name(&template_name)?;
+                read_drawing(&get_templates_dir(&app)?.join(format!("{}.jamal", name)))
+                    .map_err(|e| format!("Failed to load template \"{}\": {}", name, e))?
+            }
+        };
+        
+        regenerate_record_ids(&mut drawing.store);
+        
+        let now = timestamp_millis();
+        drawing.created_at = now;
+        drawing.updated_at = now;
+        drawing.session_seconds = 0;
+        drawing.history = None;
+        drawing.checksum = None;
+        drawing.extra.clear();
+        drawing.name = target_path
+            .as_deref()
+            .and_then(|path| Path::new(path).file_stem())
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| "Untitled".to_string());
+        
+        if let Some(path) = &target_path {
+            write_drawing(&check_path_scope(&app, path)?, &mut drawing)?;
+        }
+        
+        Ok(drawing)
+    })
+    .await
+}
+
+/// Scan the start of a JSON document for the top-level `version` (a number) and
+/// `store` keys of a drawing, without parsing the rest
+fn looks_like_drawing(prefix: &[u8]) -> bool {
+    let mut bytes = prefix.iter().copied().enumerate();
+    let first = bytes.by_ref().find(|(_, b)| !b.is_ascii_whitespace());
+    if !matches!(first, Some((_, b'{'))) {
+        return false;
+    }
+    
+    let mut depth = 1usize;
+    let mut has_version = false;
+    let mut has_store = false;
+    while let Some((start, byte)) = bytes.next() {
+        match byte {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => {
+                depth -= 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            b'"' => {
+                // Read the string, honoring escapes
+                let mut end = start;
+                let mut escaped = false;
+                for (i, b) in bytes.by_ref() {
+                    end = i;
+                    match (escaped, b) {
+                        (false, b'\\') => escaped = true,
+                        (false, b'"') => break,
+                        _ => escaped = false,
+                    }
+                }
+                if end <= start {
+                    break;
+                }
+                if depth != 1 {
+                    continue;
+                }
+                // A top-level key is followed by a colon
+                let rest = &prefix[(end + 1).min(prefix.len())..];
+                let mut after = rest.iter().skip_while(|b| b.is_ascii_whitespace());
+                if after.next() != Some(&b':') {
+                    continue;
+                }
+                match &prefix[start + 1..end] {
+                    b"version" => {
+                        has_version = after
+                            .find(|b| !b.is_ascii_whitespace())
+                            .map(|b| b.is_ascii_digit())
+                            .unwrap_or(false)
+                    }
+                    b"store" => has_store = true,
+                    _ => {}
+                }
+                if has_version && has_store {
+                    return true;
+                }
+            }
+            _ => {}
+        }
+    }
+    
+    false
+}
+
+/// Check by content, not extension, whether a file is a drawing
+#[tauri::command]
+async fn is_jamal_drawing(app: tauri::AppHandle, path: String) -> Result<bool, String> {
+    run_blocking(app, move |app| sniff_drawing(&check_path_scope(&app, &path)?)).await
+}
+
+/// Whether a file holds a drawing: encrypted ones by their magic, compressed ones by
+/// the start of their decompressed content, plain ones by their first bytes
+fn sniff_drawing(path: &Path) -> Result<bool, String> {
+    use std::io::Read;
+    
+    const PREFIX_SIZE: u64 = 64 * 1024;
+    
+    let mut file = fs::File::open(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    let mut magic = Vec::new();
+    file.by_ref()
+        .take(ENCRYPTED_MAGIC.len() as u64)
+        .read_to_end(&mut magic)
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    if magic.starts_with(ENCRYPTED_MAGIC) {
+        return Ok(true);
+    }
+    
+    let content = std::io::Cursor::new(magic.clone()).chain(file);
+    let mut prefix = Vec::new();
+    let read = if magic.starts_with(&GZIP_MAGIC) {
+        // A prefix of the decompressed stream; the rest is never inflated
+        flate2::read::GzDecoder::new(content).take(PREFIX_SIZE).read_to_end(&mut prefix)
+    } else {
+        content.take(PREFIX_SIZE).read_to_end(&mut prefix)
+    };
+    if let Err(e) = read {
+        // A damaged compressed stream still may have given up its start
+        if prefix.is_empty() {
+            return Err(format!("Failed to read file: {}", e));
+        }
+    }
+    
+    Ok(looks_like_drawing(&prefix))
+}
+
+/// Get the platform, architecture and OS version, for bug reports
+#[tauri::command]
+fn get_os_info() -> OsInfo {
+    OsInfo {
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        version: sysinfo::System::os_version().unwrap_or_else(|| "unknown".to_string()),
+    }
+}
+
+/// Get the snapshots directory
+fn get_snapshots_dir<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    get_app_data_subdir(app, "snapshots")
+}
+
+/// Get the snapshot index, which maps canonical paths to their snapshots
+fn get_snapshots_index_path<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    Ok(get_snapshots_dir(app)?.join("index.json"))
+}
+
+/// Gzip some bytes
+fn gzip(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    use std::io::Write;
+    
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(bytes).map_err(|e| format!("Failed to compress: {}", e))?;
+    encoder.finish().map_err(|e| format!("Failed to compress: {}", e))
+}
+
+/// Gunzip some bytes
+fn gunzip(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    use std::io::Read;
+    
+    let mut decoded = Vec::new();
+    flate2::read::GzDecoder::new(bytes)
+        .read_to_end(&mut decoded)
+        .map_err(|e| format!("Failed to decompress: {}", e))?;
+    Ok(decoded)
+}
+
+/// Store a snapshot of some content, reusing the compressed blob of identical content
+fn store_snapshot<R: Runtime>(app: &tauri::AppHandle<R>, path: &str, label: String, content: &[u8]) -> Result<SnapshotInfo, String> {
+    let _stores = lock_json_stores();
+    let content_hash = sha256_hex(content);
+    let blob_path = get_snapshots_dir(app)?.join(format!("{}.gz", content_hash));
+    if !blob_path.exists() {
+        write_atomic(&blob_path, &gzip(content)?, false)?;
+    }
+    
+    let info = SnapshotInfo {
+        id: random_id(),
+        label,
+        created_at: chrono_timestamp(),
+        size: content.len() as u64,
+        content_hash,
+    };
+    
+    let index_path = get_snapshots_index_path(app)?;
+    let mut index: BTreeMap<String, Vec<SnapshotInfo>> = read_json_or_default(&index_path);
+    index.entry(canonical_key(path)).or_default().push(info.clone());
+    write_json(&index_path, &index)?;
+    
+    Ok(info)
+}
+
+/// Delete a file, returning its size if it was removed
+fn remove_file_counted(path: &Path) -> Option<u64> {
+    let size = fs::metadata(path).ok()?.len();
+    fs::remove_file(path).ok().map(|_| size)
+}
+
+/// Edge of the square drawing previews, in pixels
+const PREVIEW_SIZE: u32 = 256;
+
+/// Size the preview cache is pruned back to, least recently used first
+const MAX_PREVIEW_CACHE_BYTES: u64 = 64 * 1024 * 1024;
+
+fn get_previews_dir<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    get_app_data_subdir(app, "previews")
+}
+
+/// Cache file of a drawing's preview, `<path hash>-<mtime>.png`, so an edited file
+/// misses the cache. Also returns the path hash, shared by all its previews
+fn preview_cache_path<R: Runtime>(app: &tauri::AppHandle<R>, path: &Path) -> Result<(PathBuf, String), String> {
+    let modified = fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(|e| format!("Failed to read file: {}", e))?;
+    let millis = modified
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    let path_hash = sha256_hex(canonical_key(&path.to_string_lossy()).as_bytes());
+    Ok((get_previews_dir(app)?.join(format!("{}-{}.png", path_hash, millis)), path_hash))
+}
+
+/// Delete the least recently used previews until the cache fits within
+/// `MAX_PREVIEW_CACHE_BYTES`. Returns how many files and bytes were removed
+fn prune_preview_cache<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<(u32, u64), String> {
+    let mut previews: Vec<(PathBuf, u64, std::time::SystemTime)> = fs::read_dir(get_previews_dir(app)?)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            Some((entry.path(), metadata.len(), metadata.modified().ok()?))
+        })
+        .collect();
+    previews.sort_by_key(|(_, _, modified)| *modified);
+    
+    let mut total: u64 = previews.iter().map(|(_, size, _)| size).sum();
+    let (mut removed, mut freed) = (0, 0);
+    for (path, _, _) in previews {
+        if total <= MAX_PREVIEW_CACHE_BYTES {
+            break;
+        }
+        if let Some(size) = remove_file_counted(&path) {
+            total -= size;
+            removed += 1;
+            freed += size;
+        }
+    }
+    Ok((removed, freed))
+}
+
+/// A drawing's cached preview, or `needs-render` when there's none for the file as
+/// it is now. The frontend then loads the drawing, renders its SVG and passes it to
+/// `store_preview`
+#[tauri::command]
+async fn get_or_request_preview(app: tauri::AppHandle, path: String) -> Result<Preview, String> {
+    use base64::Engine;
+    
+    run_blocking(app, move |app| {
+        let path = check_path_scope(&app, &path)?;
+        let (cache_path, _) = preview_cache_path(&app, &path)?;
+        let png = match fs::read(&cache_path) {
+            Ok(png) => png,
+            Err(_) => return Ok(Preview { status: PreviewStatus::NeedsRender, thumbnail: None }),
+        };
+        
+        // Pruning goes by modification time, so mark the preview as recently used
+        let _ = fs::File::options()
+            .write(true)
+            .open(&cache_path)
+            .and_then(|file| file.set_modified(std::time::SystemTime::now()));
+        Ok(Preview {
+            status: PreviewStatus::Cached,
+            thumbnail: Some(format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(png))),
+        })
+    })
+    .await
+}
+
+/// Render a drawing's SVG into its cached preview, replacing previews of earlier
+/// versions of the file
+#[tauri::command]
+async fn store_preview(
+    app: tauri::AppHandle,
+    fonts: tauri::State<'_, FontStore>,
+    path: String,
+    svg: String,
+) -> Result<Preview, String> {
+    use base64::Engine;
+    
+    let options = fonts.svg_options()?;
+    run_blocking(app, move |app| {
+        let tree = usvg::Tree::from_str(&svg, &options)
+            .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+        let png = render_tree_fit(&tree, PREVIEW_SIZE, PREVIEW_SIZE, None)?
+            .encode_png()
+            .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+        
+        let path = check_path_scope(&app, &path)?;
+        let (cache_path, path_hash) = preview_cache_path(&app, &path)?;
+        let stale_prefix = format!("{}-", path_hash);
+        for entry in fs::read_dir(get_previews_dir(&app)?).into_iter().flatten().flatten() {
+            if entry.file_name().to_string_lossy().starts_with(&stale_prefix) && entry.path() != cache_path {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+        write_atomic(&cache_path, &png, false)?;
+        prune_preview_cache(&app)?;
+        
+        Ok(Preview {
+            status: PreviewStatus::Cached,
+            thumbnail: Some(format!("data:image/png;base64,{}", base64::engine::general_purpose::STANDARD.encode(&png))),
+        })
+    })
+    .await
+}
+
+/// List the drawings in a folder, by name, with whether each has a cached preview
+#[tauri::command]
+async fn list_gallery(app: tauri::AppHandle, directory: String) -> Result<Vec<GalleryEntry>, String> {
+    run_blocking(app, move |app| {
+        let directory = check_path_scope(&app, &directory)?;
+        let entries = fs::read_dir(&directory).map_err(|e| format!("Failed to read directory: {}", e))?;
+        
+        let mut gallery: Vec<GalleryEntry> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("jamal")))
+            .map(|path| {
+                let metadata = fs::metadata(&path).ok();
+                let cached = preview_cache_path(&app, &path).is_ok_and(|(cache_path, _)| cache_path.exists());
+                GalleryEntry {
+                    name: path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default(),
+                    size: metadata.as_ref().map(|m| m.len()).unwrap_or(0),
+                    modified_at: metadata
+                        .and_then(|m| m.modified().ok())
+                        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                        .map(|d| d.as_secs() as i64),
+                    preview: if cached { PreviewStatus::Cached } else { PreviewStatus::NeedsRender },
+                    path: path.to_string_lossy().to_string(),
+                }
+            })
+            .collect();
+        gallery.sort_by_key(|entry| entry.name.to_lowercase());
+        Ok(gallery)
+    })
+    .await
+}
+
+/// List the drawings in a folder with their tags, by name
+#[tauri::command]
+async fn list_drawings<R: Runtime>(app: tauri::AppHandle<R>, directory: String) -> Result<Vec<DrawingListEntry>, String> {
+    run_blocking(app, move |app| {
+        let directory = check_path_scope(&app, &directory)?;
+        let tags: BTreeMap<String, Vec<String>> = read_json_or_default(&get_tags_path(&app)?);
+        list_drawings_in(&directory, &tags)
+    })
+    .await
+}
+
+/// The `.jamal` files directly in a folder, with their tags from the tags store
+fn list_drawings_in(directory: &Path, tags: &BTreeMap<String, Vec<String>>) -> Result<Vec<DrawingListEntry>, String> {
+    let entries = fs::read_dir(directory).map_err(|e| format!("Failed to read directory: {}", e))?;
+    
+    let mut drawings: Vec<DrawingListEntry> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("jamal")))
+        .map(|path| {
+            let metadata = fs::metadata(&path).ok();
+            let path = path.to_string_lossy().to_string();
+            DrawingListEntry {
+                name: Path::new(&path).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default(),
+                size: metadata.as_ref().map(|m| m.len()).unwrap_or(0),
+                modified_at: metadata
+                    .and_then(|m| m.modified().ok())
+                    .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs() as i64),
+                tags: tags.get(&canonical_key(&path)).cloned().unwrap_or_default(),
+                path,
+            }
+        })
+        .collect();
+    drawings.sort_by_key(|entry| entry.name.to_lowercase());
+    Ok(drawings)
+}
+
+/// Total size of the files under `dir`, recording a warning for each directory that
+/// can't be read instead of failing
+fn directory_size(dir: &Path, warnings: &mut Vec<String>) -> u64 {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!("Skipping unreadable directory {}: {}", dir.display(), e);
+            warnings.push(format!("Skipped {}: {}", dir.display(), e));
+            return 0;
+        }
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => directory_size(&entry.path(), warnings),
+            _ => entry.metadata().map(|m| m.len()).unwrap_or(0),
+        })
+        .sum()
+}
+
+/// Report how much disk space the app's own data takes, per category. Backups kept
+/// in a user-chosen `backup_directory` are outside the app data dir and not counted
+#[tauri::command]
+async fn app_data_usage(app: tauri::AppHandle) -> Result<DataUsage, String> {
+    run_blocking(app, move |app| data_usage(&get_app_data_dir(&app)?)).await
+}
+
+/// The walk behind `app_data_usage` over the app data dir `root`
+fn data_usage(root: &Path) -> Result<DataUsage, String> {
+    let mut usage = DataUsage::default();
+    if !root.exists() {
+        return Ok(usage);
+    }
+    
+    let entries = fs::read_dir(root).map_err(|e| format!("Failed to read app data directory: {}", e))?;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = entry.path();
+        if entry.file_type().is_ok_and(|file_type| file_type.is_dir()) {
+            match name.as_str() {
+                "backups" => usage.backups += directory_size(&path, &mut usage.warnings),
+                "snapshots" => usage.snapshots += directory_size(&path, &mut usage.warnings),
+                "previews" => usage.previews += directory_size(&path, &mut usage.warnings),
+                "templates" => {
+                    let thumbnails: u64 = fs::read_dir(&path)
+                        .into_iter()
+                        .flatten()
+                        .flatten()
+                        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "png"))
+                        .filter_map(|entry| entry.metadata().ok())
+                        .map(|m| m.len())
+                        .sum();
+                    usage.thumbnails += thumbnails;
+                    usage.templates += directory_size(&path, &mut usage.warnings).saturating_sub(thumbnails);
+                }
+                _ => usage.other += directory_size(&path, &mut usage.warnings),
+            }
+            continue;
+        }
+        
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        let category = match name.as_str() {
+            "recent_files.json" => &mut usage.recent_files,
+            "preferences.json" | "tags.json" | "favorites.json" | "view_states.json" | "path_scope.json" => {
+                &mut usage.settings
+            }
+            _ if name.starts_with(LOG_FILE_NAME) => &mut usage.logs,
+            _ => &mut usage.other,
+        };
+        *category += size;
+    }
+    
+    usage.total = usage.recent_files
+        + usage.settings
+        + usage.backups
+        + usage.snapshots
+        + usage.templates
+        + usage.thumbnails
+        + usage.previews
+        + usage.logs
+        + usage.other;
+    Ok(usage)
+}
+
+/// Remove app data nothing refers to anymore: thumbnails of deleted templates,
+/// backups of deleted drawings past `orphaned_backup_max_age_days`, backups beyond
+/// the retention count, unreferenced snapshot blobs and temp files left by crashes
+#[tauri::command]
+async fn cleanup_app_data(app: tauri::AppHandle) -> Result<CleanupReport, String> {
+    run_blocking(app, move |app| {
+        let prefs = load_preferences(&app)?;
+        let mut report = CleanupReport::default();
+        let max_age_ms = prefs.orphaned_backup_max_age_days as i64 * 24 * 60 * 60 * 1000;
+        let now = timestamp_millis();
+        
+        let templates_dir = get_templates_dir(&app)?;
+        remove_orphaned_thumbnails(&templates_dir, &mut report);
+        prune_backup_dirs(&get_backups_dir(&app)?, prefs.backup_retention as usize, max_age_ms, now, &mut report);
+        
+        let snapshots_dir = get_snapshots_dir(&app)?;
+        {
+            // Held from reading the index to the last deletion, so a snapshot stored
+            // meanwhile can't have its new blob taken for an orphan
+            let _stores = lock_json_stores();
+            let index: BTreeMap<String, Vec<SnapshotInfo>> = read_json_or_default(&get_snapshots_index_path(&app)?);
+            remove_orphaned_snapshot_blobs(&snapshots_dir, &index, &mut report);
+        }
+        
+        let (previews_removed, previews_freed) = prune_preview_cache(&app)?;
+        report.previews_removed = previews_removed;
+        report.bytes_freed += previews_freed;
+        
+        // Temp files from atomic writes interrupted by a crash; recent ones may be in use
+        let app_data = get_app_data_dir(&app)?;
+        let is_stale = |entry: &fs::DirEntry| {
+            entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|modified| modified.elapsed().ok())
+                .is_some_and(|age| age > std::time::Duration::from_secs(60 * 60))
+        };
+        for dir in [&app_data, &templates_dir, &snapshots_dir] {
+            for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if name.starts_with('.') && name.ends_with(".tmp") && is_stale(&entry) {
+                    if let Some(size) = remove_file_counted(&entry.path()) {
+                        report.recovery_files_removed += 1;
+                        report.bytes_freed += size;
+                    }
+                }
+            }
+        }
+        
+        Ok(report)
+    })
+    .await
+}
+
+/// Delete the template thumbnails in `templates_dir` whose template is gone
+fn remove_orphaned_thumbnails(templates_dir: &Path, report: &mut CleanupReport) {
+    for entry in fs::read_dir(templates_dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.extension().is_some_and(|ext| ext == "png") && !path.with_extension("jamal").exists() {
+            if let Some(size) = remove_file_counted(&path) {
+                report.thumbnails_removed += 1;
+                report.bytes_freed += size;
+            }
+        }
+    }
+}
+
+/// Delete the backup directories under `backups_root` whose drawing is gone and whose
+/// newest backup is older than `max_age_ms`, and trim the rest to `retention` backups
+fn prune_backup_dirs(backups_root: &Path, retention: usize, max_age_ms: i64, now: i64, report: &mut CleanupReport) {
+    for entry in fs::read_dir(backups_root).into_iter().flatten().flatten() {
+        let dir = entry.path();
+        if !dir.is_dir() {
+            continue;
+        }
+        let backups = list_backup_files(&dir);
+        let source_exists = fs::read_to_string(dir.join("source.txt"))
+            .map(|source| Path::new(source.trim()).exists())
+            .unwrap_or(true);
+        let newest = backups.last().map(|(_, info)| info.timestamp).unwrap_or(0);
+        
+        if !source_exists && now - newest > max_age_ms {
+            for (backup, _) in &backups {
+                if let Some(size) = remove_file_counted(backup) {
+                    report.recovery_files_removed += 1;
+                    report.bytes_freed += size;
+                }
+            }
+            let _ = fs::remove_dir_all(&dir);
+            continue;
+        }
+        
+        let excess = backups.len().saturating_sub(retention);
+        for (backup, _) in backups.into_iter().take(excess) {
+            if let Some(size) = remove_file_counted(&backup) {
+                report.backups_removed += 1;
+                report.bytes_freed += size;
+            }
+        }
+    }
+}
+
+/// Delete the snapshot blobs in `snapshots_dir` that no snapshot in `index` refers to
+fn remove_orphaned_snapshot_blobs(snapshots_dir: &Path, index: &BTreeMap<String, Vec<SnapshotInfo>>, report: &mut CleanupReport) {
+    let referenced: std::collections::HashSet<&str> = index
+        .values()
+        .flatten()
+        .map(|snapshot| snapshot.content_hash.as_str())
+        .collect();
+    for entry in fs::read_dir(snapshots_dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        let is_orphan = path.extension().is_some_and(|ext| ext == "gz")
+            && path.file_stem().is_some_and(|stem| !referenced.contains(stem.to_string_lossy().as_ref()));
+        if is_orphan {
+            if let Some(size) = remove_file_counted(&path) {
+                report.snapshot_blobs_removed += 1;
+                report.bytes_freed += size;
+            }
+        }
+    }
+}
+
+/// Save the current content of a drawing as a labeled snapshot
+#[tauri::command]
+async fn create_snapshot<R: Runtime>(app: tauri::AppHandle<R>, path: String, label: String) -> Result<SnapshotInfo, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        let content = fs::read(&path).map_err(|e| format!("Failed to read file: {}", e))?;
+        // Snapshots hold the drawing JSON, whatever container the file is saved in
+        let content = decode_drawing_bytes(Path::new(&path), content, read_size_limit(&app, None)?)?;
+        let info = store_snapshot(&app, &path, label, &content)?;
+        publish_event(&app, DrawingEvent::SnapshotCreated(path));
+        Ok(info)
+    })
+    .await
+}
+
+/// List the snapshots of a drawing, newest first
+#[tauri::command]
+async fn list_snapshots<R: Runtime>(app: tauri::AppHandle<R>, path: String) -> Result<Vec<SnapshotInfo>, String> {
+    run_blocking(app, move |app| {
+        let index: BTreeMap<String, Vec<SnapshotInfo>> = read_json_or_default(&get_snapshots_index_path(&app)?);
+        let mut snapshots = index.get(&canonical_key(&path)).cloned().unwrap_or_default();
+        snapshots.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+        Ok(snapshots)
+    })
+    .await
+}
+
+/// Restore a snapshot into the drawing, snapshotting the current state first so it can be undone
+#[tauri::command]
+async fn restore_snapshot<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    queue: tauri::State<'_, WriteQueue>,
+    path: String,
+    snapshot_id: String,
+) -> Result<(), String> {
+    let restored_path = path.clone();
+    let turn = queue.wait_turns(&[&path]).await?;
+    let name = run_blocking(app.clone(), move |app| {
+        check_path_scope(&app, &path)?;
+        let index: BTreeMap<String, Vec<SnapshotInfo>> = read_json_or_default(&get_snapshots_index_path(&app)?);
+        let snapshot = index
+            .get(&canonical_key(&path))
+            .and_then(|snapshots| snapshots.iter().find(|s| s.id == snapshot_id))
+            .ok_or_else(|| format!("Snapshot not found: {}", snapshot_id))?;
+        
+        let blob = fs::read(get_snapshots_dir(&app)?.join(format!("{}.gz", snapshot.content_hash)))
+            .map_err(|e| format!("Failed to read snapshot: {}", e))?;
+        let label = format!("Before restoring \"{}\"", snapshot.label);
+        let name = restore_drawing(Path::new(&path), &blob, read_size_limit(&app, None)?, |current| {
+            store_snapshot(&app, &path, label, current).map(|_| ())
+        })?;
+        publish_event(&app, DrawingEvent::SnapshotRestored(path.clone()));
+        Ok(name)
+    })
+    .await?;
+    drop(turn);
+    add_recent_file(app, restored_path, name).await
+}
+
+/// Write the drawing in a snapshot blob over `path`, in the container the file is in
+/// now, after handing the file's current JSON to `keep_current`. Returns the
+/// drawing's name.
+fn restore_drawing(
+    path: &Path,
+    blob: &[u8],
+    limit: Option<u64>,
+    keep_current: impl FnOnce(&[u8]) -> Result<(), String>,
+) -> Result<String, String> {
+    let mut drawing: DrawingFile = serde_json::from_slice(&gunzip(blob)?)
+        .map_err(|e| format!("Failed to parse snapshot: {}", e))?;
+    drawing.updated_at = timestamp_millis();
+    
+    let container = match fs::read(path) {
+        Ok(current) => {
+            let container = drawing_container(&current);
+            keep_current(&decode_drawing_bytes(path, current, limit)?)?;
+            container
+        }
+        Err(_) => DrawingContainer::Json,
+    };
+    write_atomic(path, &encode_drawing(&drawing, container)?, false)?;
+    Ok(drawing.name)
+}
+
+/// Delete a snapshot, and its blob once no other snapshot shares it
+#[tauri::command]
+async fn delete_snapshot<R: Runtime>(app: tauri::AppHandle<R>, path: String, snapshot_id: String) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let _stores = lock_json_stores();
+        let index_path = get_snapshots_index_path(&app)?;
+        let mut index: BTreeMap<String, Vec<SnapshotInfo>> = read_json_or_default(&index_path);
+        
+        let key = canonical_key(&path);
+        let snapshots = index.get_mut(&key).ok_or_else(|| format!("Snapshot not found: {}", snapshot_id))?;
+        let position = snapshots
+            .iter()
+            .position(|s| s.id == snapshot_id)
+            .ok_or_else(|| format!("Snapshot not found: {}", snapshot_id))?;
+        let removed = snapshots.remove(position);
+        if snapshots.is_empty() {
+            index.remove(&key);
+        }
+        write_json(&index_path, &index)?;
+        
+        let still_used = index.values().flatten().any(|s| s.content_hash == removed.content_hash);
+        if !still_used {
+            let _ = fs::remove_file(get_snapshots_dir(&app)?.join(format!("{}.gz", removed.content_hash)));
+        }
+        Ok(())
+    })
+    .await
+}
+
+/// Check if a file exists. Paths out of scope are reported as missing.
+#[tauri::command]
+async fn file_exists<R: Runtime>(app: tauri::AppHandle<R>, path: String) -> bool {
+    check_path_scope(&app, &path).is_ok_and(|path| path.exists())
+}
+
+/// Get a simple timestamp (seconds since epoch)
+fn chrono_timestamp() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Get a timestamp in milliseconds since epoch, as used inside drawing files
+fn timestamp_millis() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// Add an editing session's duration to a drawing's total editing time
+#[tauri::command]
+async fn record_session(
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, WriteQueue>,
+    path: String,
+    elapsed_seconds: u64,
+) -> Result<(), String> {
+    edit_drawing(app, &queue, path, move |_, drawing| {
+        drawing.session_seconds = drawing.session_seconds.saturating_add(elapsed_seconds);
+        drawing.updated_at = timestamp_millis();
+        Ok(())
+    })
+    .await
+}
+
+/// Drawings whose JSON is above this size are renamed by splicing its bytes instead
+/// of parsing the whole store
+const IN_PLACE_EDIT_THRESHOLD: u64 = 1024 * 1024;
+
+/// A JSON object member's raw (still escaped) name and the byte range of its value
+type JsonMember<'a> = (&'a [u8], std::ops::Range<usize>);
+
+/// The top-level members of a JSON object. Nested values are skipped over, not parsed.
+fn top_level_members(json: &[u8]) -> Result<Vec<JsonMember<'_>>, String> {
+    let malformed = || "Failed to parse drawing: expected a JSON object".to_string();
+    let skip_ws = |mut i: usize| {
+        while i < json.len() && json[i].is_ascii_whitespace() {
+            i += 1;
+        }
+        i
+    };
+    // From an opening quote to just past the closing one
+    let skip_string = |mut i: usize| {
+        i += 1;
+        while i < json.len() {
+            match json[i] {
+                b'\\' => i += 2,
+                b'"' => return Some(i + 1),
+                _ => i += 1,
+            }
+        }
+        None
+    };
+    
+    let mut i = skip_ws(if json.starts_with(b"\xEF\xBB\xBF") { 3 } else { 0 });
+    if json.get(i) != Some(&b'{') {
+        return Err(malformed());
+    }
+    i = skip_ws(i + 1);
+    let mut members = Vec::new();
+    if json.get(i) == Some(&b'}') {
+        return Ok(members);
+    }
+    loop {
+        if json.get(i) != Some(&b'"') {
+            return Err(malformed());
+        }
+        let key_end = skip_string(i).ok_or_else(malformed)?;
+        let key = &json[i + 1..key_end - 1];
+        i = skip_ws(key_end);
+        if json.get(i) != Some(&b':') {
+            return Err(malformed());
+        }
+        
+        let start = skip_ws(i + 1);
+        let mut depth = 0usize;
+        let mut j = start;
+        while j < json.len() {
+            match json[j] {
+                b'"' => {
+                    j = skip_string(j).ok_or_else(malformed)?;
+                    if depth == 0 {
+                        break;
+                    }
+                    continue;
+                }
+                b'{' | b'[' => depth += 1,
+                b'}' | b']' if depth > 0 => {
+                    depth -= 1;
+                    if depth == 0 {
+                        j += 1;
+                        break;
+                    }
+                }
+                b',' | b'}' if depth == 0 => break,
+                _ => {}
+            }
+            j += 1;
+        }
+        let mut end = j;
+        while end > start && json[end - 1].is_ascii_whitespace() {
+            end -= 1;
+        }
+        members.push((key, start..end));
+        
+        i = skip_ws(j);
+        match json.get(i) {
+            Some(b',') => i = skip_ws(i + 1),
+            Some(b'}') => return Ok(members),
+            _ => return Err(malformed()),
+        }
+    }
+}
+
+/// Rename a drawing and bump its `updatedAt`, leaving the rest of the file as is and
+/// keeping its container. JSON over 1 MB, gzipped or not, is edited by replacing just
+/// those two values in the raw text, so the store is never parsed into memory.
+#[tauri::command]
+async fn update_drawing_name<R: Runtime>(
+    app: tauri::AppHandle<R>,
+    queue: tauri::State<'_, WriteQueue>,
+    path: String,
+    new_name: String,
+) -> Result<(), String> {
+    let _turn = queue.wait_turns(&[&path]).await?;
+    run_blocking(app, move |app| set_drawing_name(&check_path_scope(&app, &path)?, &new_name)).await
+}
+
+/// The edit behind `update_drawing_name`
+fn set_drawing_name(path: &Path, new_name: &str) -> Result<(), String> {
+    let rename = |drawing: &mut DrawingFile| {
+        drawing.name = new_name.to_string();
+        drawing.updated_at = timestamp_millis();
+        Ok(())
+    };
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    if bytes.starts_with(ENCRYPTED_MAGIC) {
+        return Err(format!("{}: {} is password protected", ERR_PASSWORD_REQUIRED, path.display()));
+    }
+    let container = drawing_container(&bytes);
+    let json = match container {
+        DrawingContainer::Json => bytes,
+        DrawingContainer::Gzip => gunzip(&bytes)?,
+        // Binary, so there's no text to splice
+        DrawingContainer::Cbor => return edit_drawing_file(path, rename),
+    };
+    if json.len() as u64 <= IN_PLACE_EDIT_THRESHOLD {
+        return edit_drawing_file(path, rename);
+    }
+    
+    let members = top_level_members(&json)?;
+    if !members.iter().any(|(key, _)| *key == b"name") {
+        return Err("Failed to parse drawing: missing field `name`".to_string());
+    }
+    let name = serde_json::to_string(new_name).map_err(|e| format!("Failed to serialize name: {}", e))?;
+    let updated_at = timestamp_millis().to_string();
+    
+    // The store is untouched, so its checksum stays valid
+    let mut parts: Vec<&[u8]> = Vec::new();
+    let mut copied = 0;
+    for (key, range) in members {
+        let value = match key {
+            b"name" => name.as_bytes(),
+            b"updatedAt" | b"updated_at" => updated_at.as_bytes(),
+            _ => continue,
+        };
+        parts.push(&json[copied..range.start]);
+        parts.push(value);
+        copied = range.end;
+    }
+    parts.push(&json[copied..]);
+    match container {
+        DrawingContainer::Gzip => write_atomic(path, &gzip(&parts.concat())?, false),
+        _ => write_atomic_parts(path, &parts, false),
+    }
+}
+
+/// Deflate and base64 encode a history list
+fn encode_history(history: &[serde_json::Value]) -> Result<String, String> {
+    use base64::Engine;
+    use std::io::Write;
+    
+    let json = serde_json::to_vec(history).map_err(|e| format!("Failed to serialize history: {}", e))?;
+    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&json).map_err(|e| format!("Failed to compress history: {}", e))?;
+    let compressed = encoder.finish().map_err(|e| format!("Failed to compress history: {}", e))?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(compressed))
+}
+
+/// Decode a history list written by `encode_history`
+fn decode_history(encoded: &str) -> Result<Vec<serde_json::Value>, String> {
+    use base64::Engine;
+    use std::io::Read;
+    
+    let compressed = base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("Failed to decode history: {}", e))?;
+    let mut json = Vec::new();
+    flate2::read::DeflateDecoder::new(&compressed[..])
+        .read_to_end(&mut json)
+        .map_err(|e| format!("Failed to decompress history: {}", e))?;
+    serde_json::from_slice(&json).map_err(|e| format!("Failed to parse history: {}", e))
+}
+
+/// Encode the newest history entries that fit the configured entry and size limits
+fn bounded_history(history: &[serde_json::Value], prefs: &Preferences) -> Result<Option<String>, String> {
+    let mut kept = &history[history.len().saturating_sub(prefs.history_max_entries as usize)..];
+    loop {
+        if kept.is_empty() {
+            return Ok(None);
+        }
+        let encoded = encode_history(kept)?;
+        if encoded.len() as u64 <= prefs.history_max_bytes {
+            return Ok(Some(encoded));
+        }
+        // Drop the oldest tenth and try again
+        kept = &kept[(kept.len() / 10).max(1)..];
+    }
+}
+
+/// Save a drawing along with its undo history, bounded (or stripped) per preferences
+#[tauri::command]
+async fn save_drawing_with_history(
+    app: tauri::AppHandle,
+    path: String,
+    mut drawing: DrawingFile,
+    history: Vec<serde_json::Value>,
+) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let path = check_path_scope(&app, &path)?;
+        let prefs = load_preferences(&app)?;
+        drawing.history = if prefs.persist_history {
+            bounded_history(&history, &prefs)?
+        } else {
+            None
+        };
+        write_drawing(&path, &mut drawing)
+    })
+    .await
+}
+
+/// Read a drawing and decode its undo history; drawings without history get an empty list
+#[tauri::command]
+async fn load_drawing_with_history(app: tauri::AppHandle, path: String) -> Result<DrawingWithHistory, String> {
+    run_blocking(app, move |app| {
+        let mut drawing = read_drawing(&check_path_scope(&app, &path)?)?;
+        let checksum_warning = verify_checksum(&drawing);
+        let history = match drawing.history.take() {
+            Some(encoded) => decode_history(&encoded)?,
+            None => Vec::new(),
+        };
+        Ok(DrawingWithHistory { drawing, history, checksum_warning })
+    })
+    .await
+}
+
+/// Check that a file parses as a drawing and that its checksum, if any, matches.
+/// A checksum mismatch is only a warning since hand-edited files are legitimate.
+#[tauri::command]
+async fn validate_drawing_file(app: tauri::AppHandle, path: String) -> Result<DrawingValidation, String> {
+    run_blocking(app, move |app| {
+        let path = check_path_scope(&app, &path)?;
+        Ok(match read_drawing(&path) {
+            Ok(drawing) => DrawingValidation {
+                valid: true,
+                errors: Vec::new(),
+                checksum_warning: verify_checksum(&drawing),
+            },
+            Err(e) => DrawingValidation {
+                valid: false,
+                errors: vec![e],
+                checksum_warning: None,
+            },
+        })
+    })
+    .await
+}
+
+/// Size at which a change log is rotated; one rotated file is kept
+const CHANGE_LOG_MAX_BYTES: u64 = 5 * 1024 * 1024;
+
+/// Held across a change log append and its rotation, and while change logs are
+/// moved along with their drawing, so no entry lands in a file being renamed
+static CHANGE_LOGS: Mutex<()> = Mutex::new(());
+
+fn lock_change_logs() -> std::sync::MutexGuard<'static, ()> {
+    CHANGE_LOGS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// One line of a drawing's change log
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChangeLogEntry {
+    /// Milliseconds since the epoch
+    pub timestamp: i64,
+    pub change: serde_json::Value,
+}
+
+/// The current and the rotated change log of a drawing
+fn change_log_paths(path: &str) -> (PathBuf, PathBuf) {
+    let current = PathBuf::from(format!("{}.history.jsonl", path));
+    let rotated = PathBuf::from(format!("{}.history.jsonl.1", path));
+    (current, rotated)
+}
+
+/// Append a change to the drawing's on-disk change log, next to the drawing
+#[tauri::command]
+async fn append_history(app: tauri::AppHandle, path: String, change: serde_json::Value) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        append_change(&path, change, CHANGE_LOG_MAX_BYTES)
+    })
+    .await
+}
+
+/// Append a change to the log of the drawing at `path`, first rotating a log that
+/// reached `max_bytes`
+fn append_change(path: &str, change: serde_json::Value, max_bytes: u64) -> Result<(), String> {
+    use std::io::Write;
+    
+    let _logs = lock_change_logs();
+    let (current, rotated) = change_log_paths(path);
+    if fs::metadata(&current).is_ok_and(|m| m.len() >= max_bytes) {
+        fs::rename(&current, &rotated).map_err(|e| format!("Failed to rotate change log: {}", e))?;
+    }
+    
+    let mut line = serde_json::to_string(&ChangeLogEntry { timestamp: timestamp_millis(), change })
+        .map_err(|e| format!("Failed to serialize change: {}", e))?;
+    line.push('\n');
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&current)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+        .map_err(|e| format!("Failed to append to change log: {}", e))
+}
+
+/// The most recent `limit` (default 100) change log entries, oldest first
+#[tauri::command]
+async fn read_history(app: tauri::AppHandle, path: String, limit: Option<u32>) -> Result<Vec<ChangeLogEntry>, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        read_changes(&path, limit.unwrap_or(100) as usize)
+    })
+    .await
+}
+
+/// The last `limit` entries of the change log of the drawing at `path`, oldest first
+fn read_changes(path: &str, limit: usize) -> Result<Vec<ChangeLogEntry>, String> {
+    // Both files are read under the lock, so a rotation can't happen in between
+    let _logs = lock_change_logs();
+    let (current, rotated) = change_log_paths(path);
+    
+    let mut entries: Vec<ChangeLogEntry> = Vec::new();
+    for log in [rotated, current] {
+        let content = match fs::read_to_string(&log) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("Failed to read change log: {}", e)),
+        };
+        // A line cut short by a crash is skipped rather than failing the whole log
+        entries.extend(content.lines().filter_map(|line| serde_json::from_str(line).ok()));
+    }
+    
+    let skip = entries.len().saturating_sub(limit);
+    Ok(entries.split_off(skip))
+}
+
+/// Move the change logs of a drawing to follow it to `new_path`
+fn relocate_change_logs(old_path: &str, new_path: &str) -> Result<(), String> {
+    let _logs = lock_change_logs();
+    let (old_current, old_rotated) = change_log_paths(old_path);
+    let (new_current, new_rotated) = change_log_paths(new_path);
+    for (from, to) in [(old_current, new_current), (old_rotated, new_rotated)] {
+        if from.exists() {
+            fs::rename(&from, &to).map_err(|e| format!("Failed to move change log: {}", e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Embed a file (image, font, …) in a drawing under `asset_id`, replacing any
+/// asset with the same id
+#[tauri::command]
+async fn add_asset(
+    app: tauri::AppHandle,
+    queue: tauri::State<'_, WriteQueue>,
+    path: String,
+    asset_id: String,
+    file_path: String,
+) -> Result<(), String> {
+    if asset_id.trim().is_empty() {
+        return Err("Asset id must not be empty".to_string());
+    }
+    edit_drawing(app, &queue, path, move |app, drawing| {
+        embed_asset(drawing, asset_id, &check_path_scope(app, &file_path)?)
+    })
+    .await
+}
+
+/// Embed the file at `file_path` in `drawing` under `asset_id`
+fn embed_asset(drawing: &mut DrawingFile, asset_id: String, file_path: &Path) -> Result<(), String> {
+    use base64::Engine;
+    
+    let bytes = fs::read(file_path).map_err(|e| format!("Failed to read {}: {}", file_path.display(), e))?;
+    drawing
+        .assets
+        .insert(asset_id, base64::engine::general_purpose::STANDARD.encode(bytes));
+    Ok(())
+}
+
+/// Get the bytes of an asset embedded with `add_asset`
+#[tauri::command]
+async fn get_asset(app: tauri::AppHandle, path: String, asset_id: String) -> Result<Vec<u8>, String> {
+    run_blocking(app, move |app| {
+        use base64::Engine;
+        
+        check_path_scope(&app, &path)?;
+        let drawing = read_drawing(Path::new(&path))?;
+        let encoded = drawing
+            .assets
+            .get(&asset_id)
+            .ok_or_else(|| format!("Asset not found: {}", asset_id))?;
+        base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|e| format!("Failed to decode asset {}: {}", asset_id, e))
+    })
+    .await
+}
+
+/// Whether two drawings have the same content, regardless of timestamps and formatting
+#[tauri::command]
+async fn drawings_are_equal(app: tauri::AppHandle, path_a: String, path_b: String) -> Result<bool, String> {
+    run_blocking(app, move |app| {
+        let a = read_drawing(&check_path_scope(&app, &path_a)?)?;
+        let b = read_drawing(&check_path_scope(&app, &path_b)?)?;
+        Ok(stores_are_equal(&a.store, &b.store))
+    })
+    .await
+}
+
+/// Whether two stores are equal in canonical form, so neither key order nor `1`
+/// written as `1.0` counts as a difference
+fn stores_are_equal(a: &serde_json::Value, b: &serde_json::Value) -> bool {
+    let (mut canonical_a, mut canonical_b) = (String::new(), String::new());
+    write_canonical_json(a, &mut canonical_a);
+    write_canonical_json(b, &mut canonical_b);
+    canonical_a == canonical_b
+}
+
+/// Error prefix for a drawing with nothing left to salvage
+const ERR_UNRECOVERABLE: &str = "UNRECOVERABLE";
+
+/// Parse the longest prefix of truncated JSON that is valid once its open
+/// containers are closed. Returns the value and the length of the prefix used.
+fn salvage_json(text: &str) -> Option<(serde_json::Value, usize)> {
+    // Only the last few cut points are worth trying; each attempt is a full parse
+    const MAX_ATTEMPTS: usize = 64;
+    
+    let mut closers: Vec<u8> = Vec::new();
+    let mut candidates: std::collections::VecDeque<(usize, Vec<u8>)> = std::collections::VecDeque::new();
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, byte) in text.bytes().enumerate() {
+        if in_string {
+            match byte {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match byte {
+            b'"' => in_string = true,
+            b'{' => closers.push(b'}'),
+            b'[' => closers.push(b']'),
+            // After a complete container, or before the comma following a complete value
+            b'}' | b']' => {
+                closers.pop();
+                candidates.push_back((i + 1, closers.clone()));
+            }
+            b',' => candidates.push_back((i, closers.clone())),
+            _ => continue,
+        }
+        if candidates.len() > MAX_ATTEMPTS {
+            candidates.pop_front();
+        }
+    }
+    
+    candidates.into_iter().rev().find_map(|(end, open)| {
+        let mut repaired = text[..end].to_string();
+        repaired.extend(open.iter().rev().map(|&c| c as char));
+        serde_json::from_str(&repaired).ok().map(|value| (value, end))
+    })
+}
+
+/// Salvage what's left of a drawing whose file was cut short, e.g. by a save
+/// interrupted before saves became atomic
+#[tauri::command]
+async fn recover_truncated_drawing(app: tauri::AppHandle, path: String) -> Result<RecoveredDrawing, String> {
+    run_blocking(app, move |app| {
+        recover_drawing(&check_path_scope(&app, &path)?)
+    })
+    .await
+}
+
+/// The salvage behind `recover_truncated_drawing`
+fn recover_drawing(path: &Path) -> Result<RecoveredDrawing, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    if bytes.starts_with(ENCRYPTED_MAGIC) {
+        return Err(format!("{}: {} is password protected", ERR_PASSWORD_REQUIRED, path.display()));
+    }
+    let bytes = if bytes.starts_with(&GZIP_MAGIC) { gunzip(&bytes)? } else { bytes };
+    // The cut may have split a multi-byte character
+    let text = String::from_utf8_lossy(&bytes);
+    
+    if let Ok(drawing) = serde_json::from_str::<DrawingFile>(&text) {
+        return Ok(RecoveredDrawing { drawing, bytes_dropped: 0, warning: None });
+    }
+    
+    let unrecoverable = || format!("{}: no usable drawing data in {}", ERR_UNRECOVERABLE, path.display());
+    let (mut value, end) = salvage_json(&text).ok_or_else(unrecoverable)?;
+    let object = value.as_object_mut().ok_or_else(unrecoverable)?;
+    if object.get("store").and_then(|s| s.as_object()).is_none_or(|s| s.is_empty()) {
+        return Err(unrecoverable());
+    }
+    
+    // Fill in whatever header fields were lost
+    let now = timestamp_millis();
+    let name = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    object.entry("version").or_insert(serde_json::json!(CURRENT_DRAWING_VERSION));
+    object.entry("name").or_insert(serde_json::json!(name));
+    for (key, alias) in [("createdAt", "created_at"), ("updatedAt", "updated_at")] {
+        if !object.contains_key(key) && !object.contains_key(alias) {
+            object.insert(key.to_string(), serde_json::json!(now));
+        }
+    }
+    // The checksum was computed over the complete store
+    object.remove("checksum");
+    
+    let drawing: DrawingFile = serde_json::from_value(value)
+        .map_err(|e| format!("{}: {}", ERR_UNRECOVERABLE, e))?;
+    let bytes_dropped = (text.len() - end) as u64;
+    
+    Ok(RecoveredDrawing {
+        drawing,
+        bytes_dropped,
+        warning: Some(format!(
+            "The file was truncated; the last {} bytes could not be recovered and some shapes may be missing",
+            bytes_dropped
+        )),
+    })
+}
+
+/// Decode a CBOR-encoded drawing
+fn read_cbor(path: &Path) -> Result<DrawingFile, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    ciborium::de::from_reader(&bytes[..]).map_err(|e| format!("Failed to parse CBOR drawing: {}", e))
+}
+
+/// Convert a JSON drawing to CBOR, returning the size of the written file
+#[tauri::command]
+async fn convert_drawing_to_cbor(app: tauri::AppHandle, path: String, output_path: String) -> Result<u64, String> {
+    run_blocking(app, move |app| {
+        let drawing = read_drawing(&check_path_scope(&app, &path)?)?;
+        let output_path = check_path_scope(&app, &output_path)?;
+        
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&drawing, &mut bytes)
+            .map_err(|e| format!("Failed to encode CBOR: {}", e))?;
+        write_atomic(&output_path, &bytes, false)?;
+        
+        Ok(bytes.len() as u64)
+    })
+    .await
+}
+
+/// Convert a CBOR drawing back to JSON, returning the size of the written file
+#[tauri::command]
+async fn convert_drawing_from_cbor(app: tauri::AppHandle, path: String, output_path: String) -> Result<u64, String> {
+    run_blocking(app, move |app| {
+        let drawing = read_cbor(&check_path_scope(&app, &path)?)?;
+        let output_path = check_path_scope(&app, &output_path)?;
+        
+        let content = serde_json::to_string_pretty(&drawing)
+            .map_err(|e| format!("Failed to serialize drawing: {}", e))?;
+        write_atomic(&output_path, content.as_bytes(), false)?;
+        
+        Ok(content.len() as u64)
+    })
+    .await
+}
+
+/// Read a CBOR drawing as the same JSON text `read_file` returns for `.jamal` files
+#[tauri::command]
+async fn read_cbor_drawing(app: tauri::AppHandle, path: String) -> Result<String, String> {
+    run_blocking(app, move |app| {
+        let drawing = read_cbor(&check_path_scope(&app, &path)?)?;
+        serde_json::to_string_pretty(&drawing).map_err(|e| format!("Failed to serialize drawing: {}", e))
+    })
+    .await
+}
+
+/// Newest drawing format version this build understands
+const CURRENT_DRAWING_VERSION: u32 = 1;
+
+/// Bring a drawing up to the current format version; returns whether anything changed
+fn migrate_drawing(drawing: &mut DrawingFile) -> Result<bool, String> {
+    if drawing.version > CURRENT_DRAWING_VERSION {
+        return Err(format!(
+            "Drawing was created by a newer version of Jamal (format {}, supported {})",
+            drawing.version, CURRENT_DRAWING_VERSION
+        ));
+    }
+    if drawing.version == CURRENT_DRAWING_VERSION {
+        return Ok(false);
+    }
+    // Version 0 files predate versioning and share the version 1 layout
+    drawing.version = CURRENT_DRAWING_VERSION;
+    Ok(true)
+}
+
+/// Validate, migrate and hash a drawing and mark it opened, for bootstrapping a new window
+#[tauri::command]
+async fn prepare_open(
+    app: tauri::AppHandle,
+    path: String,
+    allow_large: Option<bool>,
+    lossy: Option<bool>,
+) -> Result<OpenPayload, String> {
+    let target = path.clone();
+    let payload = run_blocking(app.clone(), move |app| {
+        check_path_scope(&app, &target)?;
+        let limit = read_size_limit(&app, allow_large)?;
+        open_payload(Path::new(&target), limit, lossy.unwrap_or(false))
+    })
+    .await?;
+    add_recent_file(app, path, payload.name.clone()).await?;
+    Ok(payload)
+}
+
+/// Read, migrate and hash the drawing at `path` for `prepare_open`
+fn open_payload(path: &Path, limit: Option<u64>, lossy: bool) -> Result<OpenPayload, String> {
+    if !path.is_file() {
+        return Err(format!("File not found: {}", path.display()));
+    }
+    let TextContent { text: mut content, size_warning, replaced_bytes } = read_text_limited(path, limit, lossy)?;
+    let mut drawing: DrawingFile = serde_json::from_str(&content)
+        .map_err(|e| format!("Failed to parse drawing: {}", e))?;
+    
+    if migrate_drawing(&mut drawing)? {
+        content = serde_json::to_string_pretty(&drawing)
+            .map_err(|e| format!("Failed to serialize drawing: {}", e))?;
+    }
+    
+    let read_only_cause = read_only_cause(path);
+    Ok(OpenPayload {
+        hash: sha256_hex(content.as_bytes()),
+        content,
+        version: drawing.version,
+        name: drawing.name,
+        writable: read_only_cause.is_none(),
+        read_only_cause,
+        size_warning,
+        replaced_bytes: replaced_bytes as u64,
+    })
+}
+
+/// Ask the user, with a native prompt the webview can't answer itself, to allow
+/// access to a path. Returns whether access is granted.
+#[tauri::command]
+async fn request_path_access(app: tauri::AppHandle, path: String, directory: Option<bool>) -> Result<bool, String> {
+    use tauri_plugin_dialog::{DialogExt, MessageDialogButtons, MessageDialogKind};
+    
+    if check_path_scope(&app, &path).is_ok() {
+        return Ok(true);
+    }
+    let normalized = normalize_scope_path(&path)?;
+    let directory = directory.unwrap_or(false);
+    
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    app.dialog()
+        .message(format!(
+            "Allow Jamal to read and write {}{}?",
+            normalized.display(),
+            if directory { " and everything in it" } else { "" }
+        ))
+        .title("File access")
+        .kind(MessageDialogKind::Warning)
+        .buttons(MessageDialogButtons::OkCancelCustom("Allow".to_string(), "Deny".to_string()))
+        .show(move |allowed| {
+            let _ = tx.send(allowed);
+        });
+    let allowed = rx.await.unwrap_or(false);
+    
+    if allowed {
+        run_blocking(app, move |app| grant_path(&app, &path, directory)).await?;
+    }
+    Ok(allowed)
+}
+
+/// Show a native open or save dialog for drawings and grant access to the chosen path
+#[tauri::command]
+async fn pick_drawing_path(
+    app: tauri::AppHandle,
+    save: Option<bool>,
+    default_name: Option<String>,
+) -> Result<Option<String>, String> {
+    use tauri_plugin_dialog::DialogExt;
+    
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let dialog = app.dialog().file().add_filter("Jamal Drawing", &["jamal"]);
+    if save.unwrap_or(false) {
+        let dialog = match default_name {
+            Some(name) => dialog.set_file_name(name),
+            None => dialog,
+        };
+        dialog.save_file(move |picked| {
+            let _ = tx.send(picked);
+        });
+    } else {
+        dialog.pick_file(move |picked| {
+            let _ = tx.send(picked);
+        });
+    }
+    
+    let picked = match rx.await.ok().flatten() {
+        Some(picked) => picked
+            .into_path()
+            .map_err(|e| format!("Failed to read selected path: {}", e))?,
+        None => return Ok(None),
+    };
+    let path = picked.to_string_lossy().to_string();
+    run_blocking(app, move |app| {
+        grant_path(&app, &path, false)?;
+        Ok(Some(path))
+    })
+    .await
+}
+
+/// Show a native save dialog for an export (`png`, `svg` or `pdf`) and grant access
+/// to the chosen path
+#[tauri::command]
+async fn pick_export_path(
+    app: tauri::AppHandle,
+    format: String,
+    default_name: Option<String>,
+) -> Result<Option<String>, String> {
+    use tauri_plugin_dialog::DialogExt;
+    
+    let filter = match format.as_str() {
+        "png" => "PNG Image",
+        "svg" => "SVG Image",
+        "pdf" => "PDF Document",
+        _ => return Err(format!("Unsupported export format: {}", format)),
+    };
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let dialog = app.dialog().file().add_filter(filter, &[format.as_str()]);
+    let dialog = match default_name {
+        Some(name) => dialog.set_file_name(name),
+        None => dialog,
+    };
+    dialog.save_file(move |picked| {
+        let _ = tx.send(picked);
+    });
+    
+    let picked = match rx.await.ok().flatten() {
+        Some(picked) => picked
+            .into_path()
+            .map_err(|e| format!("Failed to read selected path: {}", e))?,
+        None => return Ok(None),
+    };
+    let path = picked.to_string_lossy().to_string();
+    run_blocking(app, move |app| {
+        grant_path(&app, &path, false)?;
+        Ok(Some(path))
+    })
+    .await
+}
+
+/// Log file name inside the app data directory
+const LOG_FILE_NAME: &str = "jamal.log";
+
+/// Writer for the log file layer; discards output while file logging is off
+#[derive(Clone, Default)]
+struct LogFileWriter(Arc<Mutex<Option<tracing_appender::non_blocking::NonBlocking>>>);
+
+impl<'a> tracing_subscriber::fmt::MakeWriter<'a> for LogFileWriter {
+    type Writer = Box<dyn std::io::Write + 'a>;
+    
+    fn make_writer(&'a self) -> Self::Writer {
+        match self.0.lock().ok().and_then(|file| file.clone()) {
+            Some(file) => Box::new(file),
+            None => Box::new(std::io::sink()),
+        }
+    }
+}
+
+/// The installed subscriber's handles, so logging can be reconfigured at runtime
+#[derive(Default)]
+pub struct Logging {
+    level: Mutex<Option<tracing_subscriber::reload::Handle<tracing_subscriber::filter::LevelFilter, tracing_subscriber::Registry>>>,
+    file: LogFileWriter,
+    /// Keeps the background log file writer flushing
+    guard: Mutex<Option<tracing_appender::non_blocking::WorkerGuard>>,
+}
+
+/// Set the log level ("trace", "debug", "info", "warn" or "error") and whether logs
+/// are also appended to `jamal.log` in the app data directory. Can be called again
+/// to change either.
+#[tauri::command]
+async fn init_logging(
+    app: tauri::AppHandle,
+    logging: tauri::State<'_, Logging>,
+    level: String,
+    log_to_file: bool,
+) -> Result<(), String> {
+    use tracing_subscriber::filter::LevelFilter;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+    
+    let filter = match level.to_ascii_lowercase().as_str() {
+        "trace" => LevelFilter::TRACE,
+        "debug" => LevelFilter::DEBUG,
+        "info" => LevelFilter::INFO,
+        "warn" => LevelFilter::WARN,
+        "error" => LevelFilter::ERROR,
+        _ => return Err(format!("Invalid log level: {}", level)),
+    };
+    
+    let mut handle = logging.level.lock().map_err(|_| "Logging state is poisoned".to_string())?;
+    match handle.as_ref() {
+        Some(handle) => handle
+            .reload(filter)
+            .map_err(|e| format!("Failed to change log level: {}", e))?,
+        None => {
+            let (filter_layer, reload_handle) = tracing_subscriber::reload::Layer::new(filter);
+            tracing_subscriber::registry()
+                .with(filter_layer)
+                .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+                .with(tracing_subscriber::fmt::layer().with_ansi(false).with_writer(logging.file.clone()))
+                .try_init()
+                .map_err(|e| format!("Failed to initialize logging: {}", e))?;
+            *handle = Some(reload_handle);
+        }
+    }
+    
+    let mut guard = logging.guard.lock().map_err(|_| "Logging state is poisoned".to_string())?;
+    let mut file = logging.file.0.lock().map_err(|_| "Logging state is poisoned".to_string())?;
+    if log_to_file && file.is_none() {
+        let appender = tracing_appender::rolling::RollingFileAppender::builder()
+            .rotation(tracing_appender::rolling::Rotation::NEVER)
+            .filename_prefix(LOG_FILE_NAME)
+            .build(get_app_data_dir(&app)?)
+            .map_err(|e| format!("Failed to open log file: {}", e))?;
+        let (writer, worker) = tracing_appender::non_blocking(appender);
+        *file = Some(writer);
+        *guard = Some(worker);
+    } else if !log_to_file {
+        *file = None;
+        *guard = None;
+    }
+    
+    tracing::debug!("Logging at {} level, to file: {}", filter, log_to_file);
+    Ok(())
+}
+
+/// Path of the log file written when file logging is on
+#[tauri::command]
+async fn get_log_path(app: tauri::AppHandle) -> Result<String, String> {
+    Ok(get_app_data_file(&app, LOG_FILE_NAME)?.to_string_lossy().to_string())
+}
+
+/// Most of each log file that goes into a support bundle, taken from its end
+const SUPPORT_LOG_TAIL_BYTES: u64 = 1024 * 1024;
+
+/// Directories whose paths shouldn't leave the machine in a support bundle, longest
+/// first so the most specific prefix is stripped
+fn private_dirs<R: Runtime>(app: &tauri::AppHandle<R>, recent: &[RecentFile], prefs: &Preferences) -> Vec<String> {
+    let mut dirs: Vec<PathBuf> = recent
+        .iter()
+        .filter_map(|f| Path::new(&f.path).parent().map(Path::to_path_buf))
+        .collect();
+    dirs.extend(app.path().home_dir().ok());
+    dirs.extend(get_app_data_dir(app).ok());
+    dirs.extend(prefs.backup_directory.as_ref().map(PathBuf::from));
+    dirs.push(std::env::temp_dir());
+    
+    let mut dirs: Vec<String> = dirs
+        .into_iter()
+        .map(|dir| dir.to_string_lossy().trim_end_matches(['/', '\\']).to_string())
+        .filter(|dir| !dir.is_empty())
+        .collect();
+    dirs.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+    dirs.dedup();
+    dirs
+}
+
+/// Characters that end a path token in `scrub_private_paths`
+const PATH_TOKEN_END: &str = r#"\s"'<>|:,)\]"#;
+
+/// Cut every absolute path in `text` down to its file name; a bare directory is cut
+/// to its last component. Paths under `dirs` are found even when they contain spaces
+fn scrub_private_paths(text: &str, dirs: &[String]) -> String {
+    static ABSOLUTE_PATH: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let last_component = |path: &str| {
+        path.trim_end_matches(['/', '\\'])
+            .rsplit(['/', '\\'])
+            .next()
+            .unwrap_or_default()
+            .to_string()
+    };
+    
+    let mut text = text.to_string();
+    for dir in dirs {
+        if !text.contains(dir.as_str()) {
+            continue;
+        }
+        // The directory must end at a separator or the end of the path, so `/home/al`
+        // doesn't match inside `/home/alice`
+        let pattern = format!(r#"{}((?:[/\\][^{end}]*)?)([^\w.-]|$)"#, regex::escape(dir), end = PATH_TOKEN_END);
+        if let Ok(pattern) = regex::Regex::new(&pattern) {
+            text = pattern
+                .replace_all(&text, |caps: &regex::Captures| {
+                    format!("{}{}", last_component(&format!("{}{}", dir, &caps[1])), &caps[2])
+                })
+                .to_string();
+        }
+    }
+    
+    let absolute_path = ABSOLUTE_PATH.get_or_init(|| {
+        let pattern = format!(r#"(^|[\s"'=(\[<,])((?:[A-Za-z]:[/\\]|\\\\|~?/)[^{}]+)"#, PATH_TOKEN_END);
+        regex::Regex::new(&pattern).expect("valid path pattern")
+    });
+    absolute_path
+        .replace_all(&text, |caps: &regex::Captures| format!("{}{}", &caps[1], last_component(&caps[2])))
+        .to_string()
+}
+
+/// `scrub_private_paths` on every string in a JSON value
+fn scrub_json_paths(value: &mut serde_json::Value, dirs: &[String]) {
+    match value {
+        serde_json::Value::String(text) => *text = scrub_private_paths(text, dirs),
+        serde_json::Value::Array(items) => items.iter_mut().for_each(|item| scrub_json_paths(item, dirs)),
+        serde_json::Value::Object(fields) => fields.values_mut().for_each(|field| scrub_json_paths(field, dirs)),
+        _ => {}
+    }
+}
+
+/// Write a zip for attaching to a bug report: app and OS versions, the recent files
+/// list, preferences and the end of each log file, with paths cut down to file
+/// names. The recent drawings themselves are only added with `include_drawings`.
+#[tauri::command]
+async fn create_support_bundle(
+    app: tauri::AppHandle,
+    output_path: String,
+    include_drawings: Option<bool>,
+) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        let output = check_path_scope(&app, &output_path)?;
+        let recent = load_recent_files(&app)?;
+        let prefs = load_preferences(&app)?;
+        let system = serde_json::json!({
+            "app_version": app.package_info().version.to_string(),
+            "tauri_version": tauri::VERSION,
+            "os": std::env::consts::OS,
+            "os_family": std::env::consts::FAMILY,
+            "arch": std::env::consts::ARCH,
+            "created_at": chrono_timestamp(),
+        });
+        
+        let entries = support_bundle_entries(
+            &get_app_data_dir(&app)?,
+            system,
+            &recent,
+            &prefs,
+            &private_dirs(&app, &recent, &prefs),
+            include_drawings.unwrap_or(false),
+        )?;
+        write_atomic(&output, &zip_entries(&entries)?, false)
+    })
+    .await
+}
+
+/// The files of a support bundle, by name: `system`, the recent files and
+/// preferences with `dirs` scrubbed from them, the end of each log file in `data_dir`
+/// and, with `include_drawings`, the recent drawings themselves
+fn support_bundle_entries(
+    data_dir: &Path,
+    system: serde_json::Value,
+    recent: &[RecentFile],
+    prefs: &Preferences,
+    dirs: &[String],
+    include_drawings: bool,
+) -> Result<Vec<(String, Vec<u8>)>, String> {
+    use std::io::{Read, Seek};
+    
+    let file_name = |path: &str| {
+        Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    let to_json = |value: &serde_json::Value| {
+        serde_json::to_vec_pretty(value).map_err(|e| format!("Failed to serialize support data: {}", e))
+    };
+    
+    let mut recent_files = serde_json::to_value(recent).map_err(|e| format!("Failed to serialize recent files: {}", e))?;
+    for (entry, file) in recent_files.as_array_mut().into_iter().flatten().zip(recent) {
+        entry["path"] = serde_json::json!(file_name(&file.path));
+    }
+    scrub_json_paths(&mut recent_files, dirs);
+    let mut preferences = serde_json::to_value(prefs).map_err(|e| format!("Failed to serialize preferences: {}", e))?;
+    scrub_json_paths(&mut preferences, dirs);
+    
+    let mut entries: Vec<(String, Vec<u8>)> = vec![
+        ("system.json".to_string(), to_json(&system)?),
+        ("recent_files.json".to_string(), to_json(&recent_files)?),
+        ("preferences.json".to_string(), to_json(&preferences)?),
+    ];
+    let mut logs: Vec<PathBuf> = fs::read_dir(data_dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(LOG_FILE_NAME))
+        .map(|entry| entry.path())
+        .collect();
+    logs.sort();
+    for path in logs {
+        let name = file_name(&path.to_string_lossy());
+        let mut log = Vec::new();
+        let read_tail = fs::File::open(&path).and_then(|mut file| {
+            let len = file.metadata()?.len();
+            file.seek(std::io::SeekFrom::Start(len.saturating_sub(SUPPORT_LOG_TAIL_BYTES)))?;
+            file.read_to_end(&mut log)
+        });
+        if let Err(e) = read_tail {
+            tracing::warn!("Leaving {} out of the support bundle: {}", name, e);
+            continue;
+        }
+        let log = scrub_private_paths(&String::from_utf8_lossy(&log), dirs);
+        entries.push((format!("logs/{}", name), log.into_bytes()));
+    }
+    if include_drawings {
+        for (index, file) in recent.iter().enumerate() {
+            // Relative or missing entries are skipped; the list above still names them
+            if let Ok(content) = fs::read(&file.path) {
+                entries.push((format!("drawings/{:02}-{}", index + 1, file_name(&file.path)), content));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Pack named files into a zip archive in memory
+fn zip_entries(entries: &[(String, Vec<u8>)]) -> Result<Vec<u8>, String> {
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for (name, data) in entries {
+        zip.start_file(name.as_str(), options)
+            .and_then(|_| zip.write_all(data).map_err(Into::into))
+            .map_err(|e| format!("Failed to write {}: {}", name, e))?;
+    }
+    let bundle = zip.finish().map_err(|e| format!("Failed to finish support bundle: {}", e))?;
+    Ok(bundle.into_inner())
+}
+
+/// Forward every `DrawingEvent` to the frontend over `channel` until it goes away
+#[tauri::command]
+async fn subscribe_events(
+    bus: tauri::State<'_, DrawingEventBus>,
+    channel: tauri::ipc::Channel<DrawingEvent>,
+) -> Result<(), String> {
+    use tokio::sync::broadcast::error::RecvError;
+    
+    let mut receiver = bus.0.subscribe();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            match receiver.recv().await {
+                Ok(event) => {
+                    if channel.send(event).is_err() {
+                        break;
+                    }
+                }
+                // A slow subscriber misses events rather than stalling publishers
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Size, modification time and writability of a file
+#[tauri::command]
+async fn get_file_info(app: tauri::AppHandle, path: String) -> Result<FileInfo, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        let metadata = fs::metadata(&path).map_err(|e| format!("Failed to read file info: {}", e))?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let read_only_cause = read_only_cause(Path::new(&path));
+        
+        Ok(FileInfo {
+            size: metadata.len(),
+            modified,
+            writable: read_only_cause.is_none(),
+            read_only_cause,
+            path,
+        })
+    })
+    .await
+}
+
+/// Remove the read-only attribute from a file (Windows only)
+#[tauri::command]
+async fn clear_readonly_attribute(app: tauri::AppHandle, path: String) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        
+        #[cfg(windows)]
+        {
+            let mut permissions = fs::metadata(&path)
+                .map_err(|e| format!("Failed to read file info: {}", e))?
+                .permissions();
+            permissions.set_readonly(false);
+            fs::set_permissions(&path, permissions)
+                .map_err(|e| format!("Failed to clear read-only attribute: {}", e))
+        }
+        
+        #[cfg(not(windows))]
+        {
+            let _ = path;
+            Err("Clearing the read-only attribute is only supported on Windows".to_string())
+        }
+    })
+    .await
+}
+
+/// Start of an encrypted drawing envelope
+const ENCRYPTED_MAGIC: &[u8] = b"JAMALENC";
+
+/// Error prefixes the frontend matches on for encrypted files
+const ERR_PASSWORD_REQUIRED: &str = "PASSWORD_REQUIRED";
+const ERR_WRONG_PASSWORD: &str = "WRONG_PASSWORD";
+const ERR_CORRUPT_FILE: &str = "CORRUPT_FILE";
+
+/// Argon2id parameters for new envelopes: 64 MiB, 3 passes, 1 lane
+const KDF_MEMORY_KIB: u32 = 64 * 1024;
+const KDF_ITERATIONS: u32 = 3;
+const KDF_PARALLELISM: u32 = 1;
+
+/// Derive the encryption key and a password verifier. The verifier lets a wrong
+/// password be told apart from a damaged ciphertext.
+fn derive_envelope_keys(password: &str, salt: &[u8], m_cost: u32, t_cost: u32, p_cost: u32) -> Result<([u8; 32], String), String> {
+    let params = argon2::Params::new(m_cost, t_cost, p_cost, Some(64))
+        .map_err(|e| format!("{}: invalid key derivation parameters: {}", ERR_CORRUPT_FILE, e))?;
+    let argon = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+    
+    let mut derived = [0u8; 64];
+    argon
+        .hash_password_into(password.as_bytes(), salt, &mut derived)
+        .map_err(|e| format!("Failed to derive key: {}", e))?;
+    
+    let mut key = [0u8; 32];
+    key.copy_from_slice(&derived[..32]);
+    Ok((key, sha256_hex(&derived[32..])))
+}
+
+/// Encrypt content into the envelope format:
+/// magic | format (1) | m_cost, t_cost, p_cost (u32 LE) | salt (16) | verifier (32) | nonce (24) | ciphertext
+fn encrypt_envelope(plaintext: &[u8], password: &str) -> Result<Vec<u8>, String> {
+    use chacha20poly1305::aead::{Aead, KeyInit};
+    
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 24];
+    getrandom::getrandom(&mut salt).map_err(|e| format!("Failed to generate salt: {}", e))?;
+    getrandom::getrandom(&mut nonce).map_err(|e| format!("Failed to generate nonce: {}", e))?;
+    
+    let (key, verifier) = derive_envelope_keys(password, &salt, KDF_MEMORY_KIB, KDF_ITERATIONS, KDF_PARALLELISM)?;
+    let cipher = chacha20poly1305::XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&key));
+    let ciphertext = cipher
+        .encrypt(chacha20poly1305::XNonce::from_slice(&nonce), plaintext)
+        .map_err(|_| "Failed to encrypt file".to_string())?;
+    
+    let mut envelope = Vec::with_capacity(93 + ciphertext.len());
+    envelope.extend_from_slice(ENCRYPTED_MAGIC);
+    envelope.push(1);
+    for param in [KDF_MEMORY_KIB, KDF_ITERATIONS, KDF_PARALLELISM] {
+        envelope.extend_from_slice(&param.to_le_bytes());
+    }
+    envelope.extend_from_slice(&salt);
+    envelope.extend_from_slice(&hex_to_bytes(&verifier));
+    envelope.extend_from_slice(&nonce);
+    envelope.extend_from_slice(&ciphertext);
+    Ok(envelope)
+}
+
+/// Decode a hex string produced by `sha256_hex`
+fn hex_to_bytes(hex: &str) -> Vec<u8> {
+    (0..hex.len() / 2)
+        .filter_map(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok())
+        .collect()
+}
+
+/// Decrypt an envelope written by `encrypt_envelope`
+fn decrypt_envelope(envelope: &[u8], password: &str) -> Result<Vec<u8>, String> {
+    use chacha20poly1305::aead::{Aead, KeyInit};
+    
+    const HEADER_LEN: usize = 8 + 1 + 12 + 16 + 32 + 24;
+    if !envelope.starts_with(ENCRYPTED_MAGIC) {
+        return Err(format!("{}: not an encrypted drawing", ERR_CORRUPT_FILE));
+    }
+    if envelope.len() < HEADER_LEN || envelope[8] != 1 {
+        return Err(format!("{}: unsupported or truncated encrypted file", ERR_CORRUPT_FILE));
+    }
+    
+    let param = |offset: usize| u32::from_le_bytes([envelope[offset], envelope[offset + 1], envelope[offset + 2], envelope[offset + 3]]);
+    let (m_cost, t_cost, p_cost) = (param(9), param(13), param(17));
+    // The costs come from the file; without a cap a crafted header could make the
+    // key derivation take gigabytes of memory or hours of CPU
+    if m_cost > KDF_MEMORY_KIB || t_cost > KDF_ITERATIONS || p_cost > KDF_PARALLELISM {
+        return Err(format!("{}: key derivation costs exceed what Jamal writes", ERR_CORRUPT_FILE));
+    }
+    let salt = &envelope[21..37];
+    let verifier = &envelope[37..69];
+    let nonce = &envelope[69..93];
+    let ciphertext = &envelope[HEADER_LEN..];
+    
+    let (key, expected_verifier) = derive_envelope_keys(password, salt, m_cost, t_cost, p_cost)?;
+    if hex_to_bytes(&expected_verifier) != verifier {
+        return Err(format!("{}: the password is incorrect", ERR_WRONG_PASSWORD));
+    }
+    
+    let cipher = chacha20poly1305::XChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&key));
+    cipher
+        .decrypt(chacha20poly1305::XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| format!("{}: the encrypted content is damaged", ERR_CORRUPT_FILE))
+}
+
+/// Save a drawing encrypted with a password
+#[tauri::command]
+async fn save_file_encrypted(
+    app: tauri::AppHandle,
+    path: String,
+    content: String,
+    password: String,
+    create_parents: Option<bool>,
+) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        if password.is_empty() {
+            return Err("Password must not be empty".to_string());
+        }
+        check_path_scope(&app, &path)?;
+        ensure_parent_dir(&app, &path, create_parents.unwrap_or(true))?;
+        let envelope = encrypt_envelope(content.as_bytes(), &password)?;
+        write_atomic(Path::new(&path), &envelope, false).map_err(|e| format!("Failed to save file: {}", e))
+    })
+    .await
+}
+
+/// Read a password protected drawing
+#[tauri::command]
+async fn read_file_encrypted(app: tauri::AppHandle, path: String, password: String) -> Result<String, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        let envelope = fs::read(&path).map_err(|e| format!("Failed to read file: {}", e))?;
+        let plaintext = decrypt_envelope(&envelope, &password)?;
+        String::from_utf8(plaintext).map_err(|e| format!("{}: {}", ERR_CORRUPT_FILE, e))
+    })
+    .await
+}
+
+/// Re-encrypt a drawing under a new password without the content leaving the backend
+#[tauri::command]
+async fn change_file_password(
+    app: tauri::AppHandle,
+    path: String,
+    old_password: String,
+    new_password: String,
+) -> Result<(), String> {
+    run_blocking(app, move |app| {
+        if new_password.is_empty() {
+            return Err("Password must not be empty".to_string());
+        }
+        check_path_scope(&app, &path)?;
+        let envelope = fs::read(&path).map_err(|e| format!("Failed to read file: {}", e))?;
+        let plaintext = decrypt_envelope(&envelope, &old_password)?;
+        let envelope = encrypt_envelope(&plaintext, &new_password)?;
+        write_atomic(Path::new(&path), &envelope, false).map_err(|e| format!("Failed to save file: {}", e))
+    })
+    .await
+}
+
+/// Locks older than this are considered abandoned; holders refresh by re-acquiring
+const LOCK_STALE_AFTER_SECS: i64 = 8 * 60 * 60;
+
+/// Path of the advisory lock sidecar of a drawing
+fn lock_path_for(path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.lock", path))
+}
+
+/// Lock metadata identifying this process
+fn current_lock_info() -> LockInfo {
+    LockInfo {
+        hostname: sysinfo::System::host_name().unwrap_or_default(),
+        user: std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_default(),
+        pid: std::process::id(),
+        acquired_at: chrono_timestamp(),
+    }
+}
+
+/// A lock is stale when it's too old, or its process is gone on this same machine
+fn is_lock_stale(lock: &LockInfo) -> bool {
+    if chrono_timestamp() - lock.acquired_at > LOCK_STALE_AFTER_SECS {
+        return true;
+    }
+    if lock.hostname != sysinfo::System::host_name().unwrap_or_default() {
+        return false;
+    }
+    let pid = sysinfo::Pid::from_u32(lock.pid);
+    let mut system = sysinfo::System::new();
+    system.refresh_processes(sysinfo::ProcessesToUpdate::Some(&[pid]), true);
+    system.process(pid).is_none()
+}
+
+/// Take the advisory lock on a drawing. A fresh lock held by someone else is left alone
+/// and its holder returned; stale locks (or any lock, with `force`) are taken over.
+/// Re-acquiring our own lock refreshes its timestamp.
+#[tauri::command]
+async fn acquire_file_lock(
+    app: tauri::AppHandle,
+    path: String,
+    force: Option<bool>,
+) -> Result<LockStatus, String> {
+    run_blocking(app, move |app| {
+        use std::io::Write;
+        
+        check_path_scope(&app, &path)?;
+        let lock_path = lock_path_for(&path);
+        let ours = current_lock_info();
+        
+        if let Ok(content) = fs::read_to_string(&lock_path) {
+            // An unreadable lock file can't name a holder, so treat it as abandoned
+            if let Ok(holder) = serde_json::from_str::<LockInfo>(&content) {
+                let is_ours = holder.hostname == ours.hostname && holder.pid == ours.pid;
+                let stale = !is_ours && is_lock_stale(&holder);
+                if !is_ours && !stale && !force.unwrap_or(false) {
+                    return Ok(LockStatus { acquired: false, holder: Some(holder), stale: false });
+                }
+            }
+            write_json(&lock_path, &ours)?;
+        } else {
+            let content = serde_json::to_string_pretty(&ours)
+                .map_err(|e| format!("Failed to serialize lock: {}", e))?;
+            let created = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lock_path)
+                .and_then(|mut file| file.write_all(content.as_bytes()));
+            if let Err(e) = created {
+                // Someone else created it between our read and write
+                if e.kind() == std::io::ErrorKind::AlreadyExists {
+                    let holder = fs::read_to_string(&lock_path)
+                        .ok()
+                        .and_then(|content| serde_json::from_str(&content).ok());
+                    return Ok(LockStatus { acquired: false, holder, stale: false });
+                }
+                return Err(format!("Failed to create lock file: {}", e));
+            }
+        }
+        
+        app.state::<HeldLocks>().0.lock().map_err(|_| "Lock registry is poisoned".to_string())?.insert(lock_path);
+        Ok(LockStatus { acquired: true, holder: None, stale: false })
+    })
+    .await
+}
+
+/// Release a lock held by this process; returns whether there was one to release
+#[tauri::command]
+async fn release_file_lock(
+    app: tauri::AppHandle,
+    path: String,
+) -> Result<bool, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        let lock_path = lock_path_for(&path);
+        let ours = current_lock_info();
+        
+        let held_by_us = fs::read_to_string(&lock_path)
+            .ok()
+            .and_then(|content| serde_json::from_str::<LockInfo>(&content).ok())
+            .map(|holder| holder.hostname == ours.hostname && holder.pid == ours.pid)
+            .unwrap_or(false);
+        
+        app.state::<HeldLocks>().0.lock().map_err(|_| "Lock registry is poisoned".to_string())?.remove(&lock_path);
+        if held_by_us {
+            fs::remove_file(&lock_path).map_err(|e| format!("Failed to release lock: {}", e))?;
+        }
+        Ok(held_by_us)
+    })
+    .await
+}
+
+/// Check a drawing's lock without taking it
+#[tauri::command]
+async fn get_file_lock(app: tauri::AppHandle, path: String) -> Result<Option<LockStatus>, String> {
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        let content = match fs::read_to_string(lock_path_for(&path)) {
+            Ok(content) => content,
+            Err(_) => return Ok(None),
+        };
+        let holder: Option<LockInfo> = serde_json::from_str(&content).ok();
+        let stale = holder.as_ref().map(is_lock_stale).unwrap_or(true);
+        Ok(Some(LockStatus { acquired: false, holder, stale }))
+    })
+    .await
+}
+
+/// Read just a drawing's name. The file is parsed as a stream that stops at the
+/// top-level `name`; whatever comes before it is skipped without being kept.
+#[tauri::command]
+async fn get_drawing_name(app: tauri::AppHandle, path: String) -> Result<String, String> {
+    run_blocking(app, move |app| {
+        let path = check_path_scope(&app, &path)?;
+        let file = fs::File::open(&path).map_err(|e| format!("Failed to read file: {}", e))?;
+        read_drawing_name(&path, file)
+    })
+    .await
+}
+
+/// The reading behind `get_drawing_name`, for a gzip, CBOR or plain JSON drawing
+fn read_drawing_name(path: &Path, reader: impl std::io::Read) -> Result<String, String> {
+    use std::io::BufRead;
+    
+    let mut reader = std::io::BufReader::new(reader);
+    let head = reader.fill_buf().map_err(|e| format!("Failed to read file: {}", e))?;
+    if head.starts_with(ENCRYPTED_MAGIC) {
+        return Err(format!("{}: {} is password protected", ERR_PASSWORD_REQUIRED, path.display()));
+    }
+    let container = drawing_container(head);
+    if head.starts_with(b"\xEF\xBB\xBF") {
+        reader.consume(3);
+    }
+    
+    let name = match container {
+        DrawingContainer::Cbor => ciborium::de::from_reader::<NameOnly, _>(reader)
+            .map_err(|e| format!("Failed to parse CBOR drawing: {}", e))?
+            .0,
+        DrawingContainer::Gzip => json_drawing_name(std::io::BufReader::new(flate2::read::GzDecoder::new(reader)))?,
+        DrawingContainer::Json => json_drawing_name(reader)?,
+    };
+    name.ok_or_else(|| "Failed to parse drawing: missing field `name`".to_string())
+}
+
+/// Visit a drawing's top-level map up to its `name`, skipping the values before it
+fn visit_drawing_name<'de, A: serde::de::MapAccess<'de>>(mut map: A) -> Result<Option<String>, A::Error> {
+    while let Some(key) = map.next_key::<String>()? {
+        if key == "name" {
+            return map.next_value().map(Some);
+        }
+        map.next_value::<serde::de::IgnoredAny>()?;
+    }
+    Ok(None)
+}
+
+/// A drawing's name read with `visit_drawing_name`. Fine for CBOR, which doesn't
+/// mind the rest of the map going unread
+struct NameOnly(Option<String>);
+
+impl<'de> Deserialize<'de> for NameOnly {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NameOnlyVisitor;
+        
+        impl<'de> serde::de::Visitor<'de> for NameOnlyVisitor {
+            type Value = NameOnly;
+            
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a drawing")
+            }
+            
+            fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<NameOnly, A::Error> {
+                visit_drawing_name(map).map(NameOnly)
+            }
+        }
+        
+        deserializer.deserialize_map(NameOnlyVisitor)
+    }
+}
+
+/// Read a JSON drawing's name with `visit_drawing_name`. serde_json insists on
+/// reading a map to its end, so the visitor hands the name out and then stops the
+/// parse with an error
+fn json_drawing_name(reader: impl std::io::Read) -> Result<Option<String>, String> {
+    use serde::Deserializer as _;
+    
+    struct NameVisitor<'a>(&'a mut Option<String>);
+    
+    impl<'de> serde::de::Visitor<'de> for NameVisitor<'_> {
+        type Value = ();
+        
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a drawing")
+        }
+        
+        fn visit_map<A: serde::de::MapAccess<'de>>(self, map: A) -> Result<(), A::Error> {
+            *self.0 = visit_drawing_name(map)?;
+            match self.0 {
+                Some(_) => Err(serde::de::Error::custom("stopped after the name")),
+                None => Ok(()),
+            }
+        }
+    }
+    
+    let mut name = None;
+    let result = serde_json::Deserializer::from_reader(reader).deserialize_map(NameVisitor(&mut name));
+    match (name, result) {
+        (Some(name), _) => Ok(Some(name)),
+        (None, Ok(())) => Ok(None),
+        (None, Err(e)) => Err(format!("Failed to parse drawing: {}", e)),
+    }
+}
+
+/// Get a drawing's metadata without returning its store
+#[tauri::command]
+async fn get_drawing_info(app: tauri::AppHandle, path: String) -> Result<DrawingInfo, String> {
+    run_blocking(app, move |app| {
+        let path = check_path_scope(&app, &path)?;
+        let size = fs::metadata(&path)
+            .map_err(|e| format!("Failed to read file: {}", e))?
+            .len();
+        let drawing = read_drawing(&path)?;
+        
+        Ok(DrawingInfo {
+            name: drawing.name,
+            version: drawing.version,
+            created_at: drawing.created_at,
+            updated_at: drawing.updated_at,
+            session_seconds: drawing.session_seconds,
+            size,
+        })
+    })
+    .await
+}
+
+/// The records of a tldraw store, whether given as a snapshot
+/// (`{document: {store}}`), a `{store}` wrapper or the bare record map
+fn store_records(store: &serde_json::Value) -> Vec<&serde_json::Value> {
+    let records = store
+        .pointer("/document/store")
+        .or_else(|| store.get("store"))
+        .unwrap_or(store);
+    records.as_object().map(|r| r.values().collect()).unwrap_or_default()
+}
+
+/// Plain text of a shape, from `props.text` or the paragraphs of `props.richText`
+fn shape_text(shape: &serde_json::Value) -> Option<String> {
+    fn collect(node: &serde_json::Value, out: &mut String) {
+        if let Some(text) = node.get("text").and_then(|t| t.as_str()) {
+            out.push_str(text);
+        }
+        if let Some(children) = node.get("content").and_then(|c| c.as_array()) {
+            for child in children {
+                collect(child, out);
+                // Paragraphs and headings end a line
+                if child.get("content").is_some() {
+                    out.push('\n');
+                }
+            }
+        }
+    }
+    
+    let props = shape.get("props")?;
+    let text = match props.get("text").and_then(|t| t.as_str()) {
+        Some(text) => text.to_string(),
+        None => {
+            let mut text = String::new();
+            collect(props.get("richText")?, &mut text);
+            text
+        }
+    };
+    let text = text.trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// Year, month and day of a Unix timestamp in UTC
+fn civil_date(timestamp_secs: i64) -> (i64, u32, u32) {
+    // Howard Hinnant's days-to-civil algorithm
+    let days = timestamp_secs.div_euclid(86_400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Longest suggested file name, in characters, before the extension
+const MAX_SUGGESTED_NAME_CHARS: usize = 48;
+
+/// Suggest a file name from a drawing's most prominent text: the largest, then
+/// topmost text. Drawings without text get a date-based name. Accepts drawing
+/// JSON or just its store.
+#[tauri::command]
+async fn suggest_filename(content_or_store: String) -> Result<FilenameSuggestion, String> {
+    let value: serde_json::Value = serde_json::from_str(&content_or_store)
+        .map_err(|e| format!("Failed to parse drawing: {}", e))?;
+    let store = value.get("store").filter(|_| value.get("version").is_some()).unwrap_or(&value);
+    
+    let size_rank = |shape: &serde_json::Value| match shape.pointer("/props/size").and_then(|s| s.as_str()) {
+        Some("xl") => 3,
+        Some("l") => 2,
+        Some("m") => 1,
+        _ => 0,
+    };
+    let mut texts: Vec<(i32, f64, String)> = store_records(store)
+        .into_iter()
+        .filter(|record| record.get("typeName").and_then(|t| t.as_str()) == Some("shape"))
+        .filter_map(|shape| {
+            let text = shape_text(shape)?;
+            let y = shape.get("y").and_then(|y| y.as_f64()).unwrap_or(0.0);
+            Some((size_rank(shape), y, text))
+        })
+        .collect();
+    texts.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.total_cmp(&b.1)));
+    
+    let mut candidates: Vec<String> = Vec::new();
+    for (_, _, text) in texts {
+        // The first line is the heading-like part
+        let line = text.lines().next().unwrap_or("").trim();
+        let mut name: String = line.chars().take(MAX_SUGGESTED_NAME_CHARS).collect();
+        if line.chars().count() > MAX_SUGGESTED_NAME_CHARS {
+            // Cut at a word boundary when there's one reasonably close
+            if let Some(space) = name.rfind(' ').filter(|&i| i > MAX_SUGGESTED_NAME_CHARS / 2) {
+                name.truncate(space);
+            }
+        }
+        let name = sanitize_file_name(&name, "-").name;
+        if name != "Untitled" && !candidates.contains(&name) {
+            candidates.push(name);
+        }
+        if candidates.len() == 5 {
+            break;
+        }
+    }
+    
+    if candidates.is_empty() {
+        let (year, month, day) = civil_date(chrono_timestamp());
+        candidates.push(format!("Drawing {:04}-{:02}-{:02}", year, month, day));
+    }
+    
+    let suggestion = candidates.remove(0);
+    Ok(FilenameSuggestion { suggestion, alternatives: candidates })
+}
+
+/// Colors of `export_drawing_diff_svg`
+const DIFF_ADDED_COLOR: &str = "#16a34a";
+const DIFF_REMOVED_COLOR: &str = "#dc2626";
+const DIFF_UNCHANGED_COLOR: &str = "#9ca3af";
+
+/// The shape records of a store by id
+fn store_shapes(store: &serde_json::Value) -> BTreeMap<&str, &serde_json::Value> {
+    store_records(store)
+        .into_iter()
+        .filter(|record| record.get("typeName").and_then(|t| t.as_str()) == Some("shape"))
+        .filter_map(|shape| Some((shape.get("id")?.as_str()?, shape)))
+        .collect()
+}
+
+/// A shape and its ancestors, outermost first. Shapes are positioned relative to
+/// their parent shape, if any
+fn shape_ancestry<'a>(
+    shape: &'a serde_json::Value,
+    shapes: &BTreeMap<&str, &'a serde_json::Value>,
+) -> Vec<&'a serde_json::Value> {
+    let mut chain = vec![shape];
+    while let Some(parent) = chain
+        .last()
+        .and_then(|s| s.get("parentId"))
+        .and_then(|p| p.as_str())
+        .and_then(|p| shapes.get(p).copied())
+    {
+        // A damaged store could have a parent cycle
+        if chain.len() > 64 {
+            break;
+        }
+        chain.push(parent);
+    }
+    chain.reverse();
+    chain
+}
+
+/// An approximate outline of a shape in its own coordinates, from its props, with
+/// its bounds as `[min_x, min_y, max_x, max_y]`
+fn shape_outline(shape: &serde_json::Value) -> (String, [f64; 4]) {
+    let props = shape.get("props");
+    let point = |p: &serde_json::Value| Some((p.get("x")?.as_f64()?, p.get("y")?.as_f64()?));
+    let polyline = |points: Vec<(f64, f64)>| {
+        let bounds = points.iter().fold([f64::MAX, f64::MAX, f64::MIN, f64::MIN], |b, &(x, y)| {
+            [b[0].min(x), b[1].min(y), b[2].max(x), b[3].max(y)]
+        });
+        let points: Vec<String> = points.iter().map(|(x, y)| format!("{},{}", x, y)).collect();
+        (format!("<polyline fill=\"none\" points=\"{}\"/>", points.join(" ")), bounds)
+    };
+    
+    let points: Vec<(f64, f64)> = match shape.get("type").and_then(|t| t.as_str()) {
+        Some("draw") | Some("highlight") => props
+            .and_then(|p| p.get("segments"))
+            .and_then(|s| s.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|segment| segment.get("points")?.as_array())
+            .flatten()
+            .filter_map(|p| point(p))
+            .collect(),
+        Some("line") => {
+            let mut handles: Vec<&serde_json::Value> = match props.and_then(|p| p.get("points")) {
+                Some(serde_json::Value::Object(points)) => points.values().collect(),
+                Some(serde_json::Value::Array(points)) => points.iter().collect(),
+                _ => Vec::new(),
+            };
+            handles.sort_by_key(|h| h.get("index").and_then(|i| i.as_str()).unwrap_or("").to_string());
+            handles.into_iter().filter_map(|p| point(p)).collect()
+        }
+        Some("arrow") => ["start", "end"]
+            .iter()
+            .filter_map(|end| point(props?.get(*end)?))
+            .collect(),
+        _ => Vec::new(),
+    };
+    if !points.is_empty() {
+        return polyline(points);
+    }
+    
+    let dimension = |key: &str| props.and_then(|p| p.get(key)).and_then(|v| v.as_f64()).unwrap_or(100.0);
+    let (w, h) = (dimension("w"), dimension("h"));
+    let outline = match props.and_then(|p| p.get("geo")).and_then(|g| g.as_str()) {
+        Some("ellipse") | Some("oval") => format!(
+            "<ellipse cx=\"{}\" cy=\"{}\" rx=\"{}\" ry=\"{}\"/>",
+            w / 2.0,
+            h / 2.0,
+            w / 2.0,
+            h / 2.0
+        ),
+        _ => format!("<rect width=\"{}\" height=\"{}\"/>", w, h),
+    };
+    (outline, [0.0, 0.0, w, h])
+}
+
+/// Draw a shape in `color` at its page position, growing `bounds` to include it
+fn diff_shape_svg(
+    shape: &serde_json::Value,
+    shapes: &BTreeMap<&str, &serde_json::Value>,
+    color: &str,
+    bounds: &mut [f64; 4],
+) -> String {
+    let ancestry = shape_ancestry(shape, shapes);
+    let number = |s: &serde_json::Value, key: &str| s.get(key).and_then(|v| v.as_f64()).unwrap_or(0.0);
+    let transform: Vec<String> = ancestry
+        .iter()
+        .map(|s| format!("translate({} {}) rotate({})", number(s, "x"), number(s, "y"), number(s, "rotation").to_degrees()))
+        .collect();
+    
+    let (outline, local) = shape_outline(shape);
+    // Bounds ignore rotation; they only need to roughly frame the drawing
+    let (x, y) = ancestry.iter().fold((0.0, 0.0), |(x, y), s| (x + number(s, "x"), y + number(s, "y")));
+    *bounds = [
+        bounds[0].min(x + local[0]),
+        bounds[1].min(y + local[1]),
+        bounds[2].max(x + local[2]),
+        bounds[3].max(y + local[3]),
+    ];
+    
+    let label = shape_text(shape)
+        .map(|text| {
+            let line = text.lines().next().unwrap_or("");
+            format!("<text y=\"-4\" font-size=\"12\" stroke=\"none\">{}</text>", xml_escape(line))
+        })
+        .unwrap_or_default();
+    format!(
+        "<g transform=\"{}\" stroke=\"{}\" fill=\"{}\" fill-opacity=\"0.15\">{}{}</g>",
+        transform.join(" "),
+        color,
+        color,
+        outline,
+        label
+    )
+}
+
+/// Visualize what changed between two versions of a drawing: shapes only in the new
+/// version in green, shapes only in the old one in red (dashed) and the rest in grey.
+/// A modified shape shows as its old version removed and its new version added.
+/// Shape geometry is approximated from the store, not rendered the way the editor does.
+#[tauri::command]
+async fn export_drawing_diff_svg(old_content: String, new_content: String) -> Result<String, String> {
+    let old: DrawingFile = serde_json::from_str(&old_content)
+        .map_err(|e| format!("Failed to parse old drawing: {}", e))?;
+    let new: DrawingFile = serde_json::from_str(&new_content)
+        .map_err(|e| format!("Failed to parse new drawing: {}", e))?;
+    let old_shapes = store_shapes(&old.store);
+    let new_shapes = store_shapes(&new.store);
+    
+    let stacking = |shape: &&serde_json::Value| shape.get("index").and_then(|i| i.as_str()).unwrap_or("").to_string();
+    let mut unchanged: Vec<&serde_json::Value> = Vec::new();
+    let mut removed: Vec<&serde_json::Value> = Vec::new();
+    for (id, shape) in &old_shapes {
+        match new_shapes.get(id) {
+            Some(current) if current == shape => unchanged.push(*shape),
+            _ => removed.push(*shape),
+        }
+    }
+    let mut added: Vec<&serde_json::Value> = new_shapes
+        .iter()
+        .filter(|(id, shape)| old_shapes.get(*id) != Some(*shape))
+        .map(|(_, shape)| *shape)
+        .collect();
+    unchanged.sort_by_key(stacking);
+    removed.sort_by_key(stacking);
+    added.sort_by_key(stacking);
+    
+    let mut bounds = [f64::MAX, f64::MAX, f64::MIN, f64::MIN];
+    let mut layer = |shapes: &[&serde_json::Value], all: &BTreeMap<&str, &serde_json::Value>, color: &str| -> String {
+        shapes.iter().map(|shape| diff_shape_svg(shape, all, color, &mut bounds)).collect()
+    };
+    let unchanged = layer(&unchanged, &new_shapes, DIFF_UNCHANGED_COLOR);
+    let removed = layer(&removed, &old_shapes, DIFF_REMOVED_COLOR);
+    let added = layer(&added, &new_shapes, DIFF_ADDED_COLOR);
+    
+    let padding = 20.0;
+    let (x, y, width, height) = if bounds[0] <= bounds[2] {
+        (
+            bounds[0] - padding,
+            bounds[1] - padding,
+            bounds[2] - bounds[0] + 2.0 * padding,
+            bounds[3] - bounds[1] + 2.0 * padding,
+        )
+    } else {
+        (0.0, 0.0, 100.0, 100.0)
+    };
+    Ok(format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\" width=\"{}\" height=\"{}\" stroke-width=\"2\">\
+         <g id=\"unchanged\">{}</g><g id=\"removed\" stroke-dasharray=\"6 4\">{}</g><g id=\"added\">{}</g></svg>",
+        x, y, width, height, width, height, unchanged, removed, added
+    ))
+}
+
+/// Largest amount of memory a single export may need
+const MAX_EXPORT_MEMORY_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// Bytes needed to export a `width` x `height` image: the RGBA pixmap plus what
+/// the encoder for `format` allocates on top of it
+fn estimate_export_memory(width: u32, height: u32, format: &str) -> Result<u64, String> {
+    let pixmap = width as u64 * height as u64 * 4;
+    // Encoder overhead as a fraction of the pixmap size
+    let (numerator, denominator) = match format.to_ascii_lowercase().as_str() {
+        "rgba" | "raw" => (0, 1),
+        "png" | "webp" => (1, 2),
+        "jpg" | "jpeg" => (1, 4),
+        _ => return Err(format!("Unsupported export format: {}", format)),
+    };
+    Ok(pixmap + pixmap * numerator / denominator)
+}
+
+/// Refuse exports whose estimated memory use is over the limit
+fn check_export_memory(width: u32, height: u32, format: &str) -> Result<(), String> {
+    let estimate = estimate_export_memory(width, height, format)?;
+    if estimate > MAX_EXPORT_MEMORY_BYTES {
+        return Err(format!(
+            "Export of {}x{} would need about {} MB (limit {} MB)",
+            width,
+            height,
+            estimate / (1024 * 1024),
+            MAX_EXPORT_MEMORY_BYTES / (1024 * 1024)
+        ));
+    }
+    Ok(())
+}
+
+/// Estimate how much memory exporting at the given size and format will use, in bytes
+#[tauri::command]
+async fn export_memory_estimate(width: u32, height: u32, format: String) -> Result<u64, String> {
+    estimate_export_memory(width, height, &format)
+}
+
+/// Render a parsed SVG onto a new pixmap; a zero width or height uses the SVG's own size
+fn render_tree(tree: &usvg::Tree, width: u32, height: u32) -> Result<tiny_skia::Pixmap, String> {
+    let pixmap_size = tree.size().to_int_size();
+    let width = if width > 0 { width } else { pixmap_size.width() };
+    let height = if height > 0 { height } else { pixmap_size.height() };
+    check_export_memory(width, height, "png")?;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or("Failed to create pixmap")?;
+    
+    resvg::render(tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    
+    Ok(pixmap)
+}
+
+/// Elements usvg legitimately rewrites into other elements (shapes become paths,
+/// `use` and styles are resolved), so their absence after a round trip isn't a loss
+const USVG_REWRITTEN_ELEMENTS: [&str; 18] = [
+    "svg", "rect", "circle", "ellipse", "line", "polyline", "polygon", "use", "symbol",
+    "style", "switch", "a", "title", "desc", "metadata", "text", "tspan", "textPath",
+];
+
+/// Warn about every text element in a group whose glyphs produced no outlines
+fn collect_unoutlined_text(group: &usvg::Group, warnings: &mut Vec<String>) {
+    for node in group.children() {
+        match node {
+            usvg::Node::Group(group) => collect_unoutlined_text(group, warnings),
+            usvg::Node::Text(text) => {
+                let content: String = text.chunks().iter().map(|chunk| chunk.text()).collect();
+                if !content.trim().is_empty() && text.flattened().children().is_empty() {
+                    warnings.push(format!("Text {:?} could not be outlined; no font provides its glyphs", content));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Convert all text in an SVG to paths using the fonts exports resolve, so it renders
+/// the same without the fonts installed. Fill and stroke carry over to the glyph paths.
+/// The document comes back normalized by usvg, the same way exports see it.
+#[tauri::command]
+async fn outline_svg_text(fonts: tauri::State<'_, FontStore>, svg_data: String) -> Result<OutlinedSvg, String> {
+    outline_text(&svg_data, &fonts.svg_options()?)
+}
+
+/// The conversion behind `outline_svg_text`
+fn outline_text(svg_data: &str, opt: &usvg::Options) -> Result<OutlinedSvg, String> {
+    let tree = usvg::Tree::from_str(svg_data, opt)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    
+    let mut warnings = Vec::new();
+    collect_unoutlined_text(tree.root(), &mut warnings);
+    
+    let svg = tree.to_string(&usvg::WriteOptions {
+        preserve_text: false,
+        ..usvg::WriteOptions::default()
+    });
+    Ok(OutlinedSvg { svg, warnings })
+}
+
+/// Record the elements of a usvg group using each feature some export format can't keep
+fn collect_svg_features<'a>(group: &'a usvg::Group, features: &mut BTreeMap<&'static str, Vec<&'a str>>) {
+    // usvg moves a shape's opacity, filter, mask or clip path onto an anonymous
+    // group around it, leaving the id on the shape
+    let id = match group.children() {
+        [only] if group.id().is_empty() => only.id(),
+        _ => group.id(),
+    };
+    let mut add = |feature: &'static str| features.entry(feature).or_default().push(id);
+    if group.opacity().get() < 1.0 {
+        add("transparency");
+    }
+    if !group.filters().is_empty() {
+        add("filters");
+    }
+    if group.mask().is_some() {
+        add("masks");
+    }
+    if group.clip_path().is_some() {
+        add("clip_paths");
+    }
+    
+    for node in group.children() {
+        match node {
+            usvg::Node::Group(child) => collect_svg_features(child, features),
+            usvg::Node::Text(text) => {
+                features.entry("text").or_default().push(text.id());
+                collect_svg_features(text.flattened(), features);
+            }
+            usvg::Node::Image(image) => features.entry("images").or_default().push(image.id()),
+            usvg::Node::Path(path) => {
+                let paints = path
+                    .fill()
+                    .map(|fill| (fill.paint(), fill.opacity().get()))
+                    .into_iter()
+                    .chain(path.stroke().map(|stroke| (stroke.paint(), stroke.opacity().get())));
+                for (paint, opacity) in paints {
+                    let mut add = |feature: &'static str| features.entry(feature).or_default().push(path.id());
+                    let stops = match paint {
+                        usvg::Paint::Color(_) => &[][..],
+                        usvg::Paint::LinearGradient(gradient) => gradient.stops(),
+                        usvg::Paint::RadialGradient(gradient) => gradient.stops(),
+                        usvg::Paint::Pattern(_) => {
+                            add("patterns");
+                            &[][..]
+                        }
+                    };
+                    if !stops.is_empty() {
+                        add("gradients");
+                    }
+                    if opacity < 1.0 || stops.iter().any(|stop| stop.opacity().get() < 1.0) {
+                        add("transparency");
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Report the SVG features an export format will lose, so the UI can warn before a
+/// lossy export. Formats are those the app exports: png, webp, jpeg, gif, eps, svg and odg.
+#[tauri::command]
+async fn export_compatibility(
+    fonts: tauri::State<'_, FontStore>,
+    svg_data: String,
+    format: String,
+) -> Result<CompatReport, String> {
+    compatibility_report(&svg_data, &fonts.svg_options()?, &format)
+}
+
+/// The analysis behind `export_compatibility`
+fn compatibility_report(svg_data: &str, opt: &usvg::Options, format: &str) -> Result<CompatReport, String> {
+    let format = format.to_lowercase();
+    let lost: &[(&str, &str)] = match format.as_str() {
+        "png" | "webp" => &[],
+        "jpg" | "jpeg" => &[("transparency", "JPEG has no alpha channel; transparent areas are flattened")],
+        "gif" => &[
+            ("transparency", "GIF only has on/off transparency; partial transparency is lost"),
+            ("gradients", "GIF is limited to 256 colors; gradients will band"),
+        ],
+        "eps" => &[
+            ("transparency", "EPS has no transparency; shapes are drawn opaque"),
+            ("gradients", "Gradients are flattened to their first color"),
+            ("patterns", "Patterns are drawn as solid black"),
+            ("filters", "Filters are dropped"),
+            ("masks", "Masks are dropped"),
+            ("clip_paths", "Clip paths are dropped"),
+            ("images", "Embedded images are dropped"),
+        ],
+        "svg" => &[
+            ("filters", "Filters aren't part of the SVG Tiny profile that some viewers implement"),
+            ("masks", "Masks aren't part of the SVG Tiny profile that some viewers implement"),
+            ("text", "Text needs the same fonts installed to look the same; outline it to be safe"),
+        ],
+        "odg" => &[("filters", "LibreOffice renders few SVG filters; it falls back to the embedded PNG")],
+        _ => return Err(format!("Unsupported export format: {}", format)),
+    };
+    
+    let tree = usvg::Tree::from_str(svg_data, opt)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    let mut features = BTreeMap::new();
+    collect_svg_features(tree.root(), &mut features);
+    
+    let warnings = lost
+        .iter()
+        .filter_map(|(feature, message)| {
+            let elements = features.get(feature)?;
+            let mut element_ids: Vec<String> = elements
+                .iter()
+                .filter(|id| !id.is_empty())
+                .map(|id| id.to_string())
+                .collect();
+            element_ids.dedup();
+            Some(CompatWarning {
+                feature: feature.to_string(),
+                message: message.to_string(),
+                element_ids,
+                element_count: elements.len() as u32,
+            })
+        })
+        .collect();
+    
+    Ok(CompatReport { format, warnings })
+}
+
+/// Render an SVG, round-trip it through usvg's writer, render again and compare
+#[tauri::command]
+async fn validate_svg_roundtrip(
+    fonts: tauri::State<'_, FontStore>,
+    svg_data: String,
+) -> Result<RoundtripReport, String> {
+    svg_roundtrip_report(&svg_data, &fonts.svg_options()?)
+}
+
+/// The comparison behind `validate_svg_roundtrip`
+fn svg_roundtrip_report(svg_data: &str, opt: &usvg::Options) -> Result<RoundtripReport, String> {
+    let original = usvg::Tree::from_str(svg_data, opt)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    
+    let serialized = original.to_string(&usvg::WriteOptions::default());
+    let roundtrip = usvg::Tree::from_str(&serialized, opt)
+        .map_err(|e| format!("Failed to re-parse serialized SVG: {}", e))?;
+    
+    let size = original.size().to_int_size();
+    let before = render_tree(&original, size.width(), size.height())?;
+    let after = render_tree(&roundtrip, size.width(), size.height())?;
+    
+    let mut differing_pixels = 0u64;
+    let mut total_diff = 0u64;
+    for (a, b) in before.data().chunks_exact(4).zip(after.data().chunks_exact(4)) {
+        let diff: u64 = a.iter().zip(b).map(|(x, y)| x.abs_diff(*y) as u64).sum();
+        if diff > 0 {
+            differing_pixels += 1;
+        }
+        total_diff += diff;
+    }
+    let total_pixels = (size.width() as u64) * (size.height() as u64);
+    let difference = total_diff as f64 / (total_pixels.max(1) * 4 * 255) as f64;
+    
+    // Compare element types on both sides to name what usvg dropped
+    let element_names = |svg: &str| -> Result<Vec<String>, String> {
+        let doc = roxmltree::Document::parse(svg).map_err(|e| format!("Failed to parse SVG: {}", e))?;
+        let mut names: Vec<String> = doc
+            .descendants()
+            .filter(|n| n.is_element())
+            .map(|n| n.tag_name().name().to_string())
+            .collect();
+        names.sort();
+        names.dedup();
+        Ok(names)
+    };
+    let kept = element_names(&serialized)?;
+    let dropped_node_types = element_names(svg_data)?
+        .into_iter()
+        .filter(|name| !kept.contains(name) && !USVG_REWRITTEN_ELEMENTS.contains(&name.as_str()))
+        .collect();
+    
+    Ok(RoundtripReport {
+        difference,
+        differing_pixels,
+        total_pixels,
+        dropped_node_types,
+    })
+}
+
+/// Length of the tag at the start of `input` (up to and including `>`), skipping quoted values
+fn tag_length(input: &str) -> usize {
+    let mut quote: Option<char> = None;
+    for (i, c) in input.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '>') => return i + 1,
+            _ => {}
+        }
+    }
+    input.len()
+}
+
+/// Collapse whitespace runs outside quoted attribute values and drop it before the closing `>`
+fn minify_tag(tag: &str) -> String {
+    let mut out = String::with_capacity(tag.len());
+    let mut quote: Option<char> = None;
+    let mut pending_space = false;
+    for c in tag.chars() {
+        if quote.is_none() && c.is_whitespace() {
+            pending_space = true;
+            continue;
+        }
+        if pending_space && !(quote.is_none() && (c == '>' || c == '/' || c == '=')) && !out.ends_with('=') {
+            out.push(' ');
+        }
+        pending_space = false;
+        match quote {
+            Some(q) if c == q => quote = None,
+            None if c == '"' || c == '\'' => quote = Some(c),
+            _ => {}
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Collapse runs of whitespace into a single space
+fn collapse_whitespace(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_space = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !in_space {
+                out.push(' ');
+            }
+            in_space = true;
+        } else {
+            out.push(c);
+            in_space = false;
+        }
+    }
+    out
+}
+
+/// Strip comments and insignificant whitespace from an SVG document.
+/// Whitespace inside `<text>` is collapsed rather than removed, and content under
+/// `xml:space="preserve"`, CDATA sections, `<style>` and `<script>` is kept verbatim.
+fn minify_svg(svg: &str) -> String {
+    let mut out = String::with_capacity(svg.len());
+    // Open elements as (local name, preserves whitespace)
+    let mut stack: Vec<(String, bool)> = Vec::new();
+    let mut rest = svg;
+    
+    while !rest.is_empty() {
+        if rest.starts_with("<!--") {
+            rest = match rest.find("-->") {
+                Some(end) => &rest[end + 3..],
+                None => "",
+            };
+        } else if rest.starts_with("<![CDATA[") {
+            let end = rest.find("]]>").map(|e| e + 3).unwrap_or(rest.len());
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            let end = tag_length(rest);
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if rest.starts_with('<') {
+            let end = tag_length(rest);
+            let tag = &rest[..end];
+            rest = &rest[end..];
+            out.push_str(&minify_tag(tag));
+            
+            let closing = tag.starts_with("</");
+            let name: String = tag[if closing { 2 } else { 1 }..]
+                .chars()
+                .take_while(|c| !c.is_whitespace() && *c != '>' && *c != '/')
+                .collect();
+            let local = name.rsplit(':').next().unwrap_or(&name).to_string();
+            
+            if closing {
+                if let Some(index) = stack.iter().rposition(|(n, _)| *n == local) {
+                    stack.truncate(index);
+                }
+            } else if !tag.trim_end_matches('>').ends_with('/') {
+                if local == "style" || local == "script" {
+                    let close = format!("</{}", name);
+                    let end = rest.find(&close).unwrap_or(rest.len());
+                    out.push_str(&rest[..end]);
+                    rest = &rest[end..];
+                }
+                let preserve = tag.contains("xml:space=\"preserve\"") || tag.contains("xml:space='preserve'");
+                stack.push((local, preserve));
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = &rest[..end];
+            rest = &rest[end..];
+            
+            if stack.iter().any(|(_, preserve)| *preserve) {
+                out.push_str(text);
+            } else if stack.iter().any(|(name, _)| name == "text") {
+                out.push_str(&collapse_whitespace(text));
+            } else {
+                out.push_str(collapse_whitespace(text).trim());
+            }
+        }
+    }
+    
+    out
+}
+
+/// Ids referenced from a string through `url(#id)`
+fn url_references(value: &str) -> Vec<String> {
+    value
+        .split("url(")
+        .skip(1)
+        .filter_map(|rest| {
+            let inner = rest.split(')').next()?.trim().trim_matches(|c| c == '"' || c == '\'');
+            inner.strip_prefix('#').map(|id| id.to_string())
+        })
+        .collect()
+}
+
+/// Ids referenced directly by an element (its attributes and text), not its descendants
+fn element_references(node: roxmltree::Node) -> Vec<String> {
+    let mut refs = Vec::new();
+    for attr in node.attributes() {
+        if attr.name() == "href" {
+            if let Some(id) = attr.value().strip_prefix('#') {
+                refs.push(id.to_string());
+            }
+        }
+        refs.extend(url_references(attr.value()));
+    }
+    if let Some(text) = node.text() {
+        refs.extend(url_references(text));
+    }
+    refs
+}
+
+/// Remove `<defs>` children that nothing rendered references, directly or through other defs
+fn prune_defs(svg: &str) -> Result<String, String> {
+    let doc = roxmltree::Document::parse(svg).map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    
+    let in_defs = |node: roxmltree::Node| node.ancestors().skip(1).any(|a| a.tag_name().name() == "defs");
+    let subtree_references = |node: roxmltree::Node| -> Vec<String> {
+        node.descendants()
+            .filter(|n| n.is_element())
+            .flat_map(element_references)
+            .collect()
+    };
+    
+    // Everything outside defs is rendered; follow references from there
+    let mut reachable: std::collections::HashSet<String> = std::collections::HashSet::new();
+    let mut queue: Vec<String> = doc
+        .descendants()
+        .filter(|n| n.is_element() && !in_defs(*n) && n.tag_name().name() != "defs")
+        .flat_map(element_references)
+        .collect();
+    while let Some(id) = queue.pop() {
+        if !reachable.insert(id.clone()) {
+            continue;
+        }
+        if let Some(node) = doc.descendants().find(|n| n.attribute("id") == Some(id.as_str())) {
+            queue.extend(subtree_references(node));
+        }
+    }
+    
+    let mut removed: Vec<std::ops::Range<usize>> = doc
+        .descendants()
+        .filter(|n| n.tag_name().name() == "defs")
+        .flat_map(|defs| defs.children().filter(|c| c.is_element()))
+        .filter(|child| child.attribute("id").is_some())
+        .filter(|child| {
+            !child
+                .descendants()
+                .filter_map(|n| n.attribute("id"))
+                .any(|id| reachable.contains(id))
+        })
+        .map(|child| child.range())
+        .collect();
+    
+    // Remove from the end so earlier ranges stay valid; skip ranges nested in removed ones
+    removed.sort_by_key(|range| std::cmp::Reverse(range.start));
+    let mut pruned = svg.to_string();
+    let mut last_start = usize::MAX;
+    for range in removed {
+        if range.end <= last_start {
+            pruned.replace_range(range.clone(), "");
+            last_start = range.start;
+        }
+    }
+    
+    Ok(pruned)
+}
+
+/// Remove unreferenced gradients, markers, clip paths and other defs from an SVG
+#[tauri::command]
+async fn prune_svg_defs(svg_data: String) -> Result<String, String> {
+    prune_defs(&svg_data)
+}
+
+/// Split the inside of a start tag into its name and `(attribute, value)` pairs
+fn parse_start_tag(tag: &str) -> (String, Vec<(String, String)>) {
+    let inner = tag.trim_start_matches('<').trim_end_matches('>').trim_end_matches('/');
+    let mut chars = inner.char_indices().peekable();
+    let name_end = inner.find(char::is_whitespace).unwrap_or(inner.len());
+    let name = inner[..name_end].to_string();
+    while chars.peek().map(|(i, _)| *i < name_end).unwrap_or(false) {
+        chars.next();
+    }
+    
+    let mut attributes = Vec::new();
+    loop {
+        // Attribute name
+        while chars.peek().map(|(_, c)| c.is_whitespace()).unwrap_or(false) {
+            chars.next();
+        }
+        let start = match chars.peek() {
+            Some((i, _)) => *i,
+            None => break,
+        };
+        let mut end = start;
+        while let Some((i, c)) = chars.peek().copied() {
+            if c == '=' || c.is_whitespace() {
+                break;
+            }
+            end = i + c.len_utf8();
+            chars.next();
+        }
+        let attr_name = inner[start..end].to_string();
+        
+        // Optional `= value`
+        while chars.peek().map(|(_, c)| c.is_whitespace()).unwrap_or(false) {
+            chars.next();
+        }
+        let mut value = String::new();
+        if chars.peek().map(|(_, c)| *c == '=').unwrap_or(false) {
+            chars.next();
+            while chars.peek().map(|(_, c)| c.is_whitespace()).unwrap_or(false) {
+                chars.next();
+            }
+            if let Some((_, quote)) = chars.next() {
+                for (_, c) in chars.by_ref() {
+                    if c == quote {
+                        break;
+                    }
+                    value.push(c);
+                }
+            }
+        }
+        if attr_name.is_empty() {
+            break;
+        }
+        attributes.push((attr_name, value));
+    }
+    
+    (name, attributes)
+}
+
+/// JSX name of an SVG attribute or CSS property (`stroke-width` → `strokeWidth`)
+fn jsx_name(name: &str) -> String {
+    match name {
+        "class" => return "className".to_string(),
+        "for" => return "htmlFor".to_string(),
+        _ if name.starts_with("data-") || name.starts_with("aria-") => return name.to_string(),
+        _ => {}
+    }
+    let mut out = String::with_capacity(name.len());
+    let mut upper = false;
+    for (i, c) in name.chars().enumerate() {
+        if c == '-' || c == ':' {
+            upper = i > 0 || c == '-';
+        } else if upper {
+            out.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Render a start tag as JSX; `class_name_prop` wires the component's className into it
+fn jsx_start_tag(tag: &str, class_name_prop: bool) -> String {
+    let self_closing = tag.trim_end_matches('>').ends_with('/');
+    let (name, attributes) = parse_start_tag(tag);
+    
+    let mut out = format!("<{}", name);
+    let mut has_class = false;
+    for (attr, value) in attributes {
+        let jsx_attr = jsx_name(&attr);
+        if jsx_attr == "style" {
+            let properties: Vec<String> = value
+                .split(';')
+                .filter_map(|declaration| {
+                    let (property, property_value) = declaration.split_once(':')?;
+                    Some(format!(
+                        "{}: {}",
+                        jsx_name(property.trim()),
+                        serde_json::to_string(property_value.trim()).ok()?
+                    ))
+                })
+                .collect();
+            out.push_str(&format!(" style={{{{ {} }}}}", properties.join(", ")));
+        } else if jsx_attr == "className" && class_name_prop {
+            has_class = true;
+            out.push_str(&format!(
+                " className={{[{}, className].filter(Boolean).join(' ')}}",
+                serde_json::to_string(&value).unwrap_or_default()
+            ));
+        } else if value.contains('"') {
+            out.push_str(&format!(" {}={{{}}}", jsx_attr, serde_json::to_string(&value).unwrap_or_default()));
+        } else {
+            out.push_str(&format!(" {}=\"{}\"", jsx_attr, value));
+        }
+    }
+    if class_name_prop && !has_class {
+        out.push_str(" className={className}");
+    }
+    out.push_str(if self_closing { " />" } else { ">" });
+    out
+}
+
+/// Turn SVG markup into JSX: attributes camelCased, comments and prolog dropped,
+/// braces in text escaped and `<style>` content wrapped in a template literal
+fn svg_to_jsx(svg: &str) -> String {
+    let mut out = String::with_capacity(svg.len());
+    let mut rest = svg;
+    let mut root_seen = false;
+    
+    while !rest.is_empty() {
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map(|end| &rest[end + 3..]).unwrap_or("");
+        } else if rest.starts_with("<?") || rest.starts_with("<!") {
+            rest = &rest[tag_length(rest)..];
+        } else if rest.starts_with("</") {
+            let end = tag_length(rest);
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+        } else if rest.starts_with('<') {
+            let end = tag_length(rest);
+            let tag = &rest[..end];
+            rest = &rest[end..];
+            
+            let is_root = !root_seen;
+            root_seen = true;
+            out.push_str(&jsx_start_tag(tag, is_root));
+            
+            let (name, _) = parse_start_tag(tag);
+            if name.rsplit(':').next() == Some("style") && !tag.trim_end_matches('>').ends_with('/') {
+                let close = rest.find(&format!("</{}", name)).unwrap_or(rest.len());
+                let css = rest[..close].replace("<![CDATA[", "").replace("]]>", "");
+                out.push_str(&format!("{{`{}`}}", css.replace('\\', "\\\\").replace('`', "\\`").replace("${", "\\${")));
+                rest = &rest[close..];
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            out.push_str(&rest[..end].replace('{', "{'{'}").replace('}', "{'}'}"));
+            rest = &rest[end..];
+        }
+    }
+    
+    out.trim().to_string()
+}
+
+/// Wrap an SVG in a ready-to-paste TypeScript React component
+#[tauri::command]
+async fn export_to_react_component(svg_data: String, component_name: String) -> Result<String, String> {
+    let valid_name = component_name.chars().next().map(|c| c.is_ascii_uppercase()).unwrap_or(false)
+        && component_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_name {
+        return Err(format!("Component name must be a PascalCase identifier, got {:?}", component_name));
+    }
+    roxmltree::Document::parse(&svg_data).map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    
+    let jsx: String = svg_to_jsx(&svg_data)
+        .lines()
+        .map(|line| format!("    {}\n", line))
+        .collect();
+    
+    Ok(format!(
+        "import * as React from 'react';\n\
+         \n\
+         export interface {name}Props {{\n  className?: string;\n}}\n\
+         \n\
+         export function {name}({{ className }}: {name}Props): React.JSX.Element {{\n  return (\n{jsx}  );\n}}\n\
+         \n\
+         export default {name};\n",
+        name = component_name,
+        jsx = jsx
+    ))
+}
+
+/// Rewrite a parsed attribute as `name="value"`, keeping any entities as they were
+fn format_attribute(name: &str, value: &str) -> String {
+    format!(" {}=\"{}\"", name, value.replace('"', "&quot;"))
+}
+
+/// Store each data-URI image that appears more than once a single time in a
+/// `<symbol>`, and point every occurrence at it with `<use>`
+fn dedupe_svg_images(svg: &str) -> (String, u32) {
+    let image_key = |attributes: &[(String, String)]| -> Option<String> {
+        let href = attributes
+            .iter()
+            .find(|(name, _)| name == "href" || name == "xlink:href")
+            .map(|(_, value)| value)?;
+        if !href.starts_with("data:") {
+            return None;
+        }
+        let aspect = attributes
+            .iter()
+            .find(|(name, _)| name == "preserveAspectRatio")
+            .map(|(_, value)| value.as_str())
+            .unwrap_or("");
+        Some(sha256_hex(format!("{}|{}", aspect, href).as_bytes()))
+    };
+    
+    // First pass: count identical images
+    let mut counts: std::collections::HashMap<String, u32> = std::collections::HashMap::new();
+    let mut rest = svg;
+    while let Some(start) = rest.find("<image") {
+        let end = start + tag_length(&rest[start..]);
+        let (_, attributes) = parse_start_tag(&rest[start..end]);
+        if let Some(key) = image_key(&attributes) {
+            *counts.entry(key).or_default() += 1;
+        }
+        rest = &rest[end..];
+    }
+    
+    // Second pass: rewrite duplicates as <use> and collect their symbols
+    let mut symbols: Vec<(String, String)> = Vec::new();
+    let mut out = String::with_capacity(svg.len());
+    let mut duplicates_removed = 0;
+    let mut rest = svg;
+    while let Some(start) = rest.find("<image") {
+        out.push_str(&rest[..start]);
+        let end = start + tag_length(&rest[start..]);
+        let tag = &rest[start..end];
+        rest = &rest[end..];
+        
+        let (_, attributes) = parse_start_tag(tag);
+        let key = match image_key(&attributes) {
+            Some(key) if counts.get(&key).copied().unwrap_or(0) > 1 => key,
+            _ => {
+                out.push_str(tag);
+                continue;
+            }
+        };
+        
+        let symbol_id = format!("jamal-image-{}", &key[..12]);
+        if symbols.iter().any(|(id, _)| *id == symbol_id) {
+            duplicates_removed += 1;
+        } else {
+            let image_attributes: String = attributes
+                .iter()
+                .filter(|(name, _)| name == "href" || name == "xlink:href" || name == "preserveAspectRatio")
+                .map(|(name, value)| format_attribute(name, value))
+                .collect();
+            symbols.push((
+                symbol_id.clone(),
+                format!(
+                    "<symbol id=\"{}\" overflow=\"visible\"><image{} width=\"100%\" height=\"100%\"/></symbol>",
+                    symbol_id, image_attributes
+                ),
+            ));
+        }
+        
+        let use_attributes: String = attributes
+            .iter()
+            .filter(|(name, _)| name != "href" && name != "xlink:href" && name != "preserveAspectRatio")
+            .map(|(name, value)| format_attribute(name, value))
+            .collect();
+        out.push_str(&format!("<use href=\"#{}\"{}/>", symbol_id, use_attributes));
+        
+        // Drop the end tag of a non-self-closing <image>
+        if !tag.trim_end_matches('>').ends_with('/') {
+            let trimmed = rest.trim_start();
+            if trimmed.starts_with("</image>") {
+                rest = &trimmed["</image>".len()..];
+            }
+        }
+    }
+    out.push_str(rest);
+    
+    if symbols.is_empty() {
+        return (out, 0);
+    }
+    
+    // Put the symbols right after the root start tag
+    let defs: String = std::iter::once("<defs>".to_string())
+        .chain(symbols.into_iter().map(|(_, symbol)| symbol))
+        .chain(std::iter::once("</defs>".to_string()))
+        .collect();
+    let insert_at = out
+        .find("<svg")
+        .map(|start| start + tag_length(&out[start..]))
+        .unwrap_or(0);
+    out.insert_str(insert_at, &defs);
+    
+    (out, duplicates_removed)
+}
+
+/// Export an SVG with repeated embedded images stored once and referenced with `<use>`
+#[tauri::command]
+async fn export_svg_optimized(svg_data: String) -> Result<SvgOptimizationResult, String> {
+    roxmltree::Document::parse(&svg_data).map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    
+    let (svg, duplicates_removed) = dedupe_svg_images(&svg_data);
+    Ok(SvgOptimizationResult {
+        original_size: svg_data.len() as u64,
+        optimized_size: svg.len() as u64,
+        svg,
+        duplicates_removed,
+    })
+}
+
+/// Export an SVG that scales to its container: the root's fixed `width` and `height`
+/// become `100%` and `preserveAspectRatio` (default `xMidYMid meet`) keeps it
+/// undistorted. A missing `viewBox` is derived from the old size so the drawing
+/// keeps its coordinate system.
+#[tauri::command]
+async fn export_responsive_svg(svg_data: String, preserve_aspect_ratio: Option<String>) -> Result<String, String> {
+    let doc = roxmltree::Document::parse(&svg_data).map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    let view_box = match doc.root_element().attribute("viewBox") {
+        Some(_) => None,
+        None => {
+            let (width, height) = svg_intrinsic_size(&svg_data).ok_or("SVG has neither a viewBox nor a fixed size")?;
+            Some(format!("0 0 {} {}", width, height))
+        }
+    };
+    
+    let start = svg_data.find("<svg").ok_or("Not an SVG document")?;
+    let end = start + tag_length(&svg_data[start..]);
+    let tag = &svg_data[start..end];
+    let (name, attributes) = parse_start_tag(tag);
+    
+    let mut root = format!("<{}", name);
+    for (attr_name, value) in &attributes {
+        if !matches!(attr_name.as_str(), "width" | "height" | "preserveAspectRatio") {
+            root.push_str(&format_attribute(attr_name, value));
+        }
+    }
+    if let Some(view_box) = &view_box {
+        root.push_str(&format_attribute("viewBox", view_box));
+    }
+    root.push_str(&format_attribute("width", "100%"));
+    root.push_str(&format_attribute("height", "100%"));
+    root.push_str(&format_attribute(
+        "preserveAspectRatio",
+        preserve_aspect_ratio.as_deref().unwrap_or("xMidYMid meet"),
+    ));
+    root.push_str(if tag.ends_with("/>") { "/>" } else { ">" });
+    
+    Ok(format!("{}{}{}", &svg_data[..start], root, &svg_data[end..]))
+}
+
+/// Whether an id can be used as an XML id and a `#fragment` reference
+fn is_valid_symbol_id(id: &str) -> bool {
+    let mut chars = id.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+}
+
+/// Build a `<symbol>` from the root element of an SVG document
+fn svg_to_symbol(svg: &str, id: &str) -> Result<String, String> {
+    let doc = roxmltree::Document::parse(svg).map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    let root = doc.root_element();
+    if root.tag_name().name() != "svg" {
+        return Err("Root element is not <svg>".to_string());
+    }
+    
+    let view_box = match root.attribute("viewBox") {
+        Some(view_box) => view_box.to_string(),
+        None => {
+            let dimension = |name: &str| {
+                root.attribute(name)
+                    .and_then(|v| v.trim_end_matches("px").parse::<f64>().ok())
+            };
+            match (dimension("width"), dimension("height")) {
+                (Some(width), Some(height)) => format!("0 0 {} {}", width, height),
+                _ => return Err("SVG has no viewBox or numeric width and height".to_string()),
+            }
+        }
+    };
+    
+    let content = match (root.first_child(), root.last_child()) {
+        (Some(first), Some(last)) => &svg[first.range().start..last.range().end],
+        _ => "",
+    };
+    
+    Ok(format!(
+        "<symbol id=\"{}\" viewBox=\"{}\">{}</symbol>",
+        id,
+        xml_escape(&view_box),
+        content
+    ))
+}
+
+/// Combine several SVG files into one sprite sheet of `<symbol>` elements
+#[tauri::command]
+async fn build_svg_sprite(app: tauri::AppHandle, paths: Vec<String>, ids: Vec<String>) -> Result<String, String> {
+    run_blocking(app, move |app| {
+        let mut sources = Vec::with_capacity(paths.len());
+        for path in paths {
+            let svg = fs::read_to_string(check_path_scope(&app, &path)?).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+            sources.push((path, svg));
+        }
+        build_sprite(&sources, &ids)
+    })
+    .await
+}
+
+/// Sprite sheet of `(name, svg)` sources, one symbol per id; `name` labels errors
+fn build_sprite(sources: &[(String, String)], ids: &[String]) -> Result<String, String> {
+    if sources.len() != ids.len() {
+        return Err(format!(
+            "Expected one id per file, got {} files and {} ids",
+            sources.len(),
+            ids.len()
+        ));
+    }
+    
+    let mut seen = std::collections::HashSet::new();
+    for id in ids {
+        if !is_valid_symbol_id(id) {
+            return Err(format!("Invalid symbol id: {}", id));
+        }
+        if !seen.insert(id.as_str()) {
+            return Err(format!("Duplicate symbol id: {}", id));
+        }
+    }
+    
+    let mut sprite = String::from(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" xmlns:xlink=\"http://www.w3.org/1999/xlink\">",
+    );
+    for ((name, svg), id) in sources.iter().zip(ids) {
+        let symbol = svg_to_symbol(svg, id).map_err(|e| format!("{}: {}", name, e))?;
+        sprite.push_str(&symbol);
+    }
+    sprite.push_str("</svg>");
+    
+    Ok(sprite)
+}
+
+/// Parse a `#RGB`, `#RRGGBB` or `rgb(r, g, b)` paint into its 8-bit channels
+fn parse_paint_rgb(value: &str) -> Option<[u8; 3]> {
+    let value = value.trim();
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 3 && hex.len() != 6 {
+            return None;
+        }
+        let color = parse_hex_color(value).ok()?.to_color_u8();
+        return Some([color.red(), color.green(), color.blue()]);
+    }
+    let inner = value.strip_prefix("rgb(")?.strip_suffix(')')?;
+    let channels: Vec<u8> = inner
+        .split(',')
+        .map(|c| c.trim().parse::<u8>())
+        .collect::<Result<_, _>>()
+        .ok()?;
+    <[u8; 3]>::try_from(channels).ok()
+}
+
+/// Convert an sRGB color to CIE L*a*b* with a D65 white point
+fn srgb_to_lab([r, g, b]: [u8; 3]) -> [f64; 3] {
+    let linear = |c: u8| {
+        let c = c as f64 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(r), linear(g), linear(b));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f64| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// The palette color closest to a paint by CIE76 distance, or `None` when the
+/// paint isn't a plain color or already is that palette color
+fn snap_paint(value: &str, palette: &[([u8; 3], [f64; 3])]) -> Option<String> {
+    let rgb = parse_paint_rgb(value)?;
+    let lab = srgb_to_lab(rgb);
+    let distance = |other: &[f64; 3]| {
+        lab.iter().zip(other).map(|(a, b)| (a - b).powi(2)).sum::<f64>()
+    };
+    let (nearest, _) = palette
+        .iter()
+        .min_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)))?;
+    if *nearest == rgb {
+        return None;
+    }
+    Some(format!("#{:02x}{:02x}{:02x}", nearest[0], nearest[1], nearest[2]))
+}
+
+/// Snap every fill and stroke in an SVG to the nearest color of a brand palette.
+/// Gradients, patterns and stylesheets are left as they are and reported in `warnings`
+#[tauri::command]
+async fn remap_svg_palette(svg_data: String, palette: Vec<String>) -> Result<PaletteRemapResult, String> {
+    roxmltree::Document::parse(&svg_data).map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    if palette.is_empty() {
+        return Err("Palette must contain at least one color".to_string());
+    }
+    let palette: Vec<([u8; 3], [f64; 3])> = palette
+        .iter()
+        .map(|color| {
+            let rgb = parse_paint_rgb(color).ok_or_else(|| format!("Invalid palette color: {}", color))?;
+            Ok((rgb, srgb_to_lab(rgb)))
+        })
+        .collect::<Result<_, String>>()?;
+    
+    let mut warnings: Vec<String> = Vec::new();
+    let mut warn = |warning: String| {
+        if !warnings.contains(&warning) {
+            warnings.push(warning);
+        }
+    };
+    let mut colors_changed = 0;
+    let mut pattern_depth = 0u32;
+    let mut out = String::with_capacity(svg_data.len());
+    let mut rest = svg_data.as_str();
+    while let Some(start) = rest.find('<') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+    
+        // Comments and CDATA can contain anything, so copy them through whole
+        let raw_end = if rest.starts_with("<!--") {
+            Some(rest.find("-->").map(|i| i + 3).unwrap_or(rest.len()))
+        } else if rest.starts_with("<![CDATA[") {
+            Some(rest.find("]]>").map(|i| i + 3).unwrap_or(rest.len()))
+        } else {
+            None
+        };
+        if let Some(end) = raw_end {
+            out.push_str(&rest[..end]);
+            rest = &rest[end..];
+            continue;
+        }
+    
+        let end = tag_length(rest);
+        let tag = &rest[..end];
+        rest = &rest[end..];
+        if let Some(closing) = tag.strip_prefix("</") {
+            let name = closing.trim_end_matches('>').trim();
+            if name.rsplit(':').next() == Some("pattern") {
+                pattern_depth = pattern_depth.saturating_sub(1);
+            }
+            out.push_str(tag);
+            continue;
+        }
+        if tag.starts_with("<!") || tag.starts_with("<?") {
+            out.push_str(tag);
+            continue;
+        }
+    
+        let (name, attributes) = parse_start_tag(tag);
+        let local_name = name.rsplit(':').next().unwrap_or(&name);
+        let self_closing = tag.trim_end_matches('>').ends_with('/');
+        if local_name == "pattern" && !self_closing {
+            pattern_depth += 1;
+        }
+        if local_name == "style" {
+            warn("Colors inside <style> elements were left unchanged".to_string());
+        }
+        if pattern_depth > 0 {
+            out.push_str(tag);
+            continue;
+        }
+    
+        let mut changed = false;
+        let mut snap = |value: &str| -> Option<String> {
+            if let Some(reference) = value.trim().strip_prefix("url(") {
+                warn(format!(
+                    "Gradient or pattern {} was left unchanged",
+                    reference.trim_end_matches(')').trim_matches(|c| c == '\'' || c == '"')
+                ));
+                return None;
+            }
+            let snapped = snap_paint(value, &palette)?;
+            colors_changed += 1;
+            changed = true;
+            Some(snapped)
+        };
+        let rewritten: String = attributes
+            .iter()
+            .map(|(attr_name, value)| {
+                let value = match attr_name.as_str() {
+                    "fill" | "stroke" => snap(value).unwrap_or_else(|| value.clone()),
+                    "style" => value
+                        .split(';')
+                        .map(|declaration| match declaration.split_once(':') {
+                            Some((property, paint)) if matches!(property.trim(), "fill" | "stroke") => snap(paint)
+                                .map(|snapped| format!("{}:{}", property, snapped))
+                                .unwrap_or_else(|| declaration.to_string()),
+                            _ => declaration.to_string(),
+                        })
+                        .collect::<Vec<_>>()
+                        .join(";"),
+                    _ => value.clone(),
+                };
+                format_attribute(attr_name, &value)
+            })
+            .collect();
+    
+        if changed {
+            out.push_str(&format!("<{}{}{}>", name, rewritten, if self_closing { "/" } else { "" }));
+        } else {
+            out.push_str(tag);
+        }
+    }
+    out.push_str(rest);
+    
+    Ok(PaletteRemapResult { svg: out, colors_changed, warnings })
+}
+
+/// Export SVG with comments and insignificant whitespace removed
+#[tauri::command]
+async fn export_svg_minified(svg_data: String) -> Result<String, String> {
+    roxmltree::Document::parse(&svg_data).map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    Ok(minify_svg(&svg_data))
+}
+
+/// Save minified SVG to file
+#[tauri::command]
+async fn save_svg_minified(
+    app: tauri::AppHandle,
+    path: String,
+    svg_data: String,
+    create_parents: Option<bool>,
+) -> Result<(), String> {
+    let minified = export_svg_minified(svg_data).await?;
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        ensure_parent_dir(&app, &path, create_parents.unwrap_or(true))?;
+        fs::write(&path, &minified).map_err(|e| format!("Failed to save SVG: {}", e))
+    })
+    .await
+}
+
+/// Render a parsed SVG scaled to fit and centered within a `width`×`height` pixmap,
+/// letterboxed with `background` (transparent when `None`)
+fn render_tree_fit(
+    tree: &usvg::Tree,
+    width: u32,
+    height: u32,
+    background: Option<tiny_skia::Color>,
+) -> Result<tiny_skia::Pixmap, String> {
+    check_export_memory(width, height, "png")?;
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or("Failed to create pixmap")?;
+    if let Some(color) = background {
+        pixmap.fill(color);
+    }
+    
+    let size = tree.size();
+    let scale = (width as f32 / size.width()).min(height as f32 / size.height());
+    let transform = tiny_skia::Transform::from_scale(scale, scale).post_translate(
+        (width as f32 - size.width() * scale) / 2.0,
+        (height as f32 - size.height() * scale) / 2.0,
+    );
+    resvg::render(tree, transform, &mut pixmap.as_mut());
+    
+    Ok(pixmap)
+}
+
+/// Parse a `#RGB`, `#RRGGBB` or `#RRGGBBAA` color
+fn parse_hex_color(color: &str) -> Result<tiny_skia::Color, String> {
+    let hex = color.trim().trim_start_matches('#');
+    let expanded: String = if hex.len() == 3 {
+        hex.chars().flat_map(|c| [c, c]).collect()
+    } else {
+        hex.to_string()
+    };
+    let channel = |i: usize| {
+        expanded
+            .get(i..i + 2)
+            .and_then(|c| u8::from_str_radix(c, 16).ok())
+            .ok_or_else(|| format!("Invalid color: {}", color))
+    };
+    
+    match expanded.len() {
+        6 => Ok(tiny_skia::Color::from_rgba8(channel(0)?, channel(2)?, channel(4)?, 255)),
+        8 => Ok(tiny_skia::Color::from_rgba8(channel(0)?, channel(2)?, channel(4)?, channel(6)?)),
+        _ => Err(format!("Invalid color: {}", color)),
+    }
+}
+
+/// Private, safe-to-copy PNG chunk holding the gzipped source drawing
+const SOURCE_CHUNK_TYPE: &[u8; 4] = b"jaMl";
+
+/// Insert a chunk right after IHDR, which is always the first chunk
+fn insert_png_chunk(png: &[u8], chunk_type: &[u8; 4], data: &[u8]) -> Result<Vec<u8>, String> {
+    // Signature (8) + IHDR length, type, 13 data bytes and CRC
+    const IHDR_END: usize = 8 + 4 + 4 + 13 + 4;
+    if png.len() < IHDR_END || &png[12..16] != b"IHDR" {
+        return Err("Not a PNG image".to_string());
+    }
+    
+    let mut crc = flate2::Crc::new();
+    crc.update(chunk_type);
+    crc.update(data);
+    
+    let mut out = Vec::with_capacity(png.len() + data.len() + 12);
+    out.extend_from_slice(&png[..IHDR_END]);
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(chunk_type);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc.sum().to_be_bytes());
+    out.extend_from_slice(&png[IHDR_END..]);
+    Ok(out)
+}
+
+/// Find the data of the first chunk of a type in a PNG
+fn find_png_chunk<'a>(png: &'a [u8], chunk_type: &[u8; 4]) -> Option<&'a [u8]> {
+    let mut offset = 8;
+    while offset + 12 <= png.len() {
+        let length = u32::from_be_bytes(png[offset..offset + 4].try_into().ok()?) as usize;
+        let data_start = offset + 8;
+        let data_end = data_start.checked_add(length)?;
+        if data_end + 4 > png.len() {
+            return None;
+        }
+        if &png[offset + 4..data_start] == chunk_type {
+            return Some(&png[data_start..data_end]);
+        }
+        offset = data_end + 4;
+    }
+    None
+}
+
+/// Recover the drawing embedded in a PNG exported with `embed_source`
+#[tauri::command]
+async fn extract_drawing_from_png(app: tauri::AppHandle, path: String) -> Result<DrawingFile, String> {
+    run_blocking(app, move |app| {
+        let png = fs::read(check_path_scope(&app, &path)?).map_err(|e| format!("Failed to read file: {}", e))?;
+        embedded_drawing(&png, &path)
+    })
+    .await
+}
+
+/// Store `drawing` gzipped in a PNG's source chunk
+fn embed_drawing(png: &[u8], drawing: &DrawingFile) -> Result<Vec<u8>, String> {
+    let json = serde_json::to_vec(drawing).map_err(|e| format!("Failed to serialize drawing: {}", e))?;
+    insert_png_chunk(png, SOURCE_CHUNK_TYPE, &gzip(&json)?)
+}
+
+/// The drawing stored by `embed_drawing` in a PNG; `name` labels errors
+fn embedded_drawing(png: &[u8], name: &str) -> Result<DrawingFile, String> {
+    if !png.starts_with(b"\x89PNG\r\n\x1a\n") {
+        return Err(format!("Not a PNG image: {}", name));
+    }
+    let chunk = find_png_chunk(png, SOURCE_CHUNK_TYPE)
+        .ok_or_else(|| format!("{} has no embedded drawing; it wasn't exported as an editable PNG", name))?;
+    serde_json::from_slice(&gunzip(chunk)?).map_err(|e| format!("Failed to parse embedded drawing: {}", e))
+}
+
+/// Problems in an SVG's structure that usvg would either reject with an unhelpful
+/// message or silently ignore, as `(errors, warnings)`
+fn svg_structure_issues(svg: &str) -> (Vec<String>, Vec<String>) {
+    let doc = match roxmltree::Document::parse(svg) {
+        Ok(doc) => doc,
+        Err(e) => return (vec![format!("Malformed XML: {}", e)], Vec::new()),
+    };
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    
+    let root = doc.root_element();
+    if root.tag_name().name() != "svg" {
+        errors.push(format!("Root element is <{}>, not <svg>", root.tag_name().name()));
+    } else if root.tag_name().namespace() != Some("http://www.w3.org/2000/svg") {
+        errors.push("Root <svg> is missing xmlns=\"http://www.w3.org/2000/svg\"".to_string());
+    }
+    let has_size = root.has_attribute("width") && root.has_attribute("height");
+    if !has_size && !root.has_attribute("viewBox") {
+        warnings.push("No width, height or viewBox; the size defaults to 100x100".to_string());
+    }
+    for attribute in ["width", "height"] {
+        if root.attribute(attribute).is_some_and(|value| value.trim_end().ends_with('%')) {
+            warnings.push(format!("Percentage {} is resolved against a 100x100 canvas", attribute));
+        }
+    }
+    
+    for node in root.descendants().filter(|node| node.is_element()) {
+        match node.tag_name().name() {
+            "script" => warnings.push("<script> elements are ignored".to_string()),
+            "foreignObject" => warnings.push("<foreignObject> content is not rendered".to_string()),
+            "image" => {
+                let href = node
+                    .attribute("href")
+                    .or_else(|| node.attribute(("http://www.w3.org/1999/xlink", "href")))
+                    .unwrap_or("");
+                if !href.starts_with("data:") {
+                    warnings.push(format!("External image {:?} is not loaded; embed it as a data URI", href));
+                }
+            }
+            _ => {}
+        }
+    }
+    warnings.dedup();
+    
+    (errors, warnings)
+}
+
+/// Check an SVG before exporting it, reporting every problem found instead of
+/// failing on the first, and its size when it can be rendered
+#[tauri::command]
+async fn validate_svg(
+    fonts: tauri::State<'_, FontStore>,
+    trees: tauri::State<'_, TreeCache>,
+    svg_data: String,
+) -> Result<SvgValidationResult, String> {
+    let (mut errors, warnings) = svg_structure_issues(&svg_data);
+    let mut size = None;
+    if errors.is_empty() {
+        // Parsed through the cache, so an export that follows doesn't parse again
+        match trees.get_or_parse(&svg_data, &fonts.svg_options()?) {
+            Ok(tree) => size = Some(tree.size()),
+            Err(e) => errors.push(e),
+        }
+    }
+    
+    Ok(SvgValidationResult {
+        is_valid: errors.is_empty(),
+        errors,
+        warnings,
+        width: size.map(|size| size.width()),
+        height: size.map(|size| size.height()),
+    })
+}
+
+/// Parse an absolute CSS length into pixels at 96 DPI; percentages aren't absolute
+fn parse_css_length(value: &str) -> Option<f32> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || matches!(c, '.' | '-' | '+' | 'e' | 'E')))
+        .unwrap_or(value.len());
+    let number: f32 = value[..split].parse().ok()?;
+    let per_unit = match value[split..].trim() {
+        "" | "px" => 1.0,
+        "in" => 96.0,
+        "cm" => 96.0 / 2.54,
+        "mm" => 96.0 / 25.4,
+        "pt" => 96.0 / 72.0,
+        "pc" => 16.0,
+        _ => return None,
+    };
+    Some(number * per_unit).filter(|px| *px > 0.0 && px.is_finite())
+}
+
+/// Size of an SVG in CSS pixels: its `width` and `height` when both are absolute,
+/// otherwise its `viewBox` (whose user units are CSS pixels), scaled to whichever
+/// of `width` and `height` is given
+fn svg_intrinsic_size(svg: &str) -> Option<(f32, f32)> {
+    let doc = roxmltree::Document::parse(svg).ok()?;
+    let root = doc.root_element();
+    let width = root.attribute("width").and_then(parse_css_length);
+    let height = root.attribute("height").and_then(parse_css_length);
+    if let (Some(width), Some(height)) = (width, height) {
+        return Some((width, height));
+    }
+    
+    let view_box: Vec<f32> = root
+        .attribute("viewBox")?
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    let (vb_width, vb_height) = match view_box[..] {
+        [_, _, w, h] if w > 0.0 && h > 0.0 => (w, h),
+        _ => return None,
+    };
+    Some(match (width, height) {
+        (Some(width), None) => (width, width * vb_height / vb_width),
+        (None, Some(height)) => (height * vb_width / vb_height, height),
+        _ => (vb_width, vb_height),
+    })
+}
+
+/// Export canvas as PNG image bytes. With `embed_source`, `drawing` is stored in a
+/// private chunk so `extract_drawing_from_png` can turn the image back into a drawing.
+/// A `width` and `height` of 0 use the SVG's own size in CSS pixels at 96 DPI, taken
+/// from its `width`/`height` or, failing that, its `viewBox`; when only one is 0 it
+/// follows the SVG's aspect ratio.
+#[tauri::command]
+async fn export_to_png(
+    fonts: tauri::State<'_, FontStore>,
+    trees: tauri::State<'_, TreeCache>,
+    svg_data: String,
+    width: u32,
+    height: u32,
+    embed_source: Option<bool>,
+    drawing: Option<DrawingFile>,
+) -> Result<Vec<u8>, String> {
+    let (errors, _) = svg_structure_issues(&svg_data);
+    if !errors.is_empty() {
+        return Err(format!("Invalid SVG: {}", errors.join("; ")));
+    }
+    
+    // For PNG export, we'll use resvg to render SVG to PNG
+    let tree = trees.get_or_parse(&svg_data, &fonts.svg_options()?)?;
+    
+    let intrinsic = if width == 0 || height == 0 { svg_intrinsic_size(&svg_data) } else { None };
+    let pixmap = match intrinsic {
+        Some((svg_width, svg_height)) => {
+            let (width, height) = match (width, height) {
+                (0, 0) => (svg_width.ceil() as u32, svg_height.ceil() as u32),
+                (0, height) => ((height as f32 * svg_width / svg_height).round() as u32, height),
+                (width, _) => (width, (width as f32 * svg_height / svg_width).round() as u32),
+            };
+            render_tree_fit(&tree, width.max(1), height.max(1), None)?
+        }
+        None => render_tree(&tree, width, height)?,
+    };
+    
+    let png_data = pixmap.encode_png()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    
+    if !embed_source.unwrap_or(false) {
+        return Ok(png_data);
+    }
+    let drawing = drawing.ok_or("embed_source needs the drawing to embed")?;
+    embed_drawing(&png_data, &drawing)
+}
+
+/// Export the canvas's alpha channel as a grayscale PNG, white where opaque
+#[tauri::command]
+async fn export_alpha_mask(
+    fonts: tauri::State<'_, FontStore>,
+    svg_data: String,
+    width: u32,
+    height: u32,
+) -> Result<Vec<u8>, String> {
+    render_alpha_mask(&svg_data, &fonts.svg_options()?, width, height)
+}
+
+/// The rendering behind `export_alpha_mask`
+fn render_alpha_mask(svg_data: &str, opt: &usvg::Options, width: u32, height: u32) -> Result<Vec<u8>, String> {
+    let tree = usvg::Tree::from_str(svg_data, opt)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    let pixmap = render_tree(&tree, width, height)?;
+    let alpha: Vec<u8> = pixmap.pixels().iter().map(|pixel| pixel.alpha()).collect();
+    
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, pixmap.width(), pixmap.height());
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&alpha))
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    
+    Ok(png_data)
+}
+
+/// PostScript `r g b setrgbcolor` for a paint; gradients and patterns fall back to
+/// their first stop or black, as PostScript level 2 has no direct equivalent
+fn ps_color(paint: &usvg::Paint) -> String {
+    let color = match paint {
+        usvg::Paint::Color(color) => *color,
+        usvg::Paint::LinearGradient(gradient) => gradient.stops().first().map(|s| s.color()).unwrap_or(usvg::Color::black()),
+        usvg::Paint::RadialGradient(gradient) => gradient.stops().first().map(|s| s.color()).unwrap_or(usvg::Color::black()),
+        usvg::Paint::Pattern(_) => usvg::Color::black(),
+    };
+    format!(
+        "{:.4} {:.4} {:.4} setrgbcolor",
+        color.red as f32 / 255.0,
+        color.green as f32 / 255.0,
+        color.blue as f32 / 255.0
+    )
+}
+
+/// Append PostScript drawing a usvg group (and, through flattening, its text) in
+/// SVG user coordinates
+fn write_ps_group(group: &usvg::Group, out: &mut String) {
+    use usvg::tiny_skia_path::PathSegment;
+    
+    for node in group.children() {
+        let path = match node {
+            usvg::Node::Group(group) => {
+                write_ps_group(group, out);
+                continue;
+            }
+            usvg::Node::Text(text) => {
+                write_ps_group(text.flattened(), out);
+                continue;
+            }
+            usvg::Node::Image(_) => continue,
+            usvg::Node::Path(path) => path,
+        };
+        if !path.is_visible() || (path.fill().is_none() && path.stroke().is_none()) {
+            continue;
+        }
+        
+        let t = path.abs_transform();
+        let map = |p: usvg::tiny_skia_path::Point| (t.sx * p.x + t.kx * p.y + t.tx, t.ky * p.x + t.sy * p.y + t.ty);
+        let mut current = (0.0, 0.0);
+        let mut start = (0.0, 0.0);
+        out.push_str("newpath\n");
+        for segment in path.data().segments() {
+            match segment {
+                PathSegment::MoveTo(p) => {
+                    current = map(p);
+                    start = current;
+                    out.push_str(&format!("{:.3} {:.3} moveto\n", current.0, current.1));
+                }
+                PathSegment::LineTo(p) => {
+                    current = map(p);
+                    out.push_str(&format!("{:.3} {:.3} lineto\n", current.0, current.1));
+                }
+                PathSegment::QuadTo(p1, p) => {
+                    // Raise the quadratic to a cubic
+                    let (c, end) = (map(p1), map(p));
+                    let c1 = (current.0 + 2.0 / 3.0 * (c.0 - current.0), current.1 + 2.0 / 3.0 * (c.1 - current.1));
+                    let c2 = (end.0 + 2.0 / 3.0 * (c.0 - end.0), end.1 + 2.0 / 3.0 * (c.1 - end.1));
+                    out.push_str(&format!(
+                        "{:.3} {:.3} {:.3} {:.3} {:.3} {:.3} curveto\n",
+                        c1.0, c1.1, c2.0, c2.1, end.0, end.1
+                    ));
+                    current = end;
+                }
+                PathSegment::CubicTo(p1, p2, p) => {
+                    let (c1, c2, end) = (map(p1), map(p2), map(p));
+                    out.push_str(&format!(
+                        "{:.3} {:.3} {:.3} {:.3} {:.3} {:.3} curveto\n",
+                        c1.0, c1.1, c2.0, c2.1, end.0, end.1
+                    ));
+                    current = end;
+                }
+                PathSegment::Close => {
+                    current = start;
+                    out.push_str("closepath\n");
+                }
+            }
+        }
+        
+        if let Some(fill) = path.fill() {
+            let op = match fill.rule() {
+                usvg::FillRule::EvenOdd => "eofill",
+                usvg::FillRule::NonZero => "fill",
+            };
+            let keep = if path.stroke().is_some() { "gsave " } else { "" };
+            let restore = if path.stroke().is_some() { " grestore" } else { "" };
+            out.push_str(&format!("{}{} {}{}\n", keep, ps_color(fill.paint()), op, restore));
+        }
+        if let Some(stroke) = path.stroke() {
+            // Points are already transformed, so scale the width by the transform too
+            let scale = (t.sx * t.sy - t.kx * t.ky).abs().sqrt();
+            let cap = match stroke.linecap() {
+                usvg::LineCap::Butt => 0,
+                usvg::LineCap::Round => 1,
+                usvg::LineCap::Square => 2,
+            };
+            let join = match stroke.linejoin() {
+                usvg::LineJoin::Miter | usvg::LineJoin::MiterClip => 0,
+                usvg::LineJoin::Round => 1,
+                usvg::LineJoin::Bevel => 2,
+            };
+            out.push_str(&format!(
+                "{} {:.3} setlinewidth {} setlinecap {} setlinejoin stroke\n",
+                ps_color(stroke.paint()),
+                stroke.width().get() * scale,
+                cap,
+                join
+            ));
+        }
+    }
+}
+
+/// Export the canvas as Encapsulated PostScript at `width_pt`×`height_pt` points.
+/// Shapes and text (as outlines) are kept as vectors; images are dropped and
+/// transparency and gradients are flattened to solid colors.
+#[tauri::command]
+async fn export_to_eps(
+    fonts: tauri::State<'_, FontStore>,
+    svg_data: String,
+    width_pt: f32,
+    height_pt: f32,
+) -> Result<Vec<u8>, String> {
+    if !(width_pt > 0.0 && height_pt > 0.0) {
+        return Err("EPS size must be positive".to_string());
+    }
+    let tree = usvg::Tree::from_str(&svg_data, &fonts.svg_options()?)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    let size = tree.size();
+    
+    let mut eps = String::new();
+    eps.push_str("%!PS-Adobe-3.0 EPSF-3.0\n");
+    eps.push_str(&format!("%%BoundingBox: 0 0 {} {}\n", width_pt.ceil() as u32, height_pt.ceil() as u32));
+    eps.push_str(&format!("%%HiResBoundingBox: 0 0 {:.3} {:.3}\n", width_pt, height_pt));
+    eps.push_str("%%Creator: Jamal\n%%LanguageLevel: 2\n%%EndComments\n");
+    // SVG's y axis points down, PostScript's up
+    eps.push_str(&format!(
+        "gsave\n0 {:.3} translate\n{:.6} {:.6} scale\n",
+        height_pt,
+        width_pt / size.width(),
+        -height_pt / size.height()
+    ));
+    write_ps_group(tree.root(), &mut eps);
+    eps.push_str("grestore\nshowpage\n%%EOF\n");
+    
+    Ok(eps.into_bytes())
+}
+
+/// Render a Deep Zoom (DZI) image pyramid for web viewers such as OpenSeadragon into
+/// `output_dir`: `drawing.dzi` and `drawing_files/<level>/<column>_<row>.png`. The
+/// full-resolution level's longer side is `base_dimension` pixels. Every tile is
+/// rendered on its own, so memory use depends on `tile_size`, not on the image size.
+/// Returns the descriptor's path.
+#[tauri::command]
+async fn export_dzi(
+    app: tauri::AppHandle,
+    fonts: tauri::State<'_, FontStore>,
+    svg_data: String,
+    tile_size: u32,
+    base_dimension: u32,
+    output_dir: String,
+) -> Result<String, String> {
+    let options = fonts.svg_options()?;
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &output_dir)?;
+        write_dzi(&svg_data, &options, tile_size, base_dimension, Path::new(&output_dir))
+            .map(|descriptor_path| descriptor_path.to_string_lossy().to_string())
+    })
+    .await
+}
+
+/// The pyramid behind `export_dzi`; returns the descriptor's path
+fn write_dzi(svg_data: &str, opt: &usvg::Options, tile_size: u32, base_dimension: u32, output_dir: &Path) -> Result<PathBuf, String> {
+    if !(1..=4096).contains(&tile_size) {
+        return Err(format!("Tile size must be between 1 and 4096, got {}", tile_size));
+    }
+    if base_dimension == 0 {
+        return Err("Base dimension must be at least 1 pixel".to_string());
+    }
+    check_export_memory(tile_size, tile_size, "png")?;
+    let tree = usvg::Tree::from_str(svg_data, opt)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    
+    let size = tree.size();
+    let full_scale = base_dimension as f32 / size.width().max(size.height());
+    let full_width = ((size.width() * full_scale).round() as u32).max(1);
+    let full_height = ((size.height() * full_scale).round() as u32).max(1);
+    // Level n is 2^n pixels across at most; the top level is full size
+    let max_level = 32 - (full_width.max(full_height) - 1).leading_zeros();
+    
+    let files_dir = output_dir.join("drawing_files");
+    for level in 0..=max_level {
+        let divisor = 2f64.powi((max_level - level) as i32);
+        let width = ((full_width as f64 / divisor).ceil() as u32).max(1);
+        let height = ((full_height as f64 / divisor).ceil() as u32).max(1);
+        let scale = full_scale / divisor as f32;
+        
+        let level_dir = files_dir.join(level.to_string());
+        fs::create_dir_all(&level_dir).map_err(|e| format!("Failed to create {}: {}", level_dir.display(), e))?;
+        for row in 0..height.div_ceil(tile_size) {
+            for column in 0..width.div_ceil(tile_size) {
+                let (x, y) = (column * tile_size, row * tile_size);
+                let mut tile = tiny_skia::Pixmap::new(tile_size.min(width - x), tile_size.min(height - y))
+                    .ok_or("Failed to create pixmap")?;
+                let transform = tiny_skia::Transform::from_scale(scale, scale).post_translate(-(x as f32), -(y as f32));
+                resvg::render(&tree, transform, &mut tile.as_mut());
+                
+                let tile_path = level_dir.join(format!("{}_{}.png", column, row));
+                tile.save_png(&tile_path)
+                    .map_err(|e| format!("Failed to save {}: {}", tile_path.display(), e))?;
+            }
+        }
+    }
+    
+    let descriptor = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" TileSize=\"{}\" Overlap=\"0\" Format=\"png\">\
+         <Size Width=\"{}\" Height=\"{}\"/></Image>\n",
+        tile_size, full_width, full_height
+    );
+    let descriptor_path = output_dir.join("drawing.dzi");
+    fs::write(&descriptor_path, descriptor).map_err(|e| format!("Failed to save DZI descriptor: {}", e))?;
+    Ok(descriptor_path)
+}
+
+/// Most colors `extract_palette` returns
+const MAX_PALETTE_COLORS: u32 = 32;
+
+/// Colors kept in a drawing's `palette`, filled in on save
+const SAVED_PALETTE_COLORS: u32 = 8;
+
+/// Find the dominant colors of an SVG as `#rrggbb`, most common first, by k-means
+/// clustering in Lab over a 100×100 rendering. Transparent areas are ignored.
+#[tauri::command]
+async fn extract_palette(
+    fonts: tauri::State<'_, FontStore>,
+    svg_data: String,
+    max_colors: u32,
+) -> Result<Vec<String>, String> {
+    dominant_colors(&svg_data, &fonts.svg_options()?, max_colors)
+}
+
+/// The clustering behind `extract_palette`
+fn dominant_colors(svg_data: &str, opt: &usvg::Options, max_colors: u32) -> Result<Vec<String>, String> {
+    use kmeans_colors::{get_kmeans_hamerly, Kmeans, Sort};
+    use palette::{FromColor, IntoColor, Lab, Srgb};
+    
+    if !(1..=MAX_PALETTE_COLORS).contains(&max_colors) {
+        return Err(format!("Color count must be between 1 and {}, got {}", MAX_PALETTE_COLORS, max_colors));
+    }
+    let tree = usvg::Tree::from_str(svg_data, opt)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    let pixmap = render_tree_fit(&tree, 100, 100, None)?;
+    
+    let lab: Vec<Lab> = pixmap
+        .pixels()
+        .iter()
+        .map(|pixel| pixel.demultiply())
+        .filter(|pixel| pixel.alpha() >= 128)
+        .map(|pixel| {
+            Srgb::new(pixel.red(), pixel.green(), pixel.blue())
+                .into_format::<f32>()
+                .into_linear()
+                .into_color()
+        })
+        .collect();
+    if lab.is_empty() {
+        return Ok(Vec::new());
+    }
+    
+    // k-means depends on its starting points; keep the best of a few seeds
+    let mut best = Kmeans::new();
+    for seed in 0..3 {
+        let run = get_kmeans_hamerly(max_colors as usize, 20, 5.0, false, &lab, seed);
+        if run.score < best.score {
+            best = run;
+        }
+    }
+    let mut colors = Lab::sort_indexed_colors(&best.centroids, &best.indices);
+    colors.sort_by(|a, b| b.percentage.total_cmp(&a.percentage));
+    
+    Ok(colors
+        .iter()
+        .filter(|color| color.percentage > 0.0)
+        .map(|color| {
+            let rgb: Srgb<u8> = Srgb::from_color(color.centroid).into_format();
+            format!("#{:02x}{:02x}{:02x}", rgb.red, rgb.green, rgb.blue)
+        })
+        .collect())
+}
+
+/// A drawing saved with a `previewSvg`, with its `palette` taken from that preview.
+/// `None` for other content and for drawings whose palette is already current
+fn with_palette(content: &str, opt: &usvg::Options) -> Option<String> {
+    if !content.contains("\"previewSvg\"") {
+        return None;
+    }
+    let mut drawing: DrawingFile = serde_json::from_str(content).ok()?;
+    let preview = drawing.extra.get("previewSvg")?.as_str()?;
+    let palette = dominant_colors(preview, opt, SAVED_PALETTE_COLORS).ok()?;
+    if drawing.palette.as_ref() == Some(&palette) {
+        return None;
+    }
+    drawing.palette = Some(palette);
+    serde_json::to_string_pretty(&drawing).ok()
+}
+
+/// Package an SVG as an OpenDocument Drawing that LibreOffice Draw opens natively.
+/// The page is sized to the drawing, which sits in a frame as the SVG itself, with
+/// a PNG rendering as the fallback for readers without SVG support.
+#[tauri::command]
+async fn export_to_odg(fonts: tauri::State<'_, FontStore>, svg_data: String) -> Result<Vec<u8>, String> {
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    
+    let tree = usvg::Tree::from_str(&svg_data, &fonts.svg_options()?)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    let fallback = render_tree(&tree, 0, 0)?
+        .encode_png()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    // CSS pixels are 1/96 inch
+    let width_cm = tree.size().width() * 2.54 / 96.0;
+    let height_cm = tree.size().height() * 2.54 / 96.0;
+    
+    let styles = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <office:document-styles xmlns:office=\"urn:oasis:names:tc:opendocument:xmlns:office:1.0\" \
+         xmlns:style=\"urn:oasis:names:tc:opendocument:xmlns:style:1.0\" \
+         xmlns:fo=\"urn:oasis:names:tc:opendocument:xmlns:xsl-fo-compatible:1.0\" office:version=\"1.3\">\
+         <office:automatic-styles><style:page-layout style:name=\"PM1\">\
+         <style:page-layout-properties fo:page-width=\"{w:.3}cm\" fo:page-height=\"{h:.3}cm\" \
+         fo:margin-top=\"0cm\" fo:margin-bottom=\"0cm\" fo:margin-left=\"0cm\" fo:margin-right=\"0cm\"/>\
+         </style:page-layout></office:automatic-styles>\
+         <office:master-styles><style:master-page style:name=\"Default\" style:page-layout-name=\"PM1\"/>\
+         </office:master-styles></office:document-styles>",
+        w = width_cm,
+        h = height_cm
+    );
+    let content = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <office:document-content xmlns:office=\"urn:oasis:names:tc:opendocument:xmlns:office:1.0\" \
+         xmlns:draw=\"urn:oasis:names:tc:opendocument:xmlns:drawing:1.0\" \
+         xmlns:svg=\"urn:oasis:names:tc:opendocument:xmlns:svg-compatible:1.0\" \
+         xmlns:xlink=\"http://www.w3.org/1999/xlink\" \
+         xmlns:loext=\"urn:org:documentfoundation:names:experimental:office:xmlns:loext:1.0\" office:version=\"1.3\">\
+         <office:body><office:drawing><draw:page draw:name=\"page1\" draw:master-page-name=\"Default\">\
+         <draw:frame svg:x=\"0cm\" svg:y=\"0cm\" svg:width=\"{w:.3}cm\" svg:height=\"{h:.3}cm\">\
+         <draw:image xlink:href=\"Pictures/drawing.svg\" xlink:type=\"simple\" xlink:show=\"embed\" \
+         xlink:actuate=\"onLoad\" loext:mime-type=\"image/svg+xml\"/>\
+         <draw:image xlink:href=\"Pictures/drawing.png\" xlink:type=\"simple\" xlink:show=\"embed\" \
+         xlink:actuate=\"onLoad\" loext:mime-type=\"image/png\"/>\
+         </draw:frame></draw:page></office:drawing></office:body></office:document-content>",
+        w = width_cm,
+        h = height_cm
+    );
+    let manifest = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <manifest:manifest xmlns:manifest=\"urn:oasis:names:tc:opendocument:xmlns:manifest:1.0\" manifest:version=\"1.3\">\
+         <manifest:file-entry manifest:full-path=\"/\" manifest:version=\"1.3\" \
+         manifest:media-type=\"application/vnd.oasis.opendocument.graphics\"/>\
+         <manifest:file-entry manifest:full-path=\"content.xml\" manifest:media-type=\"text/xml\"/>\
+         <manifest:file-entry manifest:full-path=\"styles.xml\" manifest:media-type=\"text/xml\"/>\
+         <manifest:file-entry manifest:full-path=\"Pictures/drawing.svg\" manifest:media-type=\"image/svg+xml\"/>\
+         <manifest:file-entry manifest:full-path=\"Pictures/drawing.png\" manifest:media-type=\"image/png\"/>\
+         </manifest:manifest>";
+    
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    // The mimetype must come first and uncompressed so the format can be sniffed
+    let stored = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    let deflated = SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    let entries: [(&str, SimpleFileOptions, &[u8]); 6] = [
+        ("mimetype", stored, b"application/vnd.oasis.opendocument.graphics"),
+        ("content.xml", deflated, content.as_bytes()),
+        ("styles.xml", deflated, styles.as_bytes()),
+        ("Pictures/drawing.svg", deflated, svg_data.as_bytes()),
+        // Already compressed
+        ("Pictures/drawing.png", stored, &fallback),
+        ("META-INF/manifest.xml", deflated, manifest.as_bytes()),
+    ];
+    for (name, options, data) in entries {
+        zip.start_file(name, options)
+            .and_then(|_| zip.write_all(data).map_err(Into::into))
+            .map_err(|e| format!("Failed to write {}: {}", name, e))?;
+    }
+    
+    let package = zip.finish().map_err(|e| format!("Failed to finish ODG package: {}", e))?;
+    Ok(package.into_inner())
+}
+
+/// Export a PNG at a social media preset size, centered and letterboxed on `background`
+#[tauri::command]
+async fn export_social(
+    fonts: tauri::State<'_, FontStore>,
+    svg_data: String,
+    platform: SocialPreset,
+    background: Option<String>,
+) -> Result<SocialExport, String> {
+    render_social(&svg_data, &fonts.svg_options()?, platform, background.as_deref().unwrap_or("#ffffff"))
+}
+
+/// The rendering behind `export_social`
+fn render_social(svg_data: &str, opt: &usvg::Options, platform: SocialPreset, background: &str) -> Result<SocialExport, String> {
+    let background = parse_hex_color(background)?;
+    let tree = usvg::Tree::from_str(svg_data, opt)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    
+    let (width, height) = platform.dimensions();
+    let data = render_tree_fit(&tree, width, height, Some(background))?
+        .encode_png()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    
+    Ok(SocialExport { data, width, height })
+}
+
+/// Convert millimetres to whole pixels at a resolution
+fn mm_to_px(mm: f32, dpi: f32) -> u32 {
+    (mm / 25.4 * dpi).round() as u32
+}
+
+/// Page width, height and margin in pixels for `export_to_paper`
+fn paper_pixels(paper: PaperSize, orientation: Orientation, dpi: f32, margin_mm: f32) -> Result<(u32, u32, u32), String> {
+    if !(1.0..=1200.0).contains(&dpi) {
+        return Err(format!("DPI must be between 1 and 1200, got {}", dpi));
+    }
+    let (short_mm, long_mm) = paper.dimensions_mm();
+    let (width_mm, height_mm) = match orientation {
+        Orientation::Portrait => (short_mm, long_mm),
+        Orientation::Landscape => (long_mm, short_mm),
+    };
+    if margin_mm.is_nan() || margin_mm < 0.0 || margin_mm * 2.0 >= short_mm {
+        return Err(format!("Margin must be between 0 and {} mm, got {}", short_mm / 2.0, margin_mm));
+    }
+    Ok((mm_to_px(width_mm, dpi), mm_to_px(height_mm, dpi), mm_to_px(margin_mm, dpi)))
+}
+
+/// Export a PNG sized for printing on `paper` at `dpi`, e.g. 2480×3508 for portrait
+/// A4 at 300 DPI. The drawing is fitted within `margin_mm` on a white page and the
+/// resolution is recorded in the PNG so print dialogs pick the right size.
+#[tauri::command]
+async fn export_to_paper(
+    fonts: tauri::State<'_, FontStore>,
+    svg_data: String,
+    paper: PaperSize,
+    orientation: Orientation,
+    dpi: f32,
+    margin_mm: f32,
+) -> Result<Vec<u8>, String> {
+    let (width, height, margin) = paper_pixels(paper, orientation, dpi, margin_mm)?;
+    check_export_memory(width, height, "png")?;
+    
+    let tree = usvg::Tree::from_str(&svg_data, &fonts.svg_options()?)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    let content = render_tree_fit(&tree, width - 2 * margin, height - 2 * margin, None)?;
+    let mut page = tiny_skia::Pixmap::new(width, height).ok_or("Failed to create pixmap")?;
+    page.fill(tiny_skia::Color::WHITE);
+    page.draw_pixmap(
+        margin as i32,
+        margin as i32,
+        content.as_ref(),
+        &tiny_skia::PixmapPaint::default(),
+        tiny_skia::Transform::identity(),
+        None,
+    );
+    
+    // The page is opaque, so its premultiplied pixels are also straight RGBA
+    let pixels_per_meter = (dpi / 0.0254).round() as u32;
+    let mut png_data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut png_data, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_pixel_dims(Some(png::PixelDimensions {
+        xppu: pixels_per_meter,
+        yppu: pixels_per_meter,
+        unit: png::Unit::Meter,
+    }));
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(page.data()))
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    Ok(png_data)
+}
+
+/// Stroke `path` onto `pixmap` with a solid black hairline
+fn stroke_black(pixmap: &mut tiny_skia::Pixmap, path: Option<tiny_skia::Path>, width: f32) {
+    if let Some(path) = path {
+        let mut paint = tiny_skia::Paint::default();
+        paint.set_color(tiny_skia::Color::BLACK);
+        paint.anti_alias = true;
+        let stroke = tiny_skia::Stroke { width, ..Default::default() };
+        pixmap.stroke_path(&path, &paint, &stroke, tiny_skia::Transform::identity(), None);
+    }
+}
+
+/// Export a `width`×`height` PNG for a print shop. The SVG's viewBox is grown by
+/// `bleed_mm` (at 96 DPI) on each side so artwork past the trim edge is kept, and
+/// crop and registration marks are drawn in the bleed area when requested.
+#[tauri::command]
+async fn export_to_png_for_print(
+    fonts: tauri::State<'_, FontStore>,
+    trees: tauri::State<'_, TreeCache>,
+    svg_data: String,
+    width: u32,
+    height: u32,
+    print_settings: ExportPrintSettings,
+) -> Result<Vec<u8>, String> {
+    let bleed_mm = print_settings.bleed_mm;
+    if !(0.0..=50.0).contains(&bleed_mm) {
+        return Err(format!("Bleed must be between 0 and 50 mm, got {}", bleed_mm));
+    }
+    if bleed_mm == 0.0 && (print_settings.crop_marks || print_settings.registration_marks) {
+        return Err("Crop and registration marks need a bleed to be drawn in".to_string());
+    }
+    let (errors, _) = svg_structure_issues(&svg_data);
+    if !errors.is_empty() {
+        return Err(format!("Invalid SVG: {}", errors.join("; ")));
+    }
+    check_export_memory(width, height, "png")?;
+    
+    let tree = trees.get_or_parse(&svg_data, &fonts.svg_options()?)?;
+    let size = tree.size();
+    let bleed = bleed_mm / 25.4 * 96.0;
+    let scale = (width as f32 / (size.width() + 2.0 * bleed))
+        .min(height as f32 / (size.height() + 2.0 * bleed));
+    let trim_width = size.width() * scale;
+    let trim_height = size.height() * scale;
+    let left = (width as f32 - trim_width) / 2.0;
+    let top = (height as f32 - trim_height) / 2.0;
+    
+    // Content outside the viewBox isn't clipped, so offsetting the render is the
+    // same as widening the viewBox by the bleed
+    let mut pixmap = tiny_skia::Pixmap::new(width, height).ok_or("Failed to create pixmap")?;
+    let transform = tiny_skia::Transform::from_scale(scale, scale).post_translate(left, top);
+    resvg::render(&tree, transform, &mut pixmap.as_mut());
+    
+    let bleed_px = bleed * scale;
+    let line_width = (0.25 / 72.0 * 96.0 * scale).max(1.0);
+    let (right, bottom) = (left + trim_width, top + trim_height);
+    if print_settings.crop_marks {
+        // Marks run from the image edge towards each trim corner, stopping short of it
+        let gap = bleed_px * 0.25;
+        let mut path = tiny_skia::PathBuilder::new();
+        for (x, outer_x, inner_x) in [(left, left - bleed_px, left - gap), (right, right + bleed_px, right + gap)] {
+            for (y, outer_y, inner_y) in [(top, top - bleed_px, top - gap), (bottom, bottom + bleed_px, bottom + gap)] {
+                path.move_to(outer_x, y);
+                path.line_to(inner_x, y);
+                path.move_to(x, outer_y);
+                path.line_to(x, inner_y);
+            }
+        }
+        stroke_black(&mut pixmap, path.finish(), line_width);
+    }
+    if print_settings.registration_marks {
+        // A circled crosshair centered in the bleed on each side
+        let radius = bleed_px * 0.3;
+        let centre_x = (left + right) / 2.0;
+        let centre_y = (top + bottom) / 2.0;
+        let half = bleed_px / 2.0;
+        for (x, y) in [(centre_x, top - half), (centre_x, bottom + half), (left - half, centre_y), (right + half, centre_y)] {
+            stroke_black(&mut pixmap, tiny_skia::PathBuilder::from_circle(x, y, radius), line_width);
+            let mut cross = tiny_skia::PathBuilder::new();
+            cross.move_to(x - radius * 1.4, y);
+            cross.line_to(x + radius * 1.4, y);
+            cross.move_to(x, y - radius * 1.4);
+            cross.line_to(x, y + radius * 1.4);
+            stroke_black(&mut pixmap, cross.finish(), line_width);
+        }
+    }
+    
+    pixmap.encode_png().map_err(|e| format!("Failed to encode PNG: {}", e))
+}
+
+/// Render one SVG per undo step as `frame_0001.png`, `frame_0002.png`, … in `output_dir`,
+/// each fitted onto a white `frame_width`×`frame_height` frame. The frontend produces
+/// the SVGs by replaying the drawing's history, since the store can only be rendered there.
+#[tauri::command]
+async fn export_timelapse(
+    app: tauri::AppHandle,
+    fonts: tauri::State<'_, FontStore>,
+    svg_frames: Vec<String>,
+    frame_width: u32,
+    frame_height: u32,
+    output_dir: String,
+) -> Result<Vec<String>, String> {
+    let options = fonts.svg_options()?;
+    run_blocking(app, move |app| {
+        if svg_frames.is_empty() {
+            return Err("No frames to export".to_string());
+        }
+        let output_dir = check_path_scope(&app, &output_dir)?;
+        fs::create_dir_all(&output_dir).map_err(|e| format!("Failed to create {}: {}", output_dir.display(), e))?;
+        
+        let mut written = Vec::with_capacity(svg_frames.len());
+        for (i, svg) in svg_frames.iter().enumerate() {
+            let tree = usvg::Tree::from_str(svg, &options)
+                .map_err(|e| format!("Failed to parse SVG of frame {}: {}", i + 1, e))?;
+            let png = render_tree_fit(&tree, frame_width, frame_height, Some(tiny_skia::Color::WHITE))?
+                .encode_png()
+                .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+            
+            let frame_path = output_dir.join(format!("frame_{:04}.png", i + 1));
+            fs::write(&frame_path, png).map_err(|e| format!("Failed to save {}: {}", frame_path.display(), e))?;
+            written.push(frame_path.to_string_lossy().to_string());
+        }
+        
+        Ok(written)
+    })
+    .await
+}
+
+/// Find ffmpeg, preferring a copy bundled with the app over one on the `PATH`
+fn find_ffmpeg<R: Runtime>(app: &tauri::AppHandle<R>) -> Result<PathBuf, String> {
+    let binary = if cfg!(windows) { "ffmpeg.exe" } else { "ffmpeg" };
+    let bundled = app.path().resource_dir().ok().map(|dir| dir.join(binary));
+    if let Some(bundled) = bundled.filter(|path| path.is_file()) {
+        return Ok(bundled);
+    }
+    which::which("ffmpeg").map_err(|_| {
+        "ffmpeg was not found. Install it from https://ffmpeg.org/download.html \
+         (or with your package manager, e.g. `brew install ffmpeg`, `winget install ffmpeg` \
+         or `sudo apt install ffmpeg`) and make sure it is on your PATH"
+            .to_string()
+    })
+}
+
+/// Combine PNG frames, such as those from `export_timelapse`, into an H.264 MP4
+/// with ffmpeg, emitting a `video-export-progress` event per frame
+#[tauri::command]
+async fn export_to_video(
+    app: tauri::AppHandle,
+    frame_paths: Vec<String>,
+    output_path: String,
+    fps: u32,
+) -> Result<(), String> {
+    use std::io::{Read, Write};
+    
+    if frame_paths.is_empty() {
+        return Err("No frames to export".to_string());
+    }
+    if !(1..=120).contains(&fps) {
+        return Err(format!("Frame rate must be between 1 and 120, got {}", fps));
+    }
+    for frame_path in &frame_paths {
+        check_path_scope(&app, frame_path)?;
+    }
+    check_path_scope(&app, &output_path)?;
+    let ffmpeg = find_ffmpeg(&app)?;
+    
+    // Encoding takes a while; keep it off the async runtime
+    tauri::async_runtime::spawn_blocking(move || {
+        let mut child = std::process::Command::new(&ffmpeg)
+            .args(["-y", "-loglevel", "error", "-f", "image2pipe", "-c:v", "png", "-framerate"])
+            .arg(fps.to_string())
+            .args(["-i", "-", "-c:v", "libx264", "-pix_fmt", "yuv420p", "-movflags", "+faststart"])
+            // H.264 with yuv420p needs even dimensions
+            .args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"])
+            .arg(&output_path)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to start ffmpeg: {}", e))?;
+        
+        // Drain stderr alongside so ffmpeg can't block on a full pipe
+        let mut stderr = child.stderr.take().ok_or("Failed to capture ffmpeg output")?;
+        let errors = std::thread::spawn(move || {
+            let mut output = String::new();
+            let _ = stderr.read_to_string(&mut output);
+            output
+        });
+        
+        let total = frame_paths.len() as u32;
+        let mut stdin = child.stdin.take().ok_or("Failed to open ffmpeg input")?;
+        let mut result = Ok(());
+        for (i, frame_path) in frame_paths.iter().enumerate() {
+            let frame = match fs::read(frame_path) {
+                Ok(frame) => frame,
+                Err(e) => {
+                    result = Err(format!("Failed to read frame {}: {}", frame_path, e));
+                    break;
+                }
+            };
+            if let Err(e) = stdin.write_all(&frame) {
+                result = Err(format!("Failed to send frame {} to ffmpeg: {}", frame_path, e));
+                break;
+            }
+            let _ = app.emit("video-export-progress", VideoExportProgress { frame: i as u32 + 1, total });
+        }
+        // Closing stdin tells ffmpeg the input is complete
+        drop(stdin);
+        
+        let status = child.wait().map_err(|e| format!("Failed to wait for ffmpeg: {}", e))?;
+        let errors = errors.join().unwrap_or_default();
+        if !status.success() {
+            result = Err(format!("ffmpeg failed ({}): {}", status, errors.trim()));
+        }
+        if result.is_err() {
+            // Don't leave a truncated video behind
+            let _ = fs::remove_file(&output_path);
+        }
+        result
+    })
+    .await
+    .map_err(|e| format!("Failed to export video: {}", e))?
+}
+
+/// Render an SVG and write it as a PNG file
+fn render_svg_to_png_file(svg: &str, opt: &usvg::Options, width: u32, height: u32, output: &Path) -> Result<(), String> {
+    let tree = usvg::Tree::from_str(svg, opt).map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    let png_data = render_tree(&tree, width, height)?
+        .encode_png()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
+    fs::write(output, png_data).map_err(|e| format!("Failed to save PNG: {}", e))
+}
+
+/// Run export jobs, each paired with its output path, on a pool using all cores but
+/// one, emitting `batch-export-progress` as each finishes; see `export_in_parallel`
+async fn run_parallel_exports<J, F>(
+    app: tauri::AppHandle,
+    jobs: Vec<(String, J)>,
+    export: F,
+) -> Result<Vec<ExportJobResult>, String>
+where
+    J: Send + Sync + 'static,
+    F: Fn(&J) -> Result<(), String> + Send + Sync + 'static,
+{
+    tauri::async_runtime::spawn_blocking(move || {
+        export_in_parallel(&jobs, export, |progress| {
+            let _ = app.emit("batch-export-progress", progress);
+        })
+    })
+    .await
+    .map_err(|e| format!("Failed to run batch export: {}", e))?
+}
+
+/// Run export jobs on a pool using all cores but one, reporting each as it finishes.
+/// A worker finishes its job before taking the next, so no more pixmaps than workers
+/// are held at once. Results are in input order; a failed job doesn't stop the others.
+fn export_in_parallel<J, F, P>(jobs: &[(String, J)], export: F, progress: P) -> Result<Vec<ExportJobResult>, String>
+where
+    J: Sync,
+    F: Fn(&J) -> Result<(), String> + Sync,
+    P: Fn(BatchExportProgress) + Sync,
+{
+    use rayon::prelude::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    
+    let workers = std::thread::available_parallelism()
+        .map(|n| n.get().saturating_sub(1).max(1))
+        .unwrap_or(1);
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(workers)
+        .build()
+        .map_err(|e| format!("Failed to start export workers: {}", e))?;
+    
+    let total = jobs.len() as u32;
+    let completed = AtomicU32::new(0);
+    Ok(pool.install(|| {
+        jobs.par_iter()
+            .enumerate()
+            .map(|(index, (output_path, job))| {
+                let error = export(job).err();
+                progress(BatchExportProgress {
+                    index: index as u32,
+                    completed: completed.fetch_add(1, Ordering::Relaxed) + 1,
+                    total,
+                    error: error.clone(),
+                });
+                ExportJobResult {
+                    output_path: output_path.clone(),
+                    error,
+                }
+            })
+            .collect()
+    }))
+}
+
+/// Entry points for the benchmarks in `benches/`, built with the `bench` feature
+#[cfg(feature = "bench")]
+pub mod bench {
+    use super::*;
+    
+    /// Render each SVG to its PNG file, one after another
+    pub fn export_sequential(jobs: &[ExportJob]) -> Result<(), String> {
+        let opt = usvg::Options::default();
+        for job in jobs {
+            render_svg_to_png_file(&job.svg_data, &opt, job.width, job.height, Path::new(&job.output_path))?;
+        }
+        Ok(())
+    }
+    
+    /// Render each SVG to its PNG file the way `batch_export` does
+    pub fn export_parallel(jobs: &[ExportJob]) -> Result<(), String> {
+        let opt = usvg::Options::default();
+        let jobs: Vec<_> = jobs.iter().map(|job| (job.output_path.clone(), job)).collect();
+        let results = export_in_parallel(&jobs, |job| {
+            render_svg_to_png_file(&job.svg_data, &opt, job.width, job.height, Path::new(&job.output_path))
+        }, |_| {})?;
+        match results.into_iter().find_map(|result| result.error) {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Render several SVGs to PNG files in parallel; see `run_parallel_exports`
+#[tauri::command]
+async fn batch_export(
+    app: tauri::AppHandle,
+    fonts: tauri::State<'_, FontStore>,
+    jobs: Vec<ExportJob>,
+) -> Result<Vec<ExportJobResult>, String> {
+    for job in &jobs {
+        check_path_scope(&app, &job.output_path)?;
+    }
+    let opt = fonts.svg_options()?;
+    let jobs = jobs.into_iter().map(|job| (job.output_path.clone(), job)).collect();
+    run_parallel_exports(app, jobs, move |job: &ExportJob| {
+        render_svg_to_png_file(&job.svg_data, &opt, job.width, job.height, Path::new(&job.output_path))
+    })
+    .await
+}
+
+/// Render every `.svg` file in `input_dir` to a PNG of the same name in `output_dir`,
+/// in parallel and with `batch-export-progress` events like `batch_export`. A zero
+/// `width` or `height` uses each SVG's own size.
+#[tauri::command]
+async fn batch_svg_to_png(
+    app: tauri::AppHandle,
+    fonts: tauri::State<'_, FontStore>,
+    input_dir: String,
+    output_dir: String,
+    width: u32,
+    height: u32,
+) -> Result<BatchResult, String> {
+    let opt = fonts.svg_options()?;
+    let jobs: Vec<(String, (PathBuf, PathBuf))> = run_blocking(app.clone(), move |app| {
+        check_path_scope(&app, &input_dir)?;
+        check_path_scope(&app, &output_dir)?;
+        svg_to_png_jobs(&input_dir, &output_dir)
+    })
+    .await?
+    .into_iter()
+    .map(|(input, output)| (output.to_string_lossy().to_string(), (input, output)))
+    .collect();
+    let results = run_parallel_exports(app, jobs, move |(input, output): &(PathBuf, PathBuf)| {
+        let svg = fs::read_to_string(input).map_err(|e| format!("Failed to read {}: {}", input.display(), e))?;
+        render_svg_to_png_file(&svg, &opt, width, height, output)
+    })
+    .await?;
+    
+    let failed = results.iter().filter(|result| result.error.is_some()).count() as u32;
+    Ok(BatchResult {
+        succeeded: results.len() as u32 - failed,
+        failed,
+        results,
+    })
+}
+
+/// The `.svg` files in `input_dir`, sorted, each paired with the PNG to write for it
+/// in `output_dir`, which is created
+fn svg_to_png_jobs(input_dir: &str, output_dir: &str) -> Result<Vec<(PathBuf, PathBuf)>, String> {
+    let mut inputs: Vec<PathBuf> = fs::read_dir(input_dir)
+        .map_err(|e| format!("Failed to read {}: {}", input_dir, e))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("svg")))
+        .collect();
+    inputs.sort();
+    fs::create_dir_all(output_dir).map_err(|e| format!("Failed to create {}: {}", output_dir, e))?;
+    
+    Ok(inputs
+        .into_iter()
+        .map(|input| {
+            let output = Path::new(output_dir).join(input.with_extension("png").file_name().unwrap_or_default());
+            (input, output)
+        })
+        .collect())
+}
+
+/// Largest thumbnail OS thumbnailers ask for
+const MAX_THUMBNAIL_SIZE: u32 = 1024;
+
+/// Render a square PNG thumbnail for OS file browsers, centered on a transparent
+/// background. `path` is an SVG, or a drawing saved with a `previewSvg` field.
+#[tauri::command]
+async fn generate_os_thumbnail(
+    app: tauri::AppHandle,
+    fonts: tauri::State<'_, FontStore>,
+    path: String,
+    size: u32,
+) -> Result<Vec<u8>, String> {
+    let options = fonts.svg_options()?;
+    run_blocking(app, move |app| os_thumbnail(&check_path_scope(&app, &path)?, &options, size)).await
+}
+
+/// The rendering behind `generate_os_thumbnail`
+fn os_thumbnail(path: &Path, opt: &usvg::Options, size: u32) -> Result<Vec<u8>, String> {
+    if size == 0 || size > MAX_THUMBNAIL_SIZE {
+        return Err(format!("Thumbnail size must be between 1 and {}", MAX_THUMBNAIL_SIZE));
+    }
+    
+    let content = read_text_content(path)?;
+    let svg_data = match serde_json::from_str::<DrawingFile>(&content) {
+        Ok(drawing) => drawing
+            .extra
+            .get("previewSvg")
+            .and_then(|preview| preview.as_str())
+            .map(|preview| preview.to_string())
+            .ok_or_else(|| format!("Drawing has no preview to render: {}", path.display()))?,
+        Err(_) => content,
+    };
+    
+    let tree = usvg::Tree::from_str(&svg_data, opt)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    render_tree_fit(&tree, size, size, None)?
+        .encode_png()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))
+}
+
+/// Render the canvas as ASCII art, one character per pixel of a `cols`×`rows` render
+#[tauri::command]
+async fn export_to_ascii(
+    fonts: tauri::State<'_, FontStore>,
+    svg_data: String,
+    cols: u32,
+    rows: u32,
+) -> Result<String, String> {
+    const RAMP: &[u8] = b" .:-=+*#%@";
+    
+    if cols == 0 || rows == 0 || cols > 1000 || rows > 1000 {
+        return Err(format!("ASCII size must be between 1 and 1000, got {}x{}", cols, rows));
+    }
+    
+    let tree = usvg::Tree::from_str(&svg_data, &fonts.svg_options()?)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    let pixmap = render_tree_fit(&tree, cols, rows, Some(tiny_skia::Color::WHITE))?;
+    
+    let mut ascii = String::with_capacity(((cols + 1) * rows) as usize);
+    for row in pixmap.data().chunks_exact(cols as usize * 4) {
+        for pixel in row.chunks_exact(4) {
+            let luminance = (0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32) / 255.0;
+            let index = ((1.0 - luminance) * (RAMP.len() - 1) as f32).round() as usize;
+            ascii.push(RAMP[index.min(RAMP.len() - 1)] as char);
+        }
+        ascii.push('\n');
+    }
+    
+    Ok(ascii)
+}
+
+/// Escape text for use in XML content or attribute values
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Round down to a "nice" scale value: 1, 2 or 5 × 10^n
+fn nice_scale_length(value: f64) -> f64 {
+    let magnitude = 10f64.powf(value.log10().floor());
+    let fraction = value / magnitude;
+    let nice = if fraction >= 5.0 {
+        5.0
+    } else if fraction >= 2.0 {
+        2.0
+    } else {
+        1.0
+    };
+    nice * magnitude
+}
+
+/// Render the drawing with a scale bar in the bottom-left corner, sized to a nice
+/// round length of roughly a fifth of the image width
+#[tauri::command]
+async fn export_with_scale_bar(
+    fonts: tauri::State<'_, FontStore>,
+    svg_data: String,
+    width: u32,
+    height: u32,
+    units_per_pixel: f64,
+    unit_label: String,
+) -> Result<Vec<u8>, String> {
+    render_with_scale_bar(&svg_data, &fonts.svg_options()?, width, height, units_per_pixel, &unit_label)?
+        .encode_png()
+        .map_err(|e| format!("Failed to encode PNG: {}", e))
+}
+
+/// The rendering behind `export_with_scale_bar`
+fn render_with_scale_bar(
+    svg_data: &str,
+    opt: &usvg::Options,
+    width: u32,
+    height: u32,
+    units_per_pixel: f64,
+    unit_label: &str,
+) -> Result<tiny_skia::Pixmap, String> {
+    if !(units_per_pixel.is_finite() && units_per_pixel > 0.0) {
+        return Err(format!("Units per pixel must be positive, got {}", units_per_pixel));
+    }
+    
+    let tree = usvg::Tree::from_str(svg_data, opt)
+        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
+    let mut pixmap = render_tree(&tree, width, height)?;
+    let (width, height) = (pixmap.width() as f64, pixmap.height() as f64);
+    
+    let length_units = nice_scale_length(width / 5.0 * units_per_pixel);
+    let length_px = length_units / units_per_pixel;
+    let (x, y, segments) = (16.0, height - 16.0, 5);
+    
+    let ticks: String = (0..=segments)
+        .map(|i| {
+            let tick_x = x + length_px * i as f64 / segments as f64;
+            let tick_height = if i == 0 || i == segments { 8.0 } else { 4.0 };
+            format!(
+                r#"<line x1="{tx}" y1="{y}" x2="{tx}" y2="{top}" stroke="black" stroke-width="1.5"/>"#,
+                tx = tick_x,
+                y = y,
+                top = y - tick_height
+            )
+        })
+        .collect();
+    let overlay = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{w}" height="{h}">
+<rect x="{bx}" y="{by}" width="{bw}" height="34" rx="3" fill="white" fill-opacity="0.85"/>
+<line x1="{x}" y1="{y}" x2="{x2}" y2="{y}" stroke="black" stroke-width="2"/>
+{ticks}
+<text x="{x}" y="{ty}" font-family="sans-serif" font-size="12" fill="black">{label}</text>
+</svg>"#,
+        w = width,
+        h = height,
+        bx = x - 8.0,
+        by = y - 26.0,
+        bw = length_px + 16.0,
+        x = x,
+        y = y,
+        x2 = x + length_px,
+        ticks = ticks,
+        ty = y - 12.0,
+        // Round away float noise such as 0.30000000000000004
+        label = xml_escape(&format!("{} {}", (length_units * 1e6).round() / 1e6, unit_label.trim())),
+    );
+    let overlay_tree = usvg::Tree::from_str(&overlay, opt)
+        .map_err(|e| format!("Failed to build scale bar: {}", e))?;
+    resvg::render(&overlay_tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    
+    Ok(pixmap)
 }
 
-/// Get the recent files JSON path
-fn get_recent_files_path(app: &tauri::AppHandle) -> Result<PathBuf, String> {
-    let mut path = get_app_data_dir(app)?;
-    // Ensure directory exists
-    fs::create_dir_all(&path).map_err(|e| format!("Failed to create app data directory: {}", e))?;
-    path.push("recent_files.json");
-    Ok(path)
+/// How `save_png` renders and writes
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct PngSaveOptions {
+    pub width: u32,
+    pub height: u32,
+    /// Create missing parent directories; defaults to on
+    #[serde(default)]
+    pub create_parents: Option<bool>,
 }
 
-/// Save a drawing file to disk
+/// Save PNG to file
 #[tauri::command]
-async fn save_file(path: String, content: String) -> Result<(), String> {
-    fs::write(&path, &content).map_err(|e| format!("Failed to save file: {}", e))?;
-    Ok(())
+async fn save_png(
+    app: tauri::AppHandle,
+    fonts: tauri::State<'_, FontStore>,
+    trees: tauri::State<'_, TreeCache>,
+    path: String,
+    svg_data: String,
+    options: PngSaveOptions,
+) -> Result<(), String> {
+    let PngSaveOptions { width, height, create_parents } = options;
+    let png_data = export_to_png(fonts, trees, svg_data, width, height, None, None).await?;
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        ensure_parent_dir(&app, &path, create_parents.unwrap_or(true))?;
+        fs::write(&path, &png_data).map_err(|e| format!("Failed to save PNG: {}", e))
+    })
+    .await
 }
 
-/// Read a drawing file from disk
+/// Save SVG to file, embedding the drawing's metadata when given so `import_svg`
+/// can recover it
 #[tauri::command]
-async fn read_file(path: String) -> Result<String, String> {
-    fs::read_to_string(&path).map_err(|e| format!("Failed to read file: {}", e))
+async fn save_svg(
+    app: tauri::AppHandle,
+    path: String,
+    svg_data: String,
+    drawing: Option<DrawingFile>,
+    create_parents: Option<bool>,
+) -> Result<(), String> {
+    let svg_data = match drawing {
+        Some(drawing) => embed_drawing_metadata(&svg_data, &drawing)?,
+        None => svg_data,
+    };
+    run_blocking(app, move |app| {
+        check_path_scope(&app, &path)?;
+        ensure_parent_dir(&app, &path, create_parents.unwrap_or(true))?;
+        fs::write(&path, &svg_data).map_err(|e| format!("Failed to save SVG: {}", e))
+    })
+    .await
 }
 
-/// Get the list of recent files
-#[tauri::command]
-async fn get_recent_files(app: tauri::AppHandle) -> Result<Vec<RecentFile>, String> {
-    let path = get_recent_files_path(&app)?;
-    
-    if !path.exists() {
-        return Ok(Vec::new());
+/// Marks the `<metadata>` comment carrying a drawing's fields
+const SVG_METADATA_PREFIX: &str = "jamal:";
+
+/// Put everything of a drawing but its content into a comment in `<metadata>`
+/// right after the root start tag
+fn embed_drawing_metadata(svg: &str, drawing: &DrawingFile) -> Result<String, String> {
+    let mut header = serde_json::to_value(drawing)
+        .map_err(|e| format!("Failed to serialize drawing: {}", e))?;
+    if let Some(object) = header.as_object_mut() {
+        for key in ["store", "history", "checksum", "assets"] {
+            object.remove(key);
+        }
     }
+    // "--" can't appear inside a comment; it can only occur in JSON strings, where
+    // an escaped hyphen means the same
+    let json = serde_json::to_string(&header)
+        .map_err(|e| format!("Failed to serialize drawing: {}", e))?
+        .replace("--", "-\\u002d");
     
-    let content = fs::read_to_string(&path)
-        .map_err(|e| format!("Failed to read recent files: {}", e))?;
-    
-    let files: Vec<RecentFile> = serde_json::from_str(&content)
-        .map_err(|e| format!("Failed to parse recent files: {}", e))?;
-    
-    Ok(files)
+    let start = svg.find("<svg").ok_or("Not an SVG document")?;
+    let insert_at = start + tag_length(&svg[start..]);
+    let mut out = String::with_capacity(svg.len() + json.len() + 48);
+    out.push_str(&svg[..insert_at]);
+    out.push_str(&format!("<metadata><!--{}{}--></metadata>", SVG_METADATA_PREFIX, json));
+    out.push_str(&svg[insert_at..]);
+    Ok(out)
 }
 
-/// Add a file to the recent files list
+/// Import an SVG as a new drawing. Metadata embedded by `save_svg` restores the
+/// original name, version and timestamps; otherwise they're derived from the file.
+/// The returned drawing's store is empty for the frontend to fill from `svg`.
 #[tauri::command]
-async fn add_recent_file(app: tauri::AppHandle, path: String, name: String) -> Result<(), String> {
-    let recent_path = get_recent_files_path(&app)?;
-    
-    let mut files: Vec<RecentFile> = if recent_path.exists() {
-        let content = fs::read_to_string(&recent_path)
-            .map_err(|e| format!("Failed to read recent files: {}", e))?;
-        serde_json::from_str(&content).unwrap_or_else(|_| Vec::new())
-    } else {
-        Vec::new()
+async fn import_svg(app: tauri::AppHandle, path: String) -> Result<SvgImport, String> {
+    run_blocking(app, move |app| {
+        let svg = fs::read_to_string(check_path_scope(&app, &path)?).map_err(|e| format!("Failed to read file: {}", e))?;
+        let doc = roxmltree::Document::parse(&svg).map_err(|e| format!("Failed to parse SVG: {}", e))?;
+        
+        let embedded = doc
+            .root_element()
+            .children()
+            .filter(|node| node.tag_name().name() == "metadata")
+            .flat_map(|metadata| metadata.children())
+            .filter(|node| node.is_comment())
+            .filter_map(|comment| comment.text()?.trim().strip_prefix(SVG_METADATA_PREFIX).map(str::to_string))
+            .find_map(|json| serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&json).ok());
+        
+        let now = timestamp_millis();
+        let has_metadata = embedded.is_some();
+        let mut fields = embedded.unwrap_or_default();
+        fields.entry("version").or_insert(serde_json::json!(CURRENT_DRAWING_VERSION));
+        fields.entry("name").or_insert_with(|| {
+            serde_json::json!(Path::new(&path).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default())
+        });
+        fields.entry("createdAt").or_insert(serde_json::json!(now));
+        fields.entry("updatedAt").or_insert(serde_json::json!(now));
+        fields.insert("store".to_string(), serde_json::json!({}));
+        
+        let drawing: DrawingFile = serde_json::from_value(serde_json::Value::Object(fields))
+            .map_err(|e| format!("Failed to read SVG metadata: {}", e))?;
+        
+        Ok(SvgImport { svg, drawing, has_metadata })
+    })
+    .await
+}
+
+/// Describe a font face, preferring the registered file path for custom fonts
+fn font_info(face: &usvg::fontdb::FaceInfo, custom: &[CustomFont]) -> Option<FontInfo> {
+    let path = match &face.source {
+        usvg::fontdb::Source::File(path) => path.to_string_lossy().to_string(),
+        usvg::fontdb::Source::SharedFile(path, _) => path.to_string_lossy().to_string(),
+        usvg::fontdb::Source::Binary(_) => custom
+            .iter()
+            .find(|font| font.ids.contains(&face.id))
+            .map(|font| font.path.clone())
+            .unwrap_or_default(),
     };
-    
-    // Remove if already exists
-    files.retain(|f| f.path != path);
-    
-    // Add to front
-    files.insert(0, RecentFile {
+    let style = match face.style {
+        usvg::fontdb::Style::Normal => "normal",
+        usvg::fontdb::Style::Italic => "italic",
+        usvg::fontdb::Style::Oblique => "oblique",
+    };
+    Some(FontInfo {
+        family: face.families.first()?.0.clone(),
+        style: style.to_string(),
+        weight: face.weight.0 as u32,
         path,
-        name,
-        last_opened: chrono_timestamp(),
-    });
-    
-    // Keep only last 20
-    files.truncate(20);
-    
-    // Save
-    let content = serde_json::to_string_pretty(&files)
-        .map_err(|e| format!("Failed to serialize recent files: {}", e))?;
-    fs::write(&recent_path, content)
-        .map_err(|e| format!("Failed to save recent files: {}", e))?;
-    
-    Ok(())
+    })
 }
 
-/// Remove a file from recent files list
+/// List the fonts usvg can resolve for SVG text elements
 #[tauri::command]
-async fn remove_recent_file(app: tauri::AppHandle, path: String) -> Result<(), String> {
-    let recent_path = get_recent_files_path(&app)?;
-    
-    if !recent_path.exists() {
-        return Ok(());
+async fn list_available_fonts(fonts: tauri::State<'_, FontStore>) -> Result<Vec<FontInfo>, String> {
+    let mut state = fonts.lock()?;
+    if let Some(listing) = state.listing.as_ref() {
+        return Ok(listing.clone());
     }
-    
-    let content = fs::read_to_string(&recent_path)
-        .map_err(|e| format!("Failed to read recent files: {}", e))?;
-    
-    let mut files: Vec<RecentFile> = serde_json::from_str(&content)
-        .unwrap_or_else(|_| Vec::new());
-    
-    files.retain(|f| f.path != path);
-    
-    let content = serde_json::to_string_pretty(&files)
-        .map_err(|e| format!("Failed to serialize recent files: {}", e))?;
-    fs::write(&recent_path, content)
-        .map_err(|e| format!("Failed to save recent files: {}", e))?;
-    
-    Ok(())
+
+    let db = state.database().clone();
+    let mut listing: Vec<FontInfo> = db
+        .faces()
+        .filter_map(|face| font_info(face, &state.custom))
+        .collect();
+
+    listing.sort_by(|a, b| {
+        a.family
+            .to_lowercase()
+            .cmp(&b.family.to_lowercase())
+            .then(a.weight.cmp(&b.weight))
+            .then(a.style.cmp(&b.style))
+    });
+
+    state.listing = Some(listing.clone());
+    Ok(listing)
 }
 
-/// Clear all recent files
+/// Register a font file for SVG rendering and return its family name
 #[tauri::command]
-async fn clear_recent_files(app: tauri::AppHandle) -> Result<(), String> {
-    let recent_path = get_recent_files_path(&app)?;
-    
-    if recent_path.exists() {
-        fs::remove_file(&recent_path)
-            .map_err(|e| format!("Failed to clear recent files: {}", e))?;
+async fn register_font(
+    app: tauri::AppHandle,
+    fonts: tauri::State<'_, FontStore>,
+    trees: tauri::State<'_, TreeCache>,
+    font_path: String,
+) -> Result<String, String> {
+    let scoped_path = font_path.clone();
+    let data = run_blocking(app, move |app| {
+        fs::read(check_path_scope(&app, &scoped_path)?).map_err(|e| format!("Failed to read font: {}", e))
+    })
+    .await?;
+
+    let mut state = fonts.lock()?;
+    if let Some(existing) = state.custom.iter().find(|font| font.path == font_path) {
+        return Ok(existing.family.clone());
     }
-    
-    Ok(())
+
+    let db = Arc::make_mut(state.database());
+    let ids: Vec<usvg::fontdb::ID> = db
+        .load_font_source(usvg::fontdb::Source::Binary(Arc::new(data)))
+        .iter()
+        .copied()
+        .collect();
+
+    let family = ids
+        .first()
+        .and_then(|id| db.face(*id))
+        .and_then(|face| face.families.first())
+        .map(|(family, _)| family.clone())
+        .ok_or_else(|| format!("No usable font faces found in {}", font_path))?;
+
+    state.custom.push(CustomFont {
+        family: family.clone(),
+        path: font_path,
+        ids,
+    });
+    state.listing = None;
+    trees.clear();
+
+    Ok(family)
 }
 
-/// Check if a file exists
+/// List the fonts registered through `register_font`
 #[tauri::command]
-async fn file_exists(path: String) -> bool {
-    PathBuf::from(path).exists()
+async fn list_registered_custom_fonts(fonts: tauri::State<'_, FontStore>) -> Result<Vec<FontInfo>, String> {
+    let mut state = fonts.lock()?;
+    let db = state.database().clone();
+
+    Ok(state
+        .custom
+        .iter()
+        .flat_map(|font| font.ids.iter())
+        .filter_map(|id| db.face(*id))
+        .filter_map(|face| font_info(face, &state.custom))
+        .collect())
 }
 
-/// Get a simple timestamp (seconds since epoch)
-fn chrono_timestamp() -> i64 {
-    std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .map(|d| d.as_secs() as i64)
-        .unwrap_or(0)
+/// Remove a font previously registered through `register_font`
+#[tauri::command]
+async fn unregister_font(
+    fonts: tauri::State<'_, FontStore>,
+    trees: tauri::State<'_, TreeCache>,
+    font_path: String,
+) -> Result<(), String> {
+    let mut state = fonts.lock()?;
+    let index = state
+        .custom
+        .iter()
+        .position(|font| font.path == font_path)
+        .ok_or_else(|| format!("Font is not registered: {}", font_path))?;
+
+    let font = state.custom.remove(index);
+    let db = Arc::make_mut(state.database());
+    for id in font.ids {
+        db.remove_face(id);
+    }
+    state.listing = None;
+    trees.clear();
+
+    Ok(())
 }
 
-/// Export canvas as PNG image bytes
+/// Measure text with the same font resolution and shaping as SVG exports, so on-canvas
+/// layout matches the rendered result. Falls back to a sans-serif face when the family
+/// isn't installed and flags it.
 #[tauri::command]
-async fn export_to_png(svg_data: String, width: u32, height: u32) -> Result<Vec<u8>, String> {
-    // For PNG export, we'll use resvg to render SVG to PNG
-    let opt = usvg::Options::default();
-    let tree = usvg::Tree::from_str(&svg_data, &opt)
-        .map_err(|e| format!("Failed to parse SVG: {}", e))?;
-    
-    let pixmap_size = tree.size().to_int_size();
-    let mut pixmap = tiny_skia::Pixmap::new(
-        if width > 0 { width } else { pixmap_size.width() },
-        if height > 0 { height } else { pixmap_size.height() }
-    ).ok_or("Failed to create pixmap")?;
-    
-    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
-    
-    let png_data = pixmap.encode_png()
-        .map_err(|e| format!("Failed to encode PNG: {}", e))?;
-    
-    Ok(png_data)
+async fn measure_text(
+    fonts: tauri::State<'_, FontStore>,
+    text: String,
+    font_family: String,
+    font_size: f32,
+) -> Result<TextMetrics, String> {
+    let db = fonts.lock()?.database().clone();
+    text_metrics(&db, &text, &font_family, font_size)
 }
 
-/// Save PNG to file
+/// The shaping behind `measure_text`
+fn text_metrics(db: &usvg::fontdb::Database, text: &str, font_family: &str, font_size: f32) -> Result<TextMetrics, String> {
+    use usvg::fontdb::{Family, Query};
+
+    if !font_size.is_finite() || font_size <= 0.0 {
+        return Err(format!("Font size must be positive, got {}", font_size));
+    }
+
+    let query = |families: &[Family]| db.query(&Query { families, ..Query::default() });
+    let (id, fallback) = match query(&[Family::Name(font_family)]) {
+        Some(id) => (id, false),
+        None => (
+            query(&[Family::SansSerif])
+                .or_else(|| db.faces().next().map(|face| face.id))
+                .ok_or("No fonts are available")?,
+            true,
+        ),
+    };
+    let resolved_family = db
+        .face(id)
+        .and_then(|face| face.families.first())
+        .map(|(family, _)| family.clone())
+        .unwrap_or_default();
+
+    db.with_face_data(id, |data, index| {
+        let face = rustybuzz::Face::from_slice(data, index)
+            .ok_or_else(|| format!("Failed to load font {}", resolved_family))?;
+        let scale = font_size / face.units_per_em() as f32;
+
+        let mut width: f32 = 0.0;
+        let mut line_count = 0;
+        for line in text.split('\n') {
+            let mut buffer = rustybuzz::UnicodeBuffer::new();
+            buffer.push_str(line.trim_end_matches('\r'));
+            let shaped = rustybuzz::shape(&face, &[], buffer);
+            let advance: i32 = shaped.glyph_positions().iter().map(|position| position.x_advance).sum();
+            width = width.max(advance as f32 * scale);
+            line_count += 1;
+        }
+
+        let (ascender, descender) = (face.ascender() as f32, face.descender() as f32);
+        Ok(TextMetrics {
+            width,
+            ascent: ascender * scale,
+            descent: -descender * scale,
+            line_height: (ascender - descender + face.line_gap() as f32) * scale,
+            line_count,
+            resolved_family: resolved_family.clone(),
+            fallback,
+        })
+    })
+    .ok_or_else(|| format!("Failed to read font {}", resolved_family))?
+}
+
+/// Drop the parsed SVGs kept between exports
 #[tauri::command]
-async fn save_png(path: String, svg_data: String, width: u32, height: u32) -> Result<(), String> {
-    let png_data = export_to_png(svg_data, width, height).await?;
-    fs::write(&path, &png_data).map_err(|e| format!("Failed to save PNG: {}", e))?;
+async fn clear_export_cache(trees: tauri::State<'_, TreeCache>) -> Result<(), String> {
+    trees.clear();
     Ok(())
 }
 
-/// Save SVG to file  
+/// Wait for every save submitted so far to finish, e.g. before shutting down
 #[tauri::command]
-async fn save_svg(path: String, svg_data: String) -> Result<(), String> {
-    fs::write(&path, &svg_data).map_err(|e| format!("Failed to save SVG: {}", e))?;
+async fn flush_pending_writes(queue: tauri::State<'_, WriteQueue>) -> Result<(), String> {
+    let locks: Vec<Arc<tokio::sync::Mutex<()>>> = queue.writes()?.values().map(|entry| entry.lock.clone()).collect();
+    // The locks are fair, so getting one means every write queued before us is done
+    for lock in locks {
+        drop(lock.lock().await);
+    }
     Ok(())
 }
 
@@ -197,18 +9392,1436 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_deep_link::init())
+        .plugin(jamal_files_plugin())
+        .manage(FontStore::default())
+        .manage(TreeCache::default())
+        .manage(HeldLocks::default())
+        .manage(Logging::default())
+        .on_window_event(|window, event| {
+            // Files dropped onto the window come from the OS, so they count as granted
+            if let tauri::WindowEvent::DragDrop(tauri::DragDropEvent::Drop { paths, .. }) = event {
+                for path in paths {
+                    let _ = grant_path(window.app_handle(), &path.to_string_lossy(), false);
+                }
+            }
+        })
         .invoke_handler(tauri::generate_handler![
-            save_file,
-            read_file,
-            get_recent_files,
-            add_recent_file,
-            remove_recent_file,
-            clear_recent_files,
-            file_exists,
             export_to_png,
             save_png,
-            save_svg
+            save_svg,
+            list_available_fonts,
+            handle_dropped_files,
+            register_font,
+            list_registered_custom_fonts,
+            unregister_font,
+            get_preferences,
+            set_preferences,
+            set_post_save_hook,
+            batch_rename,
+            read_file_with_progress,
+            record_session,
+            get_drawing_info,
+            save_view_state,
+            get_view_state,
+            validate_svg_roundtrip,
+            export_svg_minified,
+            save_svg_minified,
+            export_social,
+            export_to_ascii,
+            is_jamal_drawing,
+            get_os_info,
+            prune_svg_defs,
+            export_to_react_component,
+            save_file_set,
+            save_drawing_with_history,
+            load_drawing_with_history,
+            export_with_scale_bar,
+            convert_drawing_to_cbor,
+            convert_drawing_from_cbor,
+            read_cbor_drawing,
+            validate_drawing_file,
+            prepare_open,
+            save_file_encrypted,
+            read_file_encrypted,
+            change_file_password,
+            acquire_file_lock,
+            release_file_lock,
+            get_file_lock,
+            export_svg_optimized,
+            build_svg_sprite,
+            get_file_info,
+            clear_readonly_attribute,
+            subscribe_events,
+            export_memory_estimate,
+            import_from_url,
+            request_path_access,
+            pick_drawing_path,
+            pick_export_path,
+            recover_truncated_drawing,
+            import_svg,
+            generate_os_thumbnail,
+            drawings_are_equal,
+            append_history,
+            read_history,
+            init_logging,
+            get_log_path,
+            sanitize_filename,
+            export_timelapse,
+            suggest_filename,
+            export_alpha_mask,
+            next_available_name,
+            next_available_copy_path,
+            get_drawing_name,
+            cleanup_app_data,
+            export_to_eps,
+            extract_drawing_from_png,
+            read_file_bytes,
+            add_asset,
+            get_asset,
+            remap_svg_palette,
+            export_to_paper,
+            export_to_video,
+            batch_export,
+            measure_text,
+            clear_export_cache,
+            validate_svg,
+            outline_svg_text,
+            export_to_odg,
+            export_compatibility,
+            batch_svg_to_png,
+            extract_palette,
+            export_dzi,
+            export_to_png_for_print,
+            app_data_usage,
+            export_responsive_svg,
+            export_drawing_diff_svg,
+            get_or_request_preview,
+            store_preview,
+            list_gallery,
+            create_support_bundle
+        ])
+        .build(tauri::generate_context!())
+        .expect("error while running tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Release our advisory locks on clean shutdown
+                if let Ok(held) = app.state::<HeldLocks>().0.lock() {
+                    for lock_path in held.iter() {
+                        let _ = fs::remove_file(lock_path);
+                    }
+                }
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// A fresh temp directory, canonicalized so expected paths compare equal to
+    /// resolved ones (on macOS the temp dir sits behind a symlink)
+    fn temp_dir() -> (tempfile::TempDir, PathBuf) {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let root = fs::canonicalize(dir.path()).expect("canonicalize temp dir");
+        (dir, root)
+    }
+    
+    #[test]
+    fn normalize_scope_path_rejects_relative_and_nul_paths() {
+        assert!(normalize_scope_path("drawing.jamal").is_err());
+        assert!(normalize_scope_path("../drawing.jamal").is_err());
+        assert!(normalize_scope_path("").is_err());
+        let (_dir, root) = temp_dir();
+        assert!(normalize_scope_path(&format!("{}\0.jamal", root.join("a").display())).is_err());
+    }
+    
+    #[test]
+    fn normalize_scope_path_removes_dot_segments() {
+        let (dir, root) = temp_dir();
+        fs::create_dir(root.join("a")).unwrap();
+        let sep = std::path::MAIN_SEPARATOR;
+        // Spelled from the uncanonicalized dir; `\\?\` paths take `.` and `..` literally
+        let path = format!("{}{sep}a{sep}.{sep}b{sep}..{sep}c.jamal", dir.path().display());
+        assert_eq!(normalize_scope_path(&path).unwrap(), root.join("a").join("c.jamal"));
+    }
+    
+    #[test]
+    fn normalize_scope_path_keeps_missing_components() {
+        let (_dir, root) = temp_dir();
+        let path = root.join("new").join("folder").join("drawing.jamal");
+        assert_eq!(normalize_scope_path(&path.to_string_lossy()).unwrap(), path);
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn normalize_scope_path_stops_parent_segments_at_the_root_on_unix() {
+        assert_eq!(
+            normalize_scope_path("/../../jamal-missing-dir/../jamal-missing.jamal").unwrap(),
+            PathBuf::from("/jamal-missing.jamal")
+        );
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn normalize_scope_path_resolves_symlinks_escaping_a_directory_on_unix() {
+        let (_dir, root) = temp_dir();
+        let allowed = root.join("allowed");
+        let outside = root.join("outside");
+        fs::create_dir_all(outside.join("inner")).unwrap();
+        fs::create_dir(&allowed).unwrap();
+        std::os::unix::fs::symlink(outside.join("inner"), allowed.join("link")).unwrap();
+        
+        let through_link = normalize_scope_path(&format!("{}/link/secret.jamal", allowed.display())).unwrap();
+        assert_eq!(through_link, outside.join("inner").join("secret.jamal"));
+        assert!(!through_link.starts_with(&allowed));
+        
+        // `..` after a link climbs out of the link's target, as the OS resolves it
+        let parent_of_link = normalize_scope_path(&format!("{}/link/../secret.jamal", allowed.display())).unwrap();
+        assert_eq!(parent_of_link, outside.join("secret.jamal"));
+        assert!(!parent_of_link.starts_with(&allowed));
+    }
+    
+    #[cfg(windows)]
+    #[test]
+    fn normalize_scope_path_rejects_device_paths_on_windows() {
+        assert!(normalize_scope_path(r"\\.\PhysicalDrive0").is_err());
+        assert!(normalize_scope_path(r"//./PhysicalDrive0").is_err());
+        assert!(normalize_scope_path(r"\\?\GLOBALROOT\Device\HarddiskVolume1\secret.jamal").is_err());
+        assert!(normalize_scope_path(r"\\.\pipe\jamal").is_err());
+    }
+    
+    #[cfg(windows)]
+    #[test]
+    fn normalize_scope_path_rejects_drive_and_root_relative_paths_on_windows() {
+        assert!(normalize_scope_path(r"C:drawing.jamal").is_err());
+        assert!(normalize_scope_path(r"\drawing.jamal").is_err());
+    }
+    
+    #[cfg(windows)]
+    #[test]
+    fn normalize_scope_path_accepts_verbatim_disk_and_unc_paths_on_windows() {
+        assert!(is_verbatim_disk_or_unc(r"C:\Users\drawing.jamal"));
+        assert!(is_verbatim_disk_or_unc(r"UNC\server\share\drawing.jamal"));
+        assert!(!is_verbatim_disk_or_unc(r"GLOBALROOT\Device\HarddiskVolume1"));
+        
+        // The canonical temp dir is itself a `\\?\C:\...` path
+        let (_dir, root) = temp_dir();
+        let path = root.join("drawing.jamal");
+        assert_eq!(normalize_scope_path(&path.to_string_lossy()).unwrap(), path);
+    }
+    
+    #[cfg(windows)]
+    #[test]
+    fn normalize_scope_path_unifies_separators_on_windows() {
+        let (dir, root) = temp_dir();
+        fs::create_dir(root.join("a")).unwrap();
+        let path = format!("{}/a/./b/../c.jamal", dir.path().display());
+        assert_eq!(normalize_scope_path(&path).unwrap(), root.join("a").join("c.jamal"));
+    }
+    
+    #[test]
+    fn protected_app_data_files_cover_stores_and_their_temp_files() {
+        let (_dir, root) = temp_dir();
+        assert!(is_protected_app_data_file(&root, &root.join("preferences.json")));
+        assert!(is_protected_app_data_file(&root, &root.join("Path_Scope.JSON")));
+        assert!(is_protected_app_data_file(&root, &root.join(".preferences.json.123.4.tmp")));
+        assert!(!is_protected_app_data_file(&root, &root.join("tags.json")));
+        assert!(!is_protected_app_data_file(&root, &root.join("drawings").join("preferences.json")));
+    }
+    
+    #[test]
+    fn encrypted_envelope_round_trips_and_tells_wrong_passwords_apart() {
+        let envelope = encrypt_envelope(b"{\"type\":\"jamal\"}", "secret").unwrap();
+        assert_eq!(decrypt_envelope(&envelope, "secret").unwrap(), b"{\"type\":\"jamal\"}");
+        assert!(decrypt_envelope(&envelope, "guess").unwrap_err().starts_with(ERR_WRONG_PASSWORD));
+    }
+    
+    #[test]
+    fn encrypted_envelope_rejects_inflated_key_derivation_costs() {
+        let envelope = encrypt_envelope(b"{}", "secret").unwrap();
+        for offset in [9, 13, 17] {
+            let mut crafted = envelope.clone();
+            crafted[offset..offset + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+            assert!(decrypt_envelope(&crafted, "secret").unwrap_err().starts_with(ERR_CORRUPT_FILE));
+        }
+    }
+    
+    #[test]
+    fn write_file_set_replaces_every_file_and_leaves_no_temp_files() {
+        let (_dir, root) = temp_dir();
+        let drawing = root.join("drawing.jamal").to_string_lossy().to_string();
+        let sidecar = root.join("drawing.jamal.meta").to_string_lossy().to_string();
+        fs::write(&drawing, "old").unwrap();
+        
+        write_file_set(&[
+            FileWrite { path: drawing.clone(), content: b"new".to_vec() },
+            FileWrite { path: sidecar.clone(), content: b"meta".to_vec() },
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .unwrap();
+        
+        assert_eq!(fs::read_to_string(&drawing).unwrap(), "new");
+        assert_eq!(fs::read_to_string(&sidecar).unwrap(), "meta");
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 2);
+    }
+    
+    #[test]
+    fn write_file_set_rejects_duplicate_targets() {
+        let (_dir, root) = temp_dir();
+        let drawing = root.join("drawing.jamal").to_string_lossy().to_string();
+        let files = [
+            FileWrite { path: drawing.clone(), content: b"a".to_vec() },
+            FileWrite { path: drawing.clone(), content: b"b".to_vec() },
+        ];
+        
+        assert!(write_file_set(&files).is_err());
+        assert!(!Path::new(&drawing).exists());
+    }
+    
+    #[test]
+    fn file_sets_wait_their_turn_without_superseding_queued_saves() {
+        let (_dir, root) = temp_dir();
+        let path = root.join("drawing.jamal").to_string_lossy().to_string();
+        let queue = WriteQueue::default();
+        let save = queue.enqueue(&path).unwrap();
+        
+        // A set naming the path twice still takes a single turn
+        let turns = tauri::async_runtime::block_on(queue.wait_turns(&[&path, &path])).unwrap();
+        assert!(!queue.is_superseded(&save));
+        assert_eq!(queue.writes().unwrap()[&canonical_key(&path)].pending, 2);
+        drop(turns);
+        assert_eq!(queue.writes().unwrap()[&canonical_key(&path)].pending, 1);
+        
+        let newer = queue.enqueue(&path).unwrap();
+        assert!(queue.is_superseded(&save));
+        queue.finish(&save);
+        queue.finish(&newer);
+        assert!(queue.writes().unwrap().is_empty());
+    }
+    
+    #[test]
+    fn saves_are_not_stalled_by_a_slow_reader() {
+        let (_dir, root) = temp_dir();
+        let app = tauri::test::mock_app();
+        app.manage(AppDataDir(root.clone()));
+        app.manage(WriteQueue::default());
+        app.manage(DrawingEventBus::default());
+        let handle = app.handle().clone();
+        
+        tauri::async_runtime::block_on(async move {
+            // The reader holds its blocking thread until every save is done, and
+            // reports whether that happened before it gave up waiting
+            let (release, released) = std::sync::mpsc::channel::<()>();
+            let slow_read = tauri::async_runtime::spawn(run_blocking(handle.clone(), move |_| {
+                Ok(released.recv_timeout(std::time::Duration::from_secs(30)).is_ok())
+            }));
+            
+            let autosave = SaveOptions { autosave: true, ..Default::default() };
+            for i in 0..20 {
+                let path = root.join(format!("drawing-{}.jamal", i)).to_string_lossy().to_string();
+                save_file(handle.clone(), handle.state::<WriteQueue>(), path, "{}".to_string(), Some(autosave.clone()))
+                    .await
+                    .unwrap();
+            }
+            assert!((0..20).all(|i| root.join(format!("drawing-{}.jamal", i)).is_file()));
+            
+            release.send(()).unwrap();
+            assert!(slow_read.await.unwrap().unwrap(), "saves waited for the reader");
+        });
+    }
+    
+    #[test]
+    fn decode_drawing_bytes_bounds_decompressed_output() {
+        let bomb = gzip(&vec![b' '; 1024 * 1024]).unwrap();
+        assert!(bomb.len() < 4096);
+        let error = decode_drawing_bytes(Path::new("drawing.jamal"), bomb.clone(), Some(4096)).unwrap_err();
+        assert!(error.starts_with(ERR_FILE_TOO_LARGE));
+        assert_eq!(decode_drawing_bytes(Path::new("drawing.jamal"), bomb, None).unwrap().len(), 1024 * 1024);
+    }
+    
+    fn recent_file(path: &str, name: &str) -> RecentFile {
+        RecentFile {
+            path: path.to_string(),
+            name: name.to_string(),
+            last_opened: 0,
+            tags: Vec::new(),
+            label: None,
+            pinned: false,
+            open_count: 0,
+        }
+    }
+    
+    #[test]
+    fn renamed_files_keep_their_store_entries() {
+        let (_dir, root) = temp_dir();
+        let old_path = root.join("old.jamal").to_string_lossy().to_string();
+        let new_path = root.join("new.jamal").to_string_lossy().to_string();
+        let mut tags = BTreeMap::from([(canonical_key(&old_path), vec!["wip".to_string()])]);
+        
+        assert!(move_store_entry(&mut tags, &canonical_key(&old_path), &canonical_key(&new_path)));
+        assert_eq!(tags.get(&canonical_key(&new_path)), Some(&vec!["wip".to_string()]));
+        assert!(!tags.contains_key(&canonical_key(&old_path)));
+        assert!(!move_store_entry(&mut tags, &canonical_key(&old_path), &canonical_key(&new_path)));
+    }
+    
+    #[test]
+    fn recent_entries_follow_a_rename_whatever_their_spelling() {
+        let (dir, root) = temp_dir();
+        fs::create_dir(root.join("a")).unwrap();
+        let sep = std::path::MAIN_SEPARATOR;
+        let spelled = format!("{}{sep}a{sep}..{sep}old.jamal", dir.path().display());
+        let mut recent = vec![recent_file(&spelled, "old"), recent_file(&root.join("other.jamal").to_string_lossy(), "other")];
+        let new_path = root.join("new.jamal").to_string_lossy().to_string();
+        
+        let old_key = canonical_key(&root.join("old.jamal").to_string_lossy());
+        assert!(relocate_recent_entries(&mut recent, &old_key, &new_path, "new"));
+        assert_eq!(recent[0].path, new_path);
+        assert_eq!(recent[0].name, "new");
+        assert_eq!(recent[1].name, "other");
+    }
+    
+    #[test]
+    fn shared_recent_files_are_relative_copies_merged_back_as_absolute_paths() {
+        let (_dir, root) = temp_dir();
+        let base = root.join("checkout");
+        let inside = base.join("docs").join("plan.jamal").to_string_lossy().to_string();
+        let outside = root.join("notes.jamal").to_string_lossy().to_string();
+        let recent = vec![recent_file(&inside, "plan"), recent_file(&outside, "notes")];
+        
+        let shared = relativized_recent_files(&recent, &base);
+        assert_eq!(shared.len(), 2);
+        assert_eq!(shared[0].path, "docs/plan.jamal");
+        assert_eq!(shared[1].path, "../notes.jamal");
+        assert_eq!(recent[0].path, inside);
+        
+        let other = root.join("elsewhere");
+        let mut files = vec![recent_file(&other.join("docs").join("plan.jamal").to_string_lossy(), "plan")];
+        let added = merge_shared_recent_files(&mut files, shared, |relative| {
+            // Only paths inside the checkout are in scope
+            (!relative.starts_with("..")).then(|| other.join(relative))
+        });
+        assert_eq!(added, 0);
+        assert_eq!(files.len(), 1);
+        
+        let mut files = Vec::new();
+        let shared = relativized_recent_files(&recent, &base);
+        assert_eq!(merge_shared_recent_files(&mut files, shared, |relative| Some(other.join(relative))), 2);
+        assert!(files.iter().all(|f| Path::new(&f.path).is_absolute()));
+        assert_eq!(canonical_key(&files[0].path), canonical_key(&other.join("docs/plan.jamal").to_string_lossy()));
+    }
+    
+    #[test]
+    fn renames_never_replace_an_existing_file() {
+        let (_dir, root) = temp_dir();
+        let from = root.join("plan.jamal");
+        let taken = root.join("taken.jamal");
+        fs::write(&from, "plan").unwrap();
+        fs::write(&taken, "taken").unwrap();
+        
+        assert!(rename_no_clobber(&from, &taken).unwrap_err().starts_with("A file already exists"));
+        assert_eq!(fs::read_to_string(&from).unwrap(), "plan");
+        assert_eq!(fs::read_to_string(&taken).unwrap(), "taken");
+        
+        let free = root.join("free.jamal");
+        rename_no_clobber(&from, &free).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read_to_string(&free).unwrap(), "plan");
+    }
+    
+    #[test]
+    fn copies_keep_numbers_that_are_part_of_the_name() {
+        let (_dir, root) = temp_dir();
+        let name = |stem: &str| format!("{}.jamal", stem);
+        for stem in ["Report 2024", "Plan", "Plan 2", "Sketch (2)"] {
+            fs::write(root.join(name(stem)), "{}").unwrap();
+        }
+        
+        let copy = |stem: &str| copy_path(&root.join(name(stem))).unwrap();
+        assert_eq!(copy("Report 2024"), root.join(name(&numbered_name("Report 2024", 2))));
+        assert_eq!(copy("Plan 2"), root.join(name(&numbered_name("Plan", 3))));
+        assert_eq!(copy("Sketch (2)"), root.join(name(&numbered_name("Sketch", 3))));
+        assert_eq!(copy("Plan"), root.join(name(&numbered_name("Plan", 3))));
+    }
+    
+    #[test]
+    fn batch_renames_keep_the_container_and_never_replace_files() {
+        let (_dir, root) = temp_dir();
+        let pattern = regex::Regex::new("Draft").unwrap();
+        let draft = root.join("draft.jamalz");
+        write_test_drawing(&draft, r#"{"version":1,"name":"Draft plan","store":{"a":1},"createdAt":0,"updatedAt":0}"#, DrawingContainer::Gzip);
+        
+        let (new_path, new_name) = rename_drawing(&draft.to_string_lossy(), &pattern, "Final").unwrap().unwrap();
+        assert_eq!((Path::new(&new_path), new_name.as_str()), (root.join("Final plan.jamalz").as_path(), "Final plan"));
+        assert!(!draft.exists());
+        assert_eq!(drawing_container(&fs::read(&new_path).unwrap()), DrawingContainer::Gzip);
+        assert_eq!(read_drawing_in(Path::new(&new_path)).unwrap().0.name, "Final plan");
+        
+        // A CBOR drawing whose new name is taken stays as it was
+        let other = root.join("other.jamalz");
+        write_test_drawing(&other, r#"{"version":1,"name":"Draft plan","store":{"b":2},"createdAt":0,"updatedAt":0}"#, DrawingContainer::Cbor);
+        let before = fs::read(&other).unwrap();
+        assert!(rename_drawing(&other.to_string_lossy(), &pattern, "Final").unwrap_err().starts_with("A file already exists"));
+        assert_eq!(fs::read(&other).unwrap(), before);
+        assert_eq!(read_drawing_in(Path::new(&new_path)).unwrap().0.store, serde_json::json!({ "a": 1 }));
+    }
+    
+    #[test]
+    fn list_drawings_in_surfaces_tags() {
+        let (_dir, root) = temp_dir();
+        fs::write(root.join("b.jamal"), "{}").unwrap();
+        fs::write(root.join("A.jamal"), "{}").unwrap();
+        fs::write(root.join("notes.txt"), "").unwrap();
+        let tags = BTreeMap::from([(canonical_key(&root.join("b.jamal").to_string_lossy()), vec!["client-x".to_string()])]);
+        
+        let drawings = list_drawings_in(&root, &tags).unwrap();
+        let listed: Vec<(&str, &[String])> = drawings.iter().map(|d| (d.name.as_str(), d.tags.as_slice())).collect();
+        assert_eq!(listed, vec![("A", &[][..]), ("b", &["client-x".to_string()][..])]);
+    }
+    
+    #[test]
+    fn tags_are_stored_trimmed_and_lowercased() {
+        let mut store = BTreeMap::new();
+        assert_eq!(store_tags(&mut store, "a".to_string(), vec![" wip ".to_string(), "WIP".to_string()]), vec!["wip"]);
+        assert_eq!(store_tags(&mut store, "b".to_string(), vec!["Wip".to_string(), "Client-X".to_string()]), vec!["wip", "client-x"]);
+        assert_eq!(store.get("b"), Some(&vec!["wip".to_string(), "client-x".to_string()]));
+    }
+    
+    #[test]
+    fn recent_files_filter_by_tag_case_insensitively() {
+        let mut tagged = recent_file("/drawings/a.jamal", "a");
+        tagged.tags = vec!["wip".to_string()];
+        let files = vec![tagged, recent_file("/drawings/b.jamal", "b")];
+        
+        let filtered = filter_by_tag(files, "WIP");
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].name, "a");
+    }
+    
+    #[test]
+    fn removing_the_last_tag_keeps_the_file_listed() {
+        let (_dir, root) = temp_dir();
+        let path = root.join("a.jamal").to_string_lossy().to_string();
+        let mut files = vec![recent_file(&path, "a")];
+        let mut store = BTreeMap::new();
+        store_tags(&mut store, canonical_key(&path), vec!["wip".to_string()]);
+        attach_tags(&mut files, &store);
+        assert_eq!(files[0].tags, vec!["wip"]);
+        
+        assert!(store_tags(&mut store, canonical_key(&path), Vec::new()).is_empty());
+        assert!(store.is_empty());
+        attach_tags(&mut files, &store);
+        assert_eq!(files.len(), 1);
+        assert!(files[0].tags.is_empty());
+    }
+    
+    #[test]
+    fn cleanup_removes_orphaned_snapshot_blobs_and_keeps_live_ones() {
+        let (_dir, root) = temp_dir();
+        fs::write(root.join("live.gz"), "live").unwrap();
+        fs::write(root.join("orphan.gz"), "orphan").unwrap();
+        fs::write(root.join("index.json"), "{}").unwrap();
+        let snapshot = SnapshotInfo {
+            id: "1".to_string(),
+            label: "Before".to_string(),
+            created_at: 0,
+            size: 4,
+            content_hash: "live".to_string(),
+        };
+        let index = BTreeMap::from([("/drawings/a.jamal".to_string(), vec![snapshot])]);
+        
+        let mut report = CleanupReport::default();
+        remove_orphaned_snapshot_blobs(&root, &index, &mut report);
+        assert!(root.join("live.gz").exists());
+        assert!(root.join("index.json").exists());
+        assert!(!root.join("orphan.gz").exists());
+        assert_eq!(report.snapshot_blobs_removed, 1);
+        assert_eq!(report.bytes_freed, 6);
+    }
+    
+    #[test]
+    fn cleanup_removes_orphaned_thumbnails_and_keeps_live_ones() {
+        let (_dir, root) = temp_dir();
+        fs::write(root.join("live.jamal"), "{}").unwrap();
+        fs::write(root.join("live.png"), "live").unwrap();
+        fs::write(root.join("orphan.png"), "orphan").unwrap();
+        
+        let mut report = CleanupReport::default();
+        remove_orphaned_thumbnails(&root, &mut report);
+        assert!(root.join("live.jamal").exists());
+        assert!(root.join("live.png").exists());
+        assert!(!root.join("orphan.png").exists());
+        assert_eq!(report.thumbnails_removed, 1);
+        assert_eq!(report.bytes_freed, 6);
+    }
+    
+    #[test]
+    fn cleanup_removes_old_orphaned_backups_and_backups_over_retention() {
+        let (_dir, root) = temp_dir();
+        let day = 24 * 60 * 60 * 1000;
+        let now = 100 * day;
+        let backups_root = root.join("backups");
+        let backup_dir = |name: &str, source: &Path, timestamps: &[i64]| {
+            let dir = backups_root.join(name);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("source.txt"), source.to_string_lossy().as_ref()).unwrap();
+            for timestamp in timestamps {
+                fs::write(dir.join(format!("{}.jamal", timestamp)), "data").unwrap();
+            }
+            dir
+        };
+        let live = root.join("live.jamal");
+        fs::write(&live, "{}").unwrap();
+        let deleted = root.join("deleted.jamal");
+        
+        let old_orphan = backup_dir("old", &deleted, &[now - 40 * day, now - 31 * day]);
+        let recent_orphan = backup_dir("recent", &deleted, &[now - 2 * day]);
+        let kept = backup_dir("live", &live, &[now - 3 * day, now - 2 * day, now - day]);
+        
+        let mut report = CleanupReport::default();
+        prune_backup_dirs(&backups_root, 2, 30 * day, now, &mut report);
+        assert!(!old_orphan.exists());
+        assert!(recent_orphan.join(format!("{}.jamal", now - 2 * day)).exists());
+        let remaining: Vec<i64> = list_backup_files(&kept).into_iter().map(|(_, info)| info.timestamp).collect();
+        assert_eq!(remaining, vec![now - 2 * day, now - day]);
+        assert!(kept.join("source.txt").exists());
+        assert_eq!(report.recovery_files_removed, 2);
+        assert_eq!(report.backups_removed, 1);
+        assert_eq!(report.bytes_freed, 12);
+    }
+    
+    #[test]
+    fn scrub_private_paths_keeps_only_file_names() {
+        let dirs = vec!["/home/alice smith".to_string(), "/home/al".to_string()];
+        let text = "Saved /home/alice smith/work/a.jamal (12 bytes); opened /srv/data/x.jamal, \
+                    C:\\Users\\bob\\d.jamal and \\\\nas\\share\\e.jamal from /home/alice/f.jamal; \
+                    see https://example.com/docs";
+        assert_eq!(
+            scrub_private_paths(text, &dirs),
+            "Saved a.jamal (12 bytes); opened x.jamal, d.jamal and e.jamal from f.jamal; see https://example.com/docs"
+        );
+        assert_eq!(scrub_private_paths("backups in /home/alice smith", &dirs), "backups in alice smith");
+    }
+    
+    #[test]
+    fn support_archive_entries_hold_no_absolute_paths() {
+        let (_dir, root) = temp_dir();
+        let data_dir = root.join("data");
+        let drawings = root.join("Drawings");
+        fs::create_dir_all(&data_dir).unwrap();
+        fs::create_dir_all(&drawings).unwrap();
+        let plan = drawings.join("plan.jamal");
+        fs::write(&plan, "{\"name\":\"Plan\"}").unwrap();
+        fs::write(data_dir.join(LOG_FILE_NAME), format!("INFO Saving {}\nWARN /var/tmp/x.tmp left over", plan.display())).unwrap();
+        fs::write(data_dir.join("preferences.json"), "{}").unwrap();
+        let recent = vec![recent_file(&plan.to_string_lossy(), "Plan")];
+        let dirs = vec![drawings.to_string_lossy().to_string()];
+        let entries = |include_drawings| {
+            support_bundle_entries(&data_dir, serde_json::json!({}), &recent, &Preferences::default(), &dirs, include_drawings).unwrap()
+        };
+        
+        let without = entries(false);
+        let names: Vec<&str> = without.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["system.json", "recent_files.json", "preferences.json", "logs/jamal.log"]);
+        let with = entries(true);
+        let names: Vec<&str> = with.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["system.json", "recent_files.json", "preferences.json", "logs/jamal.log", "drawings/01-plan.jamal"]);
+        assert_eq!(with[4].1, fs::read(&plan).unwrap());
+        
+        let bundle = zip_entries(&without).unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bundle)).unwrap();
+        let root_text = root.to_string_lossy().to_string();
+        for name in names.iter().take(4) {
+            let mut text = String::new();
+            std::io::Read::read_to_string(&mut archive.by_name(name).unwrap(), &mut text).unwrap();
+            assert!(!text.contains(&root_text), "{}: {}", name, text);
+        }
+        let mut log = String::new();
+        std::io::Read::read_to_string(&mut archive.by_name("logs/jamal.log").unwrap(), &mut log).unwrap();
+        assert_eq!(log, "INFO Saving plan.jamal\nWARN x.tmp left over");
+    }
+    
+    #[test]
+    fn dropped_drawings_are_openable() {
+        let (_dir, root) = temp_dir();
+        let plain = root.join("plan.jamal");
+        fs::write(&plain, r#"{"version":1,"name":"Floor plan","store":{},"createdAt":0,"updatedAt":0}"#).unwrap();
+        let (status, name, error) = classify_dropped_file(&plain.to_string_lossy(), Some(1024 * 1024));
+        assert_eq!((status, name.as_deref(), error), (DroppedFileStatus::Openable, Some("Floor plan"), None));
+        
+        // Compressed, and renamed away from `.jamal`
+        let compressed = root.join("plan-copy");
+        fs::write(&compressed, gzip(&fs::read(&plain).unwrap()).unwrap()).unwrap();
+        let (status, name, _) = classify_dropped_file(&compressed.to_string_lossy(), Some(1024 * 1024));
+        assert_eq!((status, name.as_deref()), (DroppedFileStatus::Openable, Some("Floor plan")));
+    }
+    
+    #[test]
+    fn dropped_images_are_not_drawings() {
+        let (_dir, root) = temp_dir();
+        let image = root.join("photo.png");
+        fs::write(&image, b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        let (status, name, error) = classify_dropped_file(&image.to_string_lossy(), Some(1024 * 1024));
+        assert_eq!((status, name, error), (DroppedFileStatus::NotADrawing, None, None));
+    }
+    
+    #[test]
+    fn dropped_missing_files_are_invalid() {
+        let (_dir, root) = temp_dir();
+        let (status, name, error) = classify_dropped_file(&root.join("gone.jamal").to_string_lossy(), None);
+        assert_eq!(status, DroppedFileStatus::Invalid);
+        assert!(name.is_none() && error.is_some());
+    }
+    
+    #[test]
+    fn sniff_drawing_recognizes_drawings_whatever_their_container() {
+        let (_dir, root) = temp_dir();
+        let drawing = br#"{"version": 2, "name": "Plan", "store": {"shapes": []}}"#;
+        fs::write(root.join("plain"), drawing).unwrap();
+        fs::write(root.join("compressed"), gzip(drawing).unwrap()).unwrap();
+        fs::write(root.join("encrypted"), [ENCRYPTED_MAGIC, &[1u8; 100][..]].concat()).unwrap();
+        
+        for name in ["plain", "compressed", "encrypted"] {
+            assert!(sniff_drawing(&root.join(name)).unwrap(), "{} not recognized", name);
+        }
+    }
+    
+    #[test]
+    fn sniff_drawing_rejects_other_json_and_binary_files() {
+        let (_dir, root) = temp_dir();
+        fs::write(root.join("package.json"), br#"{"name": "app", "version": "1.0.0", "scripts": {}}"#).unwrap();
+        fs::write(root.join("image.png"), b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR\xff\x00").unwrap();
+        fs::write(root.join("archive.gz"), gzip(b"\0\x01\x02 not json").unwrap()).unwrap();
+        
+        for name in ["package.json", "image.png", "archive.gz"] {
+            assert!(!sniff_drawing(&root.join(name)).unwrap(), "{} taken for a drawing", name);
+        }
+    }
+    
+    #[test]
+    fn change_log_reads_back_in_append_order_across_rotation() {
+        let (_dir, root) = temp_dir();
+        let path = root.join("plan.jamal").to_string_lossy().to_string();
+        for i in 0..6 {
+            // About 50 bytes a line, so the log rotates once, after the third
+            append_change(&path, serde_json::json!({ "step": i }), 100).unwrap();
+        }
+        assert!(change_log_paths(&path).1.exists());
+        
+        let steps: Vec<serde_json::Value> = read_changes(&path, 100).unwrap().into_iter().map(|e| e.change["step"].clone()).collect();
+        assert_eq!(steps, (0..6).map(|i| serde_json::json!(i)).collect::<Vec<_>>());
+        assert_eq!(read_changes(&path, 1).unwrap()[0].change["step"], 5);
+    }
+    
+    #[test]
+    fn change_logs_follow_a_renamed_drawing() {
+        let (_dir, root) = temp_dir();
+        let old_path = root.join("old.jamal").to_string_lossy().to_string();
+        let new_path = root.join("new.jamal").to_string_lossy().to_string();
+        append_change(&old_path, serde_json::json!("first"), CHANGE_LOG_MAX_BYTES).unwrap();
+        
+        relocate_change_logs(&old_path, &new_path).unwrap();
+        assert!(read_changes(&old_path, 100).unwrap().is_empty());
+        assert_eq!(read_changes(&new_path, 100).unwrap()[0].change, "first");
+    }
+    
+    #[test]
+    fn open_payload_reports_missing_files() {
+        let (_dir, root) = temp_dir();
+        let error = open_payload(&root.join("gone.jamal"), None, false).unwrap_err();
+        assert!(error.starts_with("File not found"), "{}", error);
+    }
+    
+    #[test]
+    fn open_payload_reads_and_hashes_the_drawing() {
+        let (_dir, root) = temp_dir();
+        let path = root.join("plan.jamal");
+        fs::write(&path, format!(r#"{{"version":{},"name":"Floor plan","store":{{}},"createdAt":0,"updatedAt":0}}"#, CURRENT_DRAWING_VERSION)).unwrap();
+        
+        let payload = open_payload(&path, Some(1024 * 1024), false).unwrap();
+        assert_eq!(payload.name, "Floor plan");
+        assert_eq!(payload.version, CURRENT_DRAWING_VERSION);
+        assert_eq!(payload.hash, sha256_hex(payload.content.as_bytes()));
+        assert!(payload.writable && payload.size_warning.is_none());
+        assert_eq!(payload.replaced_bytes, 0);
+    }
+    
+    #[test]
+    fn preferences_round_trip_through_their_store() {
+        let (_dir, root) = temp_dir();
+        let path = root.join("preferences.json");
+        let preferences = Preferences {
+            theme: "dark".to_string(),
+            default_export_format: "svg".to_string(),
+            grid_enabled: true,
+            grid_size: 32,
+            durable_saves: true,
+            ..Default::default()
+        };
+        validate_preferences(&preferences).unwrap();
+        write_json(&path, &preferences).unwrap();
+        
+        let loaded: Preferences = read_json_or_default(&path);
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), serde_json::to_value(&preferences).unwrap());
+    }
+    
+    #[test]
+    fn invalid_preferences_are_rejected() {
+        let invalid = [
+            Preferences { theme: "sepia".to_string(), ..Default::default() },
+            Preferences { default_export_format: "gif".to_string(), ..Default::default() },
+            Preferences { grid_size: 0, ..Default::default() },
+            Preferences { autosave_interval_secs: 1, ..Default::default() },
+            Preferences { max_recent_files: 0, ..Default::default() },
+        ];
+        for preferences in &invalid {
+            assert!(validate_preferences(preferences).is_err(), "{:?} accepted", preferences);
+        }
+        assert!(validate_preferences(&Preferences::default()).is_ok());
+    }
+    
+    #[test]
+    fn read_progress_increases_up_to_the_file_size() {
+        let (_dir, root) = temp_dir();
+        let path = root.join("large.jamal");
+        let size = READ_CHUNK_SIZE * 3 + READ_CHUNK_SIZE / 2;
+        fs::write(&path, vec![b' '; size]).unwrap();
+        
+        let mut events = Vec::new();
+        let content = read_bytes_with_progress(&path, None, &mut |read, total| events.push((read, total))).unwrap();
+        assert_eq!(content.len(), size);
+        assert!(events.len() >= 4, "{:?}", events);
+        assert!(events.windows(2).all(|pair| pair[0].0 <= pair[1].0), "{:?}", events);
+        assert!(events.iter().all(|&(read, total)| read <= total));
+        assert_eq!(events.last(), Some(&(size as u64, size as u64)));
+    }
+    
+    #[test]
+    fn roundtrip_report_flags_filters_usvg_drops() {
+        // usvg can't load the texture, so the filter primitive is lost
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="64" height="64">
+            <filter id="texture"><feImage href="texture.png"/></filter>
+            <rect x="16" y="16" width="32" height="32" fill="#336699" filter="url(#texture)"/>
+        </svg>"##;
+        let report = svg_roundtrip_report(svg, &usvg::Options::default()).unwrap();
+        assert_eq!(report.dropped_node_types, vec!["feImage".to_string()]);
+        
+        let plain = r##"<svg xmlns="http://www.w3.org/2000/svg" width="64" height="64">
+            <filter id="blur"><feGaussianBlur stdDeviation="2"/></filter>
+            <rect x="16" y="16" width="32" height="32" fill="#336699" filter="url(#blur)"/>
+        </svg>"##;
+        let report = svg_roundtrip_report(plain, &usvg::Options::default()).unwrap();
+        assert!(report.dropped_node_types.is_empty(), "{:?}", report);
+        assert_eq!(report.differing_pixels, 0);
+    }
+    
+    #[test]
+    fn durable_writes_are_complete_once_they_return() {
+        let (_dir, root) = temp_dir();
+        let path = root.join("plan.jamal");
+        fs::write(&path, b"old content that is longer than the new one").unwrap();
+        
+        write_atomic_parts(&path, &[b"{\"version\":1,", b"\"name\":\"Plan\"}"], true).unwrap();
+        // A fresh handle sees the synced content, not a half-replaced file
+        let mut reopened = Vec::new();
+        std::io::Read::read_to_end(&mut fs::File::open(&path).unwrap(), &mut reopened).unwrap();
+        assert_eq!(reopened, b"{\"version\":1,\"name\":\"Plan\"}");
+        
+        let leftovers: Vec<_> = fs::read_dir(&root).unwrap().flatten().map(|e| e.file_name()).collect();
+        assert_eq!(leftovers, vec![std::ffi::OsString::from("plan.jamal")]);
+    }
+    
+    #[test]
+    fn social_exports_match_their_preset_dimensions() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="30"><rect width="40" height="30" fill="#336699"/></svg>"##;
+        let presets = [
+            (SocialPreset::InstagramSquare, (1080, 1080)),
+            (SocialPreset::InstagramPortrait, (1080, 1350)),
+            (SocialPreset::InstagramStory, (1080, 1920)),
+            (SocialPreset::Twitter, (1200, 675)),
+            (SocialPreset::Facebook, (1200, 630)),
+            (SocialPreset::Linkedin, (1200, 627)),
+            (SocialPreset::Pinterest, (1000, 1500)),
+            (SocialPreset::YoutubeThumbnail, (1280, 720)),
+        ];
+        for (preset, (width, height)) in presets {
+            let export = render_social(svg, &usvg::Options::default(), preset, "#ffffff").unwrap();
+            let png = tiny_skia::Pixmap::decode_png(&export.data).unwrap();
+            assert_eq!((export.width, export.height), (width, height), "{:?}", preset);
+            assert_eq!((png.width(), png.height()), (width, height), "{:?}", preset);
+        }
+    }
+    
+    #[test]
+    fn prune_defs_removes_unused_gradients_and_keeps_used_ones() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="10" height="10">
+            <defs>
+                <linearGradient id="used"><stop offset="0" stop-color="red"/></linearGradient>
+                <linearGradient id="unused"><stop offset="0" stop-color="blue"/></linearGradient>
+                <linearGradient id="base"><stop offset="0" stop-color="green"/></linearGradient>
+                <radialGradient id="derived" xlink:href="#base"/>
+            </defs>
+            <rect width="5" height="10" fill="url(#used)"/>
+            <rect x="5" width="5" height="10" style="fill: url('#derived')"/>
+        </svg>"##;
+        let pruned = prune_defs(svg).unwrap();
+        let doc = roxmltree::Document::parse(&pruned).unwrap();
+        let ids: Vec<&str> = doc.descendants().filter_map(|n| n.attribute("id")).collect();
+        assert_eq!(ids, vec!["used", "base", "derived"]);
+    }
+    
+    #[test]
+    fn scale_bar_changes_only_its_corner() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100"><rect width="200" height="100" fill="#3366cc"/></svg>"##;
+        let opt = usvg::Options::default();
+        let plain = render_tree(&usvg::Tree::from_str(svg, &opt).unwrap(), 200, 100).unwrap();
+        let with_bar = render_with_scale_bar(svg, &opt, 200, 100, 0.5, "mm").unwrap();
+        
+        // The bar sits 16px from the bottom-left corner; the top-right stays untouched
+        let pixel = |pixmap: &tiny_skia::Pixmap, x: u32, y: u32| pixmap.pixel(x, y).unwrap();
+        assert_ne!(pixel(&plain, 20, 84), pixel(&with_bar, 20, 84));
+        assert_eq!(pixel(&plain, 190, 10), pixel(&with_bar, 190, 10));
+    }
+    
+    fn canonical(value: &serde_json::Value) -> String {
+        let mut out = String::new();
+        write_canonical_json(value, &mut out);
+        out
+    }
+    
+    #[test]
+    fn canonical_json_sorts_keys_at_every_level() {
+        let a: serde_json::Value = serde_json::from_str(r#"{"b": 1, "a": {"y": [2.0, true], "x": null}}"#).unwrap();
+        let b: serde_json::Value = serde_json::from_str(r#"{"a": {"x": null, "y": [2, true]}, "b": 1.0}"#).unwrap();
+        assert_eq!(canonical(&a), r#"{"a":{"x":null,"y":[2,true]},"b":1}"#);
+        assert_eq!(canonical(&a), canonical(&b));
+        assert_eq!(store_checksum(&a), store_checksum(&b));
+    }
+    
+    #[test]
+    fn canonical_json_keeps_unicode_as_is() {
+        let value = serde_json::json!({ "名前": "Café ☕ 🎨", "quote": "a\"b\\c\n" });
+        assert_eq!(canonical(&value), "{\"quote\":\"a\\\"b\\\\c\\n\",\"名前\":\"Café ☕ 🎨\"}");
+        // Keys compare by code point, so the order doesn't depend on the platform
+        let keys = serde_json::json!({ "é": 1, "z": 2, "a": 3 });
+        assert_eq!(canonical(&keys), r#"{"a":3,"z":2,"é":1}"#);
+    }
+    
+    #[test]
+    fn stores_compare_equal_whatever_their_number_formatting() {
+        let a: serde_json::Value = serde_json::from_str(r#"{"x":1,"shapes":[{"w":2.0,"id":"a"}]}"#).unwrap();
+        let b: serde_json::Value = serde_json::from_str(r#"{"shapes":[{"id":"a","w":2}],"x":1.0}"#).unwrap();
+        let c: serde_json::Value = serde_json::from_str(r#"{"shapes":[{"id":"a","w":2.5}],"x":1}"#).unwrap();
+        assert_ne!(a, b);
+        assert!(stores_are_equal(&a, &b));
+        assert!(!stores_are_equal(&a, &c));
+    }
+    
+    #[test]
+    fn compression_is_detected_by_content_not_extension() {
+        let (_dir, root) = temp_dir();
+        let drawing = r#"{"version":1,"name":"Plan","store":{},"createdAt":0,"updatedAt":0}"#;
+        let gzipped = root.join("gzipped.jamal");
+        let plain = root.join("plain.jamalz");
+        fs::write(&gzipped, gzip(drawing.as_bytes()).unwrap()).unwrap();
+        fs::write(&plain, drawing).unwrap();
+        
+        assert_eq!(read_text_content(&gzipped).unwrap(), drawing);
+        assert_eq!(read_text_content(&plain).unwrap(), drawing);
+        assert_eq!(read_drawing(&gzipped).unwrap().name, "Plan");
+        assert_eq!(read_drawing(&plain).unwrap().name, "Plan");
+    }
+    
+    /// Write the drawing JSON `drawing` to `path` in `container`
+    fn write_test_drawing(path: &Path, drawing: &str, container: DrawingContainer) {
+        let drawing: DrawingFile = serde_json::from_str(drawing).unwrap();
+        fs::write(path, encode_drawing(&drawing, container).unwrap()).unwrap();
+    }
+    
+    #[test]
+    fn drawing_edits_keep_the_file_container() {
+        let (_dir, root) = temp_dir();
+        let drawing = r#"{"version":1,"name":"Plan","store":{"a":1},"createdAt":0,"updatedAt":0,"cloudId":"c1"}"#;
+        for container in [DrawingContainer::Json, DrawingContainer::Gzip, DrawingContainer::Cbor] {
+            let path = root.join(format!("{:?}.jamal", container));
+            write_test_drawing(&path, drawing, container);
+            edit_drawing_file(&path, |drawing| {
+                drawing.session_seconds += 90;
+                Ok(())
+            })
+            .unwrap();
+            
+            assert_eq!(drawing_container(&fs::read(&path).unwrap()), container);
+            let (edited, _) = read_drawing_in(&path).unwrap();
+            assert_eq!(edited.session_seconds, 90);
+            assert_eq!(edited.extra["cloudId"], "c1");
+            // A file without a checksum doesn't get one
+            assert!(edited.checksum.is_none());
+        }
+        
+        // One it has follows the store
+        let path = root.join("checked.jamal");
+        write_test_drawing(&path, r#"{"version":1,"name":"Plan","store":{"a":1},"createdAt":0,"updatedAt":0,"checksum":"stale"}"#, DrawingContainer::Json);
+        edit_drawing_file(&path, |drawing| {
+            drawing.store = serde_json::json!({ "b": 2 });
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(read_drawing(&path).unwrap().checksum, Some(store_checksum(&serde_json::json!({ "b": 2 }))));
+    }
+    
+    #[test]
+    fn drawing_names_are_read_without_parsing_past_them() {
+        let (_dir, root) = temp_dir();
+        for container in [DrawingContainer::Json, DrawingContainer::Gzip, DrawingContainer::Cbor] {
+            let path = root.join(format!("{:?}.jamal", container));
+            write_test_drawing(&path, r#"{"version":1,"name":"Plan","store":{"a":1},"createdAt":0,"updatedAt":0}"#, container);
+            assert_eq!(read_drawing_name(&path, fs::File::open(&path).unwrap()).unwrap(), "Plan", "{:?}", container);
+        }
+        
+        let name = |content: &[u8]| read_drawing_name(Path::new("plan.jamal"), content);
+        // Nothing after the name is looked at
+        assert_eq!(name(b"{\"version\":1,\"name\":\"Plan\",\"store\":{\"a\": tru").unwrap(), "Plan");
+        assert_eq!(name(b"\xEF\xBB\xBF{\"store\":{\"name\":\"inner\"},\"name\":\"Late\"}").unwrap(), "Late");
+        assert!(name(b"{\"version\":1}").unwrap_err().contains("name"));
+        assert!(name(b"{\"version\":").is_err());
+        assert!(name(&[ENCRYPTED_MAGIC, &[0; 64]].concat()).unwrap_err().starts_with(ERR_PASSWORD_REQUIRED));
+    }
+    
+    #[test]
+    fn assets_are_embedded_without_changing_the_container() {
+        use base64::Engine;
+        
+        let (_dir, root) = temp_dir();
+        let path = root.join("plan.jamal");
+        let image = root.join("logo.png");
+        fs::write(&image, [0x89, b'P', b'N', b'G', 0, 1, 2]).unwrap();
+        write_test_drawing(&path, r#"{"version":1,"name":"Plan","store":{},"createdAt":0,"updatedAt":0}"#, DrawingContainer::Cbor);
+        
+        edit_drawing_file(&path, |drawing| embed_asset(drawing, "logo".to_string(), &image)).unwrap();
+        
+        assert_eq!(drawing_container(&fs::read(&path).unwrap()), DrawingContainer::Cbor);
+        let (drawing, _) = read_drawing_in(&path).unwrap();
+        let embedded = base64::engine::general_purpose::STANDARD.decode(&drawing.assets["logo"]).unwrap();
+        assert_eq!(embedded, fs::read(&image).unwrap());
+        assert!(drawing.checksum.is_none());
+    }
+    
+    #[test]
+    fn large_drawings_are_renamed_in_their_own_container() {
+        let (_dir, root) = temp_dir();
+        let store = "x".repeat(IN_PLACE_EDIT_THRESHOLD as usize + 10);
+        let drawing = format!(r#"{{"version":1,"name":"Plan","store":{{"text":"{}"}},"createdAt":0,"updatedAt":0}}"#, store);
+        for container in [DrawingContainer::Json, DrawingContainer::Gzip, DrawingContainer::Cbor] {
+            let path = root.join(format!("{:?}.jamal", container));
+            write_test_drawing(&path, &drawing, container);
+            set_drawing_name(&path, "Floor \"plan\"").unwrap();
+            
+            assert_eq!(drawing_container(&fs::read(&path).unwrap()), container);
+            let (renamed, _) = read_drawing_in(&path).unwrap();
+            assert_eq!(renamed.name, "Floor \"plan\"");
+            assert!(renamed.updated_at > 0);
+            assert_eq!(renamed.store["text"], store.as_str());
+        }
+        
+        let locked = root.join("locked.jamal");
+        fs::write(&locked, [ENCRYPTED_MAGIC, &[0; 64]].concat()).unwrap();
+        assert!(set_drawing_name(&locked, "Plan 2").unwrap_err().starts_with(ERR_PASSWORD_REQUIRED));
+    }
+    
+    #[test]
+    fn snapshots_are_restored_in_the_drawing_container() {
+        let (_dir, root) = temp_dir();
+        let path = root.join("plan.jamalz");
+        write_test_drawing(&path, r#"{"version":1,"name":"Plan","store":{"now":1},"createdAt":0,"updatedAt":0}"#, DrawingContainer::Gzip);
+        let snapshot = gzip(br#"{"version":1,"name":"Plan v1","store":{"then":1},"createdAt":0,"updatedAt":0}"#).unwrap();
+        
+        let mut kept = None;
+        let name = restore_drawing(&path, &snapshot, None, |current| {
+            kept = Some(serde_json::from_slice::<DrawingFile>(current).unwrap());
+            Ok(())
+        })
+        .unwrap();
+        
+        assert_eq!(name, "Plan v1");
+        assert_eq!(kept.unwrap().store, serde_json::json!({ "now": 1 }));
+        assert_eq!(drawing_container(&fs::read(&path).unwrap()), DrawingContainer::Gzip);
+        let (restored, _) = read_drawing_in(&path).unwrap();
+        assert_eq!(restored.store, serde_json::json!({ "then": 1 }));
+        assert!(restored.updated_at > 0);
+    }
+    
+    #[test]
+    fn sprite_holds_one_symbol_per_source() {
+        let sources = [
+            ("star.svg".to_string(), r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 24 24"><path d="M12 2L15 9H22L16 14L18 22L12 17L6 22L8 14L2 9H9Z"/></svg>"#.to_string()),
+            ("dot.svg".to_string(), r#"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><circle cx="5" cy="5" r="4"/></svg>"#.to_string()),
+        ];
+        let sprite = build_sprite(&sources, &["icon-star".to_string(), "icon-dot".to_string()]).unwrap();
+        
+        let doc = roxmltree::Document::parse(&sprite).unwrap();
+        let symbols: Vec<(&str, &str)> = doc
+            .descendants()
+            .filter(|n| n.has_tag_name("symbol"))
+            .map(|n| (n.attribute("id").unwrap(), n.attribute("viewBox").unwrap()))
+            .collect();
+        assert_eq!(symbols, vec![("icon-star", "0 0 24 24"), ("icon-dot", "0 0 10 10")]);
+        
+        assert!(build_sprite(&sources, &["same".to_string(), "same".to_string()]).is_err());
+    }
+    
+    #[test]
+    fn export_memory_is_the_rgba_pixmap_plus_encoder_overhead() {
+        let pixmap = 1920 * 1080 * 4;
+        assert_eq!(estimate_export_memory(1920, 1080, "raw").unwrap(), pixmap);
+        assert_eq!(estimate_export_memory(1920, 1080, "png").unwrap(), pixmap + pixmap / 2);
+        assert_eq!(estimate_export_memory(1920, 1080, "JPEG").unwrap(), pixmap + pixmap / 4);
+        assert!(estimate_export_memory(1920, 1080, "tiff").is_err());
+        
+        assert!(check_export_memory(1920, 1080, "png").is_ok());
+        assert!(check_export_memory(20_000, 20_000, "png").is_err());
+    }
+    
+    #[test]
+    fn truncated_drawings_keep_the_shapes_before_the_cut() {
+        let (_dir, root) = temp_dir();
+        let path = root.join("plan.jamal");
+        let full = r#"{"version":1,"name":"Plan","store":{"shapes":[{"id":"a","x":1},{"id":"b","x":2},{"id":"c","x":3}]},"createdAt":5,"updatedAt":6}"#;
+        let cut = full.find(r#"{"id":"c""#).unwrap() + 4;
+        fs::write(&path, &full[..cut]).unwrap();
+        
+        let recovered = recover_drawing(&path).unwrap();
+        let ids: Vec<&str> = recovered.drawing.store["shapes"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|shape| shape["id"].as_str())
+            .collect();
+        assert_eq!(ids, vec!["a", "b"]);
+        assert_eq!(recovered.drawing.name, "Plan");
+        assert!(recovered.bytes_dropped > 0 && recovered.warning.is_some());
+        
+        fs::write(&path, full).unwrap();
+        assert_eq!(recover_drawing(&path).unwrap().bytes_dropped, 0);
+    }
+    
+    #[test]
+    fn os_thumbnails_are_square_at_the_requested_size() {
+        let (_dir, root) = temp_dir();
+        let svg = root.join("wide.svg");
+        fs::write(&svg, r##"<svg xmlns="http://www.w3.org/2000/svg" width="300" height="100"><rect width="300" height="100" fill="#336699"/></svg>"##).unwrap();
+        let drawing = root.join("plan.jamal");
+        let preview = r##"<svg xmlns="http://www.w3.org/2000/svg" width="50" height="80"><rect width="50" height="80"/></svg>"##;
+        fs::write(&drawing, serde_json::json!({ "version": 1, "name": "Plan", "store": {}, "createdAt": 0, "updatedAt": 0, "previewSvg": preview }).to_string()).unwrap();
+        
+        for path in [&svg, &drawing] {
+            let png = tiny_skia::Pixmap::decode_png(&os_thumbnail(path, &usvg::Options::default(), 128).unwrap()).unwrap();
+            assert_eq!((png.width(), png.height()), (128, 128), "{}", path.display());
+        }
+        assert!(os_thumbnail(&svg, &usvg::Options::default(), 0).is_err());
+    }
+    
+    #[test]
+    fn palettes_are_taken_from_the_saved_preview() {
+        let preview = r##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect width="10" height="10" fill="#ff0000"/></svg>"##;
+        let content = serde_json::json!({ "version": 1, "name": "Plan", "store": {}, "createdAt": 0, "updatedAt": 0, "previewSvg": preview }).to_string();
+        
+        let saved = with_palette(&content, &usvg::Options::default()).unwrap();
+        let drawing: DrawingFile = serde_json::from_str(&saved).unwrap();
+        assert_eq!(drawing.palette.as_deref().map(<[String]>::len), Some(1));
+        assert_eq!(drawing.extra.get("previewSvg").and_then(|p| p.as_str()), Some(preview));
+        
+        // Already current, or nothing to take it from
+        assert!(with_palette(&saved, &usvg::Options::default()).is_none());
+        assert!(with_palette("{\"version\":1}", &usvg::Options::default()).is_none());
+    }
+    
+    #[test]
+    fn reads_strip_a_bom_and_crlf_line_endings() {
+        let (_dir, root) = temp_dir();
+        let bom = root.join("bom.jamal");
+        let crlf = root.join("crlf.jamal");
+        fs::write(&bom, "\u{feff}{\"version\":1,\"name\":\"Plan\"}").unwrap();
+        fs::write(&crlf, "{\r\n  \"version\": 1,\r\n  \"name\": \"Plan\"\r\n}\r\n").unwrap();
+        
+        assert_eq!(read_text_content(&bom).unwrap(), "{\"version\":1,\"name\":\"Plan\"}");
+        assert_eq!(read_text_content(&crlf).unwrap(), "{\n  \"version\": 1,\n  \"name\": \"Plan\"\n}\n");
+    }
+    
+    #[test]
+    fn alpha_mask_holds_the_rendered_alpha() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="20" height="20"><rect x="5" y="5" width="10" height="10" fill="#ff0000" fill-opacity="0.4"/></svg>"##;
+        let opt = usvg::Options::default();
+        let mask = render_alpha_mask(svg, &opt, 20, 20).unwrap();
+        
+        let mut reader = png::Decoder::new(std::io::Cursor::new(mask)).read_info().unwrap();
+        let mut gray = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut gray).unwrap();
+        assert_eq!((info.width, info.height, info.color_type), (20, 20, png::ColorType::Grayscale));
+        
+        let rendered = render_tree(&usvg::Tree::from_str(svg, &opt).unwrap(), 20, 20).unwrap();
+        let expected = rendered.pixel(10, 10).unwrap().alpha();
+        assert_eq!(gray[10 * 20 + 10], expected);
+        assert!((100..=104).contains(&expected), "{}", expected);
+        assert_eq!(gray[0], 0);
+    }
+    
+    #[test]
+    fn drawings_embedded_in_png_exports_round_trip() {
+        let drawing: DrawingFile = serde_json::from_str(
+            r#"{"version":1,"name":"Plan","store":{"shapes":[{"id":"a","text":"Café"}]},"createdAt":1,"updatedAt":2}"#,
+        )
+        .unwrap();
+        let png = tiny_skia::Pixmap::new(8, 8).unwrap().encode_png().unwrap();
+        assert!(embedded_drawing(&png, "plain.png").is_err());
+        
+        let embedded = embed_drawing(&png, &drawing).unwrap();
+        // Still a valid image
+        assert_eq!(tiny_skia::Pixmap::decode_png(&embedded).unwrap().width(), 8);
+        let extracted = embedded_drawing(&embedded, "plan.png").unwrap();
+        assert_eq!(serde_json::to_value(&extracted).unwrap(), serde_json::to_value(&drawing).unwrap());
+    }
+    
+    #[test]
+    fn colors_slightly_off_the_palette_snap_to_it() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg"><rect fill="#fe0102" stroke="#0000ff"/><circle style="fill: rgb(3, 128, 2)"/></svg>"##;
+        let palette = vec!["#ff0000".to_string(), "#008000".to_string(), "#0000ff".to_string()];
+        let result = tauri::async_runtime::block_on(remap_svg_palette(svg.to_string(), palette)).unwrap();
+        
+        assert_eq!(
+            result.svg,
+            r##"<svg xmlns="http://www.w3.org/2000/svg"><rect fill="#ff0000" stroke="#0000ff"/><circle style="fill:#008000"/></svg>"##
+        );
+        assert_eq!(result.colors_changed, 2);
+    }
+    
+    #[test]
+    fn a4_at_300_dpi_is_2480_by_3508_pixels() {
+        assert_eq!(paper_pixels(PaperSize::A4, Orientation::Portrait, 300.0, 0.0).unwrap(), (2480, 3508, 0));
+        assert_eq!(paper_pixels(PaperSize::A4, Orientation::Landscape, 300.0, 10.0).unwrap(), (3508, 2480, 118));
+        assert_eq!(paper_pixels(PaperSize::Letter, Orientation::Portrait, 300.0, 0.0).unwrap(), (2550, 3300, 0));
+        assert!(paper_pixels(PaperSize::A4, Orientation::Portrait, 300.0, 105.0).is_err());
+        assert!(paper_pixels(PaperSize::A4, Orientation::Portrait, 0.0, 0.0).is_err());
+    }
+    
+    #[test]
+    fn text_measured_with_a_bundled_font_has_a_width() {
+        let mut db = usvg::fontdb::Database::new();
+        db.load_font_file(Path::new(env!("CARGO_MANIFEST_DIR")).join("../src/assets/fonts/DMSans-Regular.ttf")).unwrap();
+        
+        let metrics = text_metrics(&db, "Floor plan", "DM Sans", 16.0).unwrap();
+        assert!(!metrics.fallback);
+        assert!(metrics.width > 0.0 && metrics.ascent > 0.0 && metrics.line_height > 0.0, "{:?}", metrics);
+        assert_eq!(metrics.line_count, 1);
+        
+        let doubled = text_metrics(&db, "Floor plan", "DM Sans", 32.0).unwrap();
+        assert!((doubled.width - metrics.width * 2.0).abs() < 0.01);
+        assert!(text_metrics(&db, "Floor plan", "Not A Font", 16.0).unwrap().fallback);
+    }
+    
+    #[test]
+    fn outlined_text_renders_like_the_original_without_fonts() {
+        let mut db = usvg::fontdb::Database::new();
+        db.load_font_file(Path::new(env!("CARGO_MANIFEST_DIR")).join("../src/assets/fonts/DMSans-Regular.ttf")).unwrap();
+        let with_fonts = usvg::Options { fontdb: Arc::new(db), ..Default::default() };
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="60">
+            <text x="10" y="40" font-family="DM Sans" font-size="32" fill="#224488">Plan 42</text>
+        </svg>"##;
+        
+        let outlined = outline_text(svg, &with_fonts).unwrap();
+        assert!(outlined.warnings.is_empty(), "{:?}", outlined.warnings);
+        assert!(!outlined.svg.contains("<text"));
+        
+        let original = render_tree(&usvg::Tree::from_str(svg, &with_fonts).unwrap(), 200, 60).unwrap();
+        // No fonts needed any more
+        let paths = render_tree(&usvg::Tree::from_str(&outlined.svg, &usvg::Options::default()).unwrap(), 200, 60).unwrap();
+        let total_diff: u64 = original.data().iter().zip(paths.data()).map(|(a, b)| a.abs_diff(*b) as u64).sum();
+        assert!(original.data().iter().any(|&channel| channel > 0));
+        assert!(total_diff as f64 / (original.data().len() as f64 * 255.0) < 0.001, "{}", total_diff);
+    }
+    
+    #[test]
+    fn compatibility_reports_the_features_each_format_loses() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100">
+            <linearGradient id="sky"><stop offset="0" stop-color="#88ccff"/><stop offset="1" stop-color="#ffffff"/></linearGradient>
+            <filter id="blur"><feGaussianBlur stdDeviation="2"/></filter>
+            <rect id="background" width="100" height="50" fill="url(#sky)"/>
+            <rect id="faded" y="50" width="50" height="50" fill="#336699" opacity="0.5"/>
+            <rect id="blurred" x="50" y="50" width="50" height="50" fill="#993366" filter="url(#blur)"/>
+        </svg>"##;
+        let expected: [(&str, &[&str]); 7] = [
+            ("png", &[]),
+            ("JPEG", &["transparency"]),
+            ("gif", &["transparency", "gradients"]),
+            ("eps", &["transparency", "gradients", "filters"]),
+            ("svg", &["filters"]),
+            ("odg", &["filters"]),
+            ("webp", &[]),
+        ];
+        for (format, features) in expected {
+            let report = compatibility_report(svg, &usvg::Options::default(), format).unwrap();
+            let reported: Vec<&str> = report.warnings.iter().map(|w| w.feature.as_str()).collect();
+            assert_eq!(reported, features, "{}", format);
+        }
+        
+        // Ids of shapes usvg wraps in a group for their opacity or filter
+        let report = compatibility_report(svg, &usvg::Options::default(), "eps").unwrap();
+        let ids: Vec<&[String]> = report.warnings.iter().map(|w| w.element_ids.as_slice()).collect();
+        assert_eq!(ids, [&["faded".to_string()][..], &["background".to_string()], &["blurred".to_string()]]);
+        assert!(compatibility_report(svg, &usvg::Options::default(), "bmp").is_err());
+    }
+    
+    #[test]
+    fn batch_svg_jobs_convert_valid_files_and_report_invalid_ones() {
+        let (_dir, root) = temp_dir();
+        let (input, output) = (root.join("svgs"), root.join("pngs"));
+        fs::create_dir(&input).unwrap();
+        let square = r##"<svg xmlns="http://www.w3.org/2000/svg" width="10" height="10"><rect width="10" height="10" fill="#336699"/></svg>"##;
+        fs::write(input.join("a.svg"), square).unwrap();
+        fs::write(input.join("b.SVG"), square).unwrap();
+        fs::write(input.join("c.svg"), "<svg><rect").unwrap();
+        fs::write(input.join("notes.txt"), "not an svg").unwrap();
+        
+        let jobs = svg_to_png_jobs(&input.to_string_lossy(), &output.to_string_lossy()).unwrap();
+        let errors: Vec<bool> = jobs
+            .iter()
+            .map(|(svg, png)| {
+                render_svg_to_png_file(&fs::read_to_string(svg).unwrap(), &usvg::Options::default(), 0, 0, png).is_err()
+            })
+            .collect();
+        assert_eq!(errors, vec![false, false, true]);
+        assert!(output.join("a.png").is_file() && output.join("b.png").is_file());
+        assert!(!output.join("c.png").exists());
+    }
+    
+    #[test]
+    fn svgs_with_only_a_viewbox_take_its_size() {
+        let view_box_only = r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="10 20 300 150"/>"#;
+        assert_eq!(svg_intrinsic_size(view_box_only), Some((300.0, 150.0)));
+        assert_eq!(svg_intrinsic_size(r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0,0,64,32"/>"#), Some((64.0, 32.0)));
+        // A single dimension scales the viewBox
+        assert_eq!(svg_intrinsic_size(r#"<svg xmlns="http://www.w3.org/2000/svg" width="600" viewBox="0 0 300 150"/>"#), Some((600.0, 300.0)));
+        // Relative sizes fall back to the viewBox
+        assert_eq!(svg_intrinsic_size(r#"<svg xmlns="http://www.w3.org/2000/svg" width="100%" height="100%" viewBox="0 0 300 150"/>"#), Some((300.0, 150.0)));
+        assert_eq!(svg_intrinsic_size(r#"<svg xmlns="http://www.w3.org/2000/svg"/>"#), None);
+    }
+    
+    #[cfg(unix)]
+    #[test]
+    fn canonical_path_resolves_symlinks_on_unix() {
+        let (_dir, root) = temp_dir();
+        fs::create_dir(root.join("real")).unwrap();
+        fs::write(root.join("real/plan.jamal"), "{}").unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("link")).unwrap();
+        
+        let through_link = root.join("link/plan.jamal");
+        assert_eq!(canonical_path(&through_link.to_string_lossy()), Some(root.join("real/plan.jamal")));
+        // Files that don't exist yet resolve through their parent
+        let new_file = root.join("link/new.jamal");
+        assert_eq!(canonical_path(&new_file.to_string_lossy()), Some(root.join("real/new.jamal")));
+    }
+    
+    #[test]
+    fn canonical_path_resolves_relative_paths_from_the_working_directory() {
+        let cwd = fs::canonicalize(std::env::current_dir().unwrap()).unwrap();
+        assert_eq!(canonical_path("."), Some(cwd.clone()));
+        assert_eq!(canonical_path("Cargo.toml"), Some(cwd.join("Cargo.toml")));
+        assert_eq!(canonical_path("not-yet.jamal"), Some(cwd.join("not-yet.jamal")));
+        assert_eq!(canonical_path("missing-dir/plan.jamal"), None);
+    }
+    
+    #[test]
+    fn dzi_pyramid_has_every_level_and_tile() {
+        let (_dir, root) = temp_dir();
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="200" height="100"><rect width="200" height="100" fill="#336699"/></svg>"##;
+        let descriptor = write_dzi(svg, &usvg::Options::default(), 32, 100, &root).unwrap();
+        assert_eq!(descriptor, root.join("drawing.dzi"));
+        assert!(fs::read_to_string(&descriptor).unwrap().contains(r#"TileSize="32" Overlap="0" Format="png"><Size Width="100" Height="50"/>"#));
+        
+        // 100×50 needs levels 0 (1×1) to 7 (full size); the top one is 4×2 tiles of 32
+        let files = root.join("drawing_files");
+        let levels: Vec<String> = (0..=7).map(|level| level.to_string()).collect();
+        assert!(levels.iter().all(|level| files.join(level).is_dir()));
+        assert!(!files.join("8").exists());
+        for (column, row) in [(0, 0), (3, 0), (0, 1), (3, 1)] {
+            assert!(files.join("7").join(format!("{}_{}.png", column, row)).is_file());
+        }
+        assert!(!files.join("7/4_0.png").exists());
+        assert!(files.join("0/0_0.png").is_file());
+        
+        // Edge tiles are cut to the image
+        let corner = tiny_skia::Pixmap::load_png(files.join("7/3_1.png")).unwrap();
+        assert_eq!((corner.width(), corner.height()), (4, 18));
+    }
+    
+    #[test]
+    fn saves_of_malformed_json_are_refused() {
+        let (_dir, root) = temp_dir();
+        let path = root.join("plan.jamal");
+        fs::write(&path, "{\"version\":1}").unwrap();
+        let app = tauri::test::mock_app();
+        app.manage(WriteQueue::default());
+        let handle = app.handle().clone();
+        
+        for malformed in ["{\"version\":1,}", "{\n  \"x\": NaN\n}"] {
+            let error = tauri::async_runtime::block_on(save_file(
+                handle.clone(),
+                handle.state::<WriteQueue>(),
+                path.to_string_lossy().to_string(),
+                malformed.to_string(),
+                Some(SaveOptions { validate: true, ..Default::default() }),
+            ))
+            .unwrap_err();
+            assert!(error.starts_with("Invalid JSON") && error.contains("line"), "{}", error);
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{\"version\":1}");
+        assert!(check_json("{\"version\":1}").is_ok());
+    }
+    
+    #[test]
+    fn data_usage_sorts_files_into_categories() {
+        let (_dir, root) = temp_dir();
+        assert_eq!(data_usage(&root.join("missing")).unwrap().total, 0);
+        
+        fs::write(root.join("recent_files.json"), vec![b'r'; 10]).unwrap();
+        fs::write(root.join("preferences.json"), vec![b'p'; 20]).unwrap();
+        fs::write(root.join("tags.json"), vec![b't'; 5]).unwrap();
+        fs::create_dir_all(root.join("backups/drawing")).unwrap();
+        fs::write(root.join("backups/drawing/1.jamal"), vec![b'b'; 100]).unwrap();
+        fs::create_dir(root.join("templates")).unwrap();
+        fs::write(root.join("templates/x.jamal"), vec![b'x'; 40]).unwrap();
+        fs::write(root.join("templates/x.png"), vec![b'i'; 30]).unwrap();
+        fs::write(root.join(format!("{}.1", LOG_FILE_NAME)), vec![b'l'; 7]).unwrap();
+        fs::write(root.join("stray.tmp"), vec![b's'; 3]).unwrap();
+        
+        let usage = data_usage(&root).unwrap();
+        assert_eq!(usage.recent_files, 10);
+        assert_eq!(usage.settings, 25);
+        assert_eq!(usage.backups, 100);
+        assert_eq!(usage.templates, 40);
+        assert_eq!(usage.thumbnails, 30);
+        assert_eq!(usage.logs, 7);
+        assert_eq!(usage.other, 3);
+        assert_eq!((usage.snapshots, usage.previews), (0, 0));
+        assert_eq!(usage.total, 215);
+        assert!(usage.warnings.is_empty());
+    }
+    
+    #[test]
+    fn responsive_svg_drops_fixed_size_but_keeps_view_box() {
+        let svg = r#"<svg xmlns="http://www.w3.org/2000/svg" width="300px" height="150" viewBox="0 0 30 15"><rect width="10" height="5"/></svg>"#;
+        let output = tauri::async_runtime::block_on(export_responsive_svg(svg.to_string(), None)).unwrap();
+        let doc = roxmltree::Document::parse(&output).unwrap();
+        let root = doc.root_element();
+        assert_eq!(root.attribute("width"), Some("100%"));
+        assert_eq!(root.attribute("height"), Some("100%"));
+        assert_eq!(root.attribute("viewBox"), Some("0 0 30 15"));
+        assert_eq!(root.attribute("preserveAspectRatio"), Some("xMidYMid meet"));
+        assert!(!output.contains("300px") && !output.contains("\"150\""));
+        assert!(output.contains(r#"<rect width="10" height="5"/>"#));
+        
+        let sized = r#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="20"/>"#;
+        let output = tauri::async_runtime::block_on(export_responsive_svg(sized.to_string(), Some("none".to_string()))).unwrap();
+        let doc = roxmltree::Document::parse(&output).unwrap();
+        assert_eq!(doc.root_element().attribute("viewBox"), Some("0 0 40 20"));
+        assert_eq!(doc.root_element().attribute("preserveAspectRatio"), Some("none"));
+    }
 }
@@ -1,13 +1,8 @@
 import { invoke } from '@tauri-apps/api/core';
-import { open, save } from '@tauri-apps/plugin-dialog';
 import type { RecentFile, DrawingFile } from '../types';
 import type { TLEditorSnapshot } from 'tldraw';
 
 const FILE_EXTENSION = 'jamal';
-const FILE_FILTER = {
-  name: 'Jamal Drawing',
-  extensions: [FILE_EXTENSION],
-};
 
 // Generate a unique ID for new tabs
 export function generateId(): string {
@@ -29,22 +24,22 @@ export function createDrawingFile(name: string, store: TLEditorSnapshot, cloudId
 
 // Save content to a file via Tauri
 export async function saveFile(path: string, content: string): Promise<void> {
-  await invoke('save_file', { path, content });
+  await invoke('plugin:jamal-files|save_file', { path, content });
 }
 
 // Read content from a file via Tauri
 export async function readFile(path: string): Promise<string> {
-  return await invoke<string>('read_file', { path });
+  return await invoke<string>('plugin:jamal-files|read_file', { path });
 }
 
 // Check if file exists
 export async function fileExists(path: string): Promise<boolean> {
-  return await invoke<boolean>('file_exists', { path });
+  return await invoke<boolean>('plugin:jamal-files|file_exists', { path });
 }
 
 // Get recent files list
 export async function getRecentFiles(): Promise<RecentFile[]> {
-  const files = await invoke<Array<{ path: string; name: string; last_opened: number }>>('get_recent_files');
+  const files = await invoke<Array<{ path: string; name: string; last_opened: number }>>('plugin:jamal-files|get_recent_files');
   return files.map((f) => ({
     path: f.path,
     name: f.name,
@@ -54,41 +49,30 @@ export async function getRecentFiles(): Promise<RecentFile[]> {
 
 // Add file to recent files
 export async function addRecentFile(path: string, name: string): Promise<void> {
-  await invoke('add_recent_file', { path, name });
+  await invoke('plugin:jamal-files|add_recent_file', { path, name });
 }
 
 // Remove file from recent files
 export async function removeRecentFile(path: string): Promise<void> {
-  await invoke('remove_recent_file', { path });
+  await invoke('plugin:jamal-files|remove_recent_file', { path });
 }
 
 // Clear all recent files
 export async function clearRecentFiles(): Promise<void> {
-  await invoke('clear_recent_files');
+  await invoke('plugin:jamal-files|clear_recent_files');
 }
 
-// Open file dialog and return selected path
+// Open file dialog and return selected path; the backend grants access to it
 export async function openFileDialog(): Promise<string | null> {
-  const result = await open({
-    multiple: false,
-    filters: [FILE_FILTER],
-  });
-  
-  if (result && typeof result === 'string') {
-    return result;
-  }
-  
-  return null;
+  return await invoke<string | null>('pick_drawing_path', { save: false });
 }
 
-// Save file dialog and return selected path
+// Save file dialog and return selected path; the backend grants access to it
 export async function saveFileDialog(defaultName?: string): Promise<string | null> {
-  const result = await save({
-    defaultPath: defaultName ? `${defaultName}.${FILE_EXTENSION}` : undefined,
-    filters: [FILE_FILTER],
+  return await invoke<string | null>('pick_drawing_path', {
+    save: true,
+    defaultName: defaultName ? `${defaultName}.${FILE_EXTENSION}` : undefined,
   });
-  
-  return result;
 }
 
 // Save a drawing to file
@@ -132,27 +116,17 @@ export async function savePng(
   width: number,
   height: number
 ): Promise<void> {
-  await invoke('save_png', { path, svgData, width, height });
+  await invoke('save_png', { path, svgData, options: { width, height } });
 }
 
 export async function saveSvg(path: string, svgData: string): Promise<void> {
   await invoke('save_svg', { path, svgData });
 }
 
-// Export dialog
+// Export dialog; the backend grants access to the chosen path
 export async function exportDialog(
   type: 'png' | 'svg' | 'pdf'
 ): Promise<string | null> {
-  const filters = {
-    png: { name: 'PNG Image', extensions: ['png'] },
-    svg: { name: 'SVG Image', extensions: ['svg'] },
-    pdf: { name: 'PDF Document', extensions: ['pdf'] },
-  };
-  
-  const result = await save({
-    filters: [filters[type]],
-  });
-  
-  return result;
+  return await invoke<string | null>('pick_export_path', { format: type });
 }
 